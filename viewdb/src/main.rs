@@ -22,7 +22,7 @@ use viewdb_engine::pumpkindb_engine::{script, storage, timestamp, lmdb};
 use viewdb_engine::pumpkindb_engine::script::dispatcher;
 use viewdb_engine::pumpkindb_engine::nvmem::MmapedFile;
 
-use viewdb_engine::ViewDBDispatcher;
+use viewdb_engine::{ViewDBDispatcher, State, Store};
 
 use clap::{App, Arg};
 
@@ -30,7 +30,7 @@ use std::thread;
 
 use std::fs;
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, mpsc};
 
 use mio::channel as mio_chan;
 
@@ -78,6 +78,46 @@ lazy_static! {
  };
 }
 
+/// Committed keys in `ENVIRONMENT`, which the engine's state restores view
+/// and reactor definitions (`0x02 ++ ...`, see `VIEW`, and `0x05 ++ ...`,
+/// see `REACTOR`) and loads the change feed (`0x04 ++ txid ++ ...`, see
+/// `FEED`) from, and stores dead letters in
+struct Committed;
+
+impl Store for Committed {
+    fn scan(&self, prefix: u8, f: &mut dyn FnMut(&[u8], &[u8])) {
+        let db = lmdb::Database::open(&*ENVIRONMENT, None, &lmdb::DatabaseOptions::defaults())
+            .expect("can't open database");
+        let txn = lmdb::ReadTransaction::new(&*ENVIRONMENT).expect("can't start transaction");
+        let access = txn.access();
+        let mut cursor = txn.cursor(&db).expect("can't open cursor");
+        let mut entry = cursor.seek_range_k::<[u8], [u8]>(&access, &[prefix][..]);
+        while let Ok((key, value)) = entry {
+            if key.first() != Some(&prefix) {
+                break;
+            }
+            f(key, value);
+            entry = cursor.next::<[u8], [u8]>(&access);
+        }
+    }
+
+    fn put(&self, key: &[u8], value: &[u8]) {
+        let db = lmdb::Database::open(&*ENVIRONMENT, None, &lmdb::DatabaseOptions::defaults())
+            .expect("can't open database");
+        let txn = lmdb::WriteTransaction::new(&*ENVIRONMENT).expect("can't start transaction");
+        txn.access().put(&db, key, value, lmdb::put::Flags::empty()).expect("can't write");
+        txn.commit().expect("can't commit transaction");
+    }
+
+    fn delete(&self, key: &[u8]) {
+        let db = lmdb::Database::open(&*ENVIRONMENT, None, &lmdb::DatabaseOptions::defaults())
+            .expect("can't open database");
+        let txn = lmdb::WriteTransaction::new(&*ENVIRONMENT).expect("can't start transaction");
+        // it's fine if there's nothing to delete
+        let _ = txn.access().del_key(&db, key);
+        txn.commit().expect("can't commit transaction");
+    }
+}
 
 pub fn main() {
     let storage_path = CONFIG.get_str("storage.path").unwrap();
@@ -127,19 +167,64 @@ pub fn main() {
     let storage = Arc::new(storage::Storage::new(&ENVIRONMENT));
     let timestamp = Arc::new(timestamp::Timestamp::new(nvmem_hlc));
 
+    let mut state = State::new(Committed);
+    let (views, changes) = state.restore().expect("can't restore views and change feed");
+    info!("Restored {} view definitions and {} changes", views, changes);
+    let state = state.shared();
+
+    // live queries with a sliding window change as time passes
+    let ticker_state = state.clone();
+    let ticker_publisher = publisher_accessor.clone();
+    let _ = thread::spawn(move || loop {
+        thread::sleep(viewdb_engine::TICK);
+        let deltas = ticker_state.lock().unwrap().tick();
+        match deltas {
+            Ok(deltas) => viewdb_engine::publish(&ticker_publisher, deltas),
+            Err(error) => error!("Can't update live queries: {:?}", error),
+        }
+    });
+
     let cpus = num_cpus::get();
     info!("Starting {} schedulers", cpus);
     for i in 0..cpus {
         debug!("Starting scheduler on core {}.", i);
         let (mut scheduler, sender) =
             script::Scheduler::new(
-                ViewDBDispatcher::new(dispatcher::StandardDispatcher::new(storage.clone(),
+                ViewDBDispatcher::new(state.clone(), publisher_accessor.clone(),
+                                      dispatcher::StandardDispatcher::new(storage.clone(),
                                                     publisher_accessor.clone(), subscriber_accessor.clone(),
                                                     timestamp.clone())));
         thread::spawn(move || scheduler.run());
         senders.push(sender)
     }
 
+    // script reactors run in environments of their own, without holding
+    // the state, as they can commit facts themselves
+    let reactor_state = state.clone();
+    let reactor_scheduler = senders[0].clone();
+    let _ = thread::spawn(move || loop {
+        thread::sleep(viewdb_engine::TICK);
+        let scripts = {
+            let mut state = reactor_state.lock().unwrap();
+            state.react();
+            state.scripts()
+        };
+        for (invocation, program) in scripts {
+            let (callback, response) = mpsc::channel();
+            let program = viewdb_engine::reaction(&program, &invocation.fact);
+            let result = match reactor_scheduler.send(script::RequestMessage::ScheduleEnv(script::EnvId::new(),
+                                                                                          program, callback)) {
+                Ok(()) => match response.recv() {
+                    Ok(script::ResponseMessage::EnvTerminated(..)) => Ok(()),
+                    Ok(script::ResponseMessage::EnvFailed(_, error, ..)) => Err(format!("{:?}", error)),
+                    Err(error) => Err(error.to_string()),
+                },
+                Err(error) => Err(error.to_string()),
+            };
+            reactor_state.lock().unwrap().settle(invocation, result);
+        }
+    });
+
     server::run(CONFIG.get_int("server.port").unwrap(),
                 senders, relay_sender, relay_receiver);
 }
\ No newline at end of file
@@ -78,7 +78,19 @@ impl<T : AsRef<[u8]> + Clone> From<(T, Option<T>)> for TraitPattern<T> {
 }
 
 pub trait TraitResolver<T : AsRef<[u8]> + Clone> {
-    fn resolve(&self, name: T) -> &Trait<T>;
+    fn resolve(&self, name: T) -> Option<&Trait<T>>;
+}
+
+impl<T : AsRef<[u8]> + Clone, R : TraitResolver<T>> TraitResolver<T> for &R {
+    fn resolve(&self, name: T) -> Option<&Trait<T>> {
+        (**self).resolve(name)
+    }
+}
+
+/// Trait defined by a Rust type (usually through `#[derive(ViewDbTrait)]`)
+pub trait TraitDefinition {
+    const NAME: &'static str;
+    fn definition() -> Trait<&'static str>;
 }
 
 
@@ -0,0 +1,142 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Derives for domain types.
+//!
+//! `#[derive(ViewDbTrait)]` implements `viewdb_core::TraitDefinition`:
+//! every field is one of the trait's attributes, optionally with a
+//! constant value. The trait is named after the struct, unless renamed.
+//!
+//! ```ignore
+//! #[derive(ViewDbTrait)]
+//! #[viewdb(name = "NameChanged")]
+//! struct NameChange {
+//!     #[viewdb(attribute = "https://viewdb.org/attributes#factType", value = "NameChanged")]
+//!     fact_type: String,
+//!     #[viewdb(attribute = "https://viewdb.org/attributes#value")]
+//!     value: String,
+//! }
+//! ```
+//!
+//! `#[derive(FromRow)]` implements `viewdb_query::mapping::FromRow`: every
+//! field is read from the binding it is named after (or the one given
+//! with `#[viewdb(binding = "...")]`), through `FromValue`.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro2::TokenStream;
+use syn::{DeriveInput, Data, Fields, Attribute, LitStr};
+use syn::spanned::Spanned;
+
+/// Value of the `#[viewdb(key = "...")]` attributes
+fn setting(attrs: &[Attribute], key: &str) -> syn::Result<Option<LitStr>> {
+    let mut result = None;
+    for attr in attrs.iter().filter(|a| a.path().is_ident("viewdb")) {
+        attr.parse_nested_meta(|meta| {
+            let value: LitStr = meta.value()?.parse()?;
+            if meta.path.is_ident(key) {
+                result = Some(value);
+            }
+            Ok(())
+        })?;
+    }
+    Ok(result)
+}
+
+fn fields(input: &DeriveInput) -> syn::Result<&syn::FieldsNamed> {
+    match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => Ok(fields),
+            _ => Err(syn::Error::new(input.span(), "expected a struct with named fields")),
+        },
+        _ => Err(syn::Error::new(input.span(), "expected a struct")),
+    }
+}
+
+fn trait_definition(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = match setting(&input.attrs, "name")? {
+        Some(name) => name.value(),
+        None => input.ident.to_string(),
+    };
+    let mut patterns = vec![];
+    for field in fields(input)?.named.iter() {
+        let attribute = setting(&field.attrs, "attribute")?
+            .ok_or_else(|| syn::Error::new(field.span(), "expected #[viewdb(attribute = \"...\")]"))?;
+        let pattern = match setting(&field.attrs, "value")? {
+            Some(value) => quote!(::viewdb_core::TraitPattern(#attribute, Some(#value))),
+            None => quote!(::viewdb_core::TraitPattern(#attribute, None)),
+        };
+        patterns.push(pattern);
+    }
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::viewdb_core::TraitDefinition for #ident #type_generics #where_clause {
+            const NAME: &'static str = #name;
+
+            fn definition() -> ::viewdb_core::Trait<&'static str> {
+                vec![#(#patterns),*].into()
+            }
+        }
+    })
+}
+
+fn from_row(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let mut bindings = vec![];
+    let mut values = vec![];
+    for (i, field) in fields(input)?.named.iter().enumerate() {
+        let ident = field.ident.as_ref().unwrap();
+        let binding = match setting(&field.attrs, "binding")? {
+            Some(binding) => binding.value(),
+            None => ident.to_string(),
+        };
+        values.push(quote! {
+            #ident: ::viewdb_query::mapping::FromValue::from_value(values[#i])
+                    .ok_or(::viewdb_query::mapping::Error::Invalid(#binding))?
+        });
+        bindings.push(binding);
+    }
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::viewdb_query::mapping::FromRow for #ident #type_generics #where_clause {
+            fn bindings() -> &'static [&'static str] {
+                &[#(#bindings),*]
+            }
+
+            fn from_row(values: &[&[u8]]) -> Result<Self, ::viewdb_query::mapping::Error> {
+                Ok(#ident { #(#values),* })
+            }
+        }
+    })
+}
+
+fn expand<F>(input: proc_macro::TokenStream, f: F) -> proc_macro::TokenStream
+    where F : FnOnce(&DeriveInput) -> syn::Result<TokenStream> {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match f(&input) {
+        Ok(tokens) => tokens.into(),
+        // not `syn::Error::to_compile_error`, which doesn't resolve in 2015 edition crates
+        Err(error) => {
+            let message = error.to_string();
+            quote_spanned!(error.span() => compile_error!(#message);).into()
+        },
+    }
+}
+
+#[proc_macro_derive(ViewDbTrait, attributes(viewdb))]
+pub fn view_db_trait(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input, trait_definition)
+}
+
+#[proc_macro_derive(FromRow, attributes(viewdb))]
+pub fn from_row_derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input, from_row)
+}
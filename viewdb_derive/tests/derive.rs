@@ -0,0 +1,61 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+#[macro_use]
+extern crate viewdb_derive;
+extern crate viewdb_core;
+extern crate viewdb_query;
+
+use viewdb_core::{TraitDefinition, TraitPattern};
+use viewdb_query::mapping::{self, FromRow, Error};
+use viewdb_query::Query;
+use viewdb_query::Condition;
+
+#[derive(ViewDbTrait)]
+#[allow(dead_code)]
+struct NameChanged {
+    #[viewdb(attribute = "https://viewdb.org/attributes#factType", value = "NameChanged")]
+    fact_type: String,
+    #[viewdb(attribute = "https://viewdb.org/attributes#value")]
+    value: String,
+}
+
+#[derive(ViewDbTrait)]
+#[viewdb(name = "Object")]
+#[allow(dead_code)]
+struct Reference {
+    #[viewdb(attribute = "https://viewdb.org/attributes#object")]
+    object: Vec<u8>,
+}
+
+#[derive(FromRow, Debug, PartialEq)]
+struct Person {
+    #[viewdb(binding = "Name")]
+    name: String,
+    changes: u64,
+}
+
+#[test]
+fn view_db_trait() {
+    let patterns: Vec<(&str, Option<&str>)> = NameChanged::definition().iter()
+                                              .map(|&TraitPattern(a, v)| (a, v)).collect();
+    assert_eq!(NameChanged::NAME, "NameChanged");
+    assert_eq!(patterns, vec![("https://viewdb.org/attributes#factType", Some("NameChanged")),
+                              ("https://viewdb.org/attributes#value", None)]);
+    assert_eq!(Reference::NAME, "Object");
+    assert_eq!(Reference::definition().iter().count(), 1);
+}
+
+#[test]
+fn from_row() {
+    assert_eq!(Person::bindings(), &["Name", "changes"]);
+    let query = Query::new(Condition::True).select("changes").select("Name");
+    let rows = vec![vec![vec![0, 2], b"Alice".to_vec()]];
+    assert_eq!(mapping::rows::<Person, _>(&query, &rows),
+               Ok(vec![Person { name: "Alice".into(), changes: 2 }]));
+    let rows = vec![vec![vec![0, 2], vec![0xff]]];
+    assert_eq!(mapping::rows::<Person, _>(&query, &rows), Err(Error::Invalid("Name")));
+}
@@ -8,38 +8,166 @@
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
+extern crate log;
+#[macro_use]
 pub extern crate pumpkindb_engine;
 extern crate pumpkinscript;
+extern crate sha1;
+extern crate viewdb_query;
 
 mod mod_core;
+mod mod_explain;
+mod mod_feed;
+mod mod_live;
+mod mod_reactor;
+mod mod_trait;
+mod mod_view;
+mod state;
+
+pub use state::{State, Shared, Store, Processing};
 
 use pumpkindb_engine::script::{Env, EnvId, PassResult, Dispatcher, Error, TryInstruction};
+use pumpkindb_engine::messaging::Publisher;
+
+use viewdb_query::encoding::Encode;
+use viewdb_query::live::{SubscriptionId, Delta};
+
+use std::time::Duration;
+
+instruction!(WRITE, b"\x85WRITE");
+instruction!(COMMIT, b"\x86COMMIT");
+
+/// `$FEEDTOPIC`
+const FEED_TOPIC: &[u8] = b"viewdb/feed";
+/// `$LIVEPREFIX`, followed by the subscription
+const LIVE_PREFIX: &[u8] = b"viewdb/live/";
 
-pub struct ViewDBDispatcher<'a, D : Dispatcher<'a>> {
+/// How often live queries with a sliding window are brought up to date
+/// (see `State::tick`)
+pub const TICK: Duration = Duration::from_secs(1);
+
+/// Program of a script reactor's invocation: the fact's identifier, pushed
+/// onto the stack, followed by the reactor's program
+pub fn reaction(program: &[u8], fact: &[u8]) -> Vec<u8> {
+    let mut reaction = vec![];
+    match fact.len() {
+        len @ 0..=120 => reaction.push(len as u8),
+        len @ 121..=255 => reaction.extend_from_slice(&[121, len as u8]),
+        len @ 256..=65535 => {
+            reaction.push(122);
+            reaction.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            reaction.push(123);
+            reaction.extend_from_slice(&(len as u32).to_be_bytes());
+        },
+    }
+    reaction.extend_from_slice(fact);
+    reaction.extend_from_slice(program);
+    reaction
+}
+
+/// Publishes every delta on its subscription's topic
+pub fn publish<P : Publisher>(publisher: &P, deltas: Vec<(SubscriptionId, Delta)>) {
+    for (id, delta) in deltas {
+        let mut topic = LIVE_PREFIX.to_vec();
+        topic.extend_from_slice(&id.to_be_bytes());
+        publisher.publish(&topic, &delta.to_bytes());
+    }
+}
+
+pub struct ViewDBDispatcher<'a, P : Publisher, D : Dispatcher<'a>> {
     core: mod_core::Handler<'a>,
+    explain: mod_explain::Handler<'a>,
+    feed: mod_feed::Handler<'a>,
+    live: mod_live::Handler<'a>,
+    reactor: mod_reactor::Handler<'a>,
+    traits: mod_trait::Handler<'a>,
+    view: mod_view::Handler<'a>,
+    state: Shared,
+    publisher: P,
     fallback: D
 }
 
-impl<'a, D : Dispatcher<'a>> ViewDBDispatcher<'a, D> {
-    pub fn new(fallback: D) -> Self {
+impl<'a, P : Publisher, D : Dispatcher<'a>> ViewDBDispatcher<'a, P, D> {
+    pub fn new(state: Shared, publisher: P, fallback: D) -> Self {
         ViewDBDispatcher {
             core: mod_core::Handler::new(),
+            explain: mod_explain::Handler::new(state.clone()),
+            feed: mod_feed::Handler::new(),
+            live: mod_live::Handler::new(state.clone()),
+            reactor: mod_reactor::Handler::new(),
+            traits: mod_trait::Handler::new(),
+            view: mod_view::Handler::new(state.clone()),
+            state,
+            publisher,
             fallback,
         }
     }
+
+    /// Publishes the changes of the environment's transaction, which has
+    /// just been committed, defines the traits it defined, runs the native reactors they triggered and
+    /// maintains materialized views (see `State::committed`), materializes
+    /// the views it asked to, and publishes the deltas of the live queries
+    /// they changed
+    fn committed(&mut self, pid: EnvId) {
+        let traits = self.traits.committed(pid);
+        let views = self.view.committed(pid);
+        let rebuilt = self.view.rebuilt(pid);
+        let reactors = self.reactor.committed(pid);
+        let changes = self.feed.committed(pid);
+        for change in changes.iter() {
+            self.publisher.publish(FEED_TOPIC, &change.to_bytes());
+        }
+        let mut state = self.state.lock().unwrap();
+        // before the views of the transaction, which may refer to them
+        for definition in traits {
+            state.define_trait(definition);
+        }
+        match state.committed(views, reactors, &changes) {
+            Ok(deltas) => publish(&self.publisher, deltas),
+            Err(error) => error!("Can't update live queries: {:?}", error),
+        }
+        for name in rebuilt {
+            if let Err(error) = state.materialize(&name) {
+                error!("Can't materialize view {:?}: {:?}", String::from_utf8_lossy(&name), error);
+            }
+        }
+    }
 }
 
-impl<'a, D : Dispatcher<'a>> Dispatcher<'a> for ViewDBDispatcher<'a, D> {
+impl<'a, P : Publisher, D : Dispatcher<'a>> Dispatcher<'a> for ViewDBDispatcher<'a, P, D> {
     fn init(&mut self, env: &mut Env<'a>, pid: EnvId) {
         self.fallback.init(env, pid)
     }
     fn done(&mut self, env: &mut Env<'a>, pid: EnvId) {
+        self.feed.done(env, pid);
+        self.reactor.done(env, pid);
+        self.traits.done(env, pid);
+        self.view.done(env, pid);
         self.fallback.done(env, pid)
     }
     fn handle(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
-        self.core.handle(env, instruction, pid)
+        if instruction == WRITE {
+            // changes recorded by an earlier transaction that didn't commit
+            self.feed.discard(pid);
+            self.reactor.discard(pid);
+            self.traits.discard(pid);
+            self.view.discard(pid);
+        }
+        let result = self.core.handle(env, instruction, pid)
+            .if_unhandled_try(|| self.feed.handle(env, instruction, pid))
+            .if_unhandled_try(|| self.live.handle(env, instruction, pid))
+            .if_unhandled_try(|| self.reactor.handle(env, instruction, pid))
+            .if_unhandled_try(|| self.traits.handle(env, instruction, pid))
+            .if_unhandled_try(|| self.view.handle(env, instruction, pid))
+            .if_unhandled_try(|| self.explain.handle(env, instruction, pid))
             .if_unhandled_try(|| self.fallback.handle(env, instruction, pid))
-            .if_unhandled_try(|| Err(Error::UnknownInstruction))
+            .if_unhandled_try(|| Err(Error::UnknownInstruction));
+        if result.is_ok() && instruction == COMMIT {
+            self.committed(pid);
+        }
+        result
     }
 }
 
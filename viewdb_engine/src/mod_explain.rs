@@ -0,0 +1,69 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pumpkindb_engine::script::{Env, EnvId, Dispatcher, PassResult, Error, TryInstruction};
+
+use std::marker::PhantomData;
+
+use viewdb_query::Query;
+use viewdb_query::encoding::Decode;
+
+use state::Shared;
+
+// (query -- text)
+instruction!(EXPLAIN_QUERY, b"\x8dEXPLAIN/QUERY");
+// (query -- text)
+instruction!(EXPLAIN_ANALYZE, b"\x8fEXPLAIN/ANALYZE");
+
+pub struct Handler<'a> {
+    state: Shared,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> Dispatcher<'a> for Handler<'a> {
+    fn handle(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        self.handle_explain_query(env, instruction, pid)
+            .if_unhandled_try(|| self.handle_explain_analyze(env, instruction, pid))
+            .if_unhandled_try(|| Err(Error::UnknownInstruction))
+    }
+}
+
+impl<'a> Handler<'a> {
+    pub fn new(state: Shared) -> Self {
+        Handler {
+            state,
+            phantom: PhantomData,
+        }
+    }
+
+    fn explain(&mut self, env: &mut Env<'a>, analyze: bool) -> PassResult<'a> {
+        let query = stack_pop!(env);
+        let explanation = match Query::from_bytes(query) {
+            Ok(q) => self.state.lock().unwrap().explain(q, analyze),
+            Err(_) => return Err(error_invalid_value!(query)),
+        };
+        match explanation {
+            Ok(text) => {
+                let slice = alloc_and_write!(text.as_bytes(), env);
+                env.push(slice);
+                Ok(())
+            },
+            Err(_) => Err(error_invalid_value!(query)),
+        }
+    }
+
+    #[inline]
+    pub fn handle_explain_query(&mut self, env: &mut Env<'a>, instruction: &'a [u8], _: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, EXPLAIN_QUERY);
+        self.explain(env, false)
+    }
+
+    #[inline]
+    pub fn handle_explain_analyze(&mut self, env: &mut Env<'a>, instruction: &'a [u8], _: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, EXPLAIN_ANALYZE);
+        self.explain(env, true)
+    }
+}
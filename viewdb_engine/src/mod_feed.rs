@@ -0,0 +1,86 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pumpkindb_engine::script::{Env, EnvId, Dispatcher, PassResult, Error, TryInstruction};
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use viewdb_query::encoding::{Encode, Decode};
+use viewdb_query::feed::Change;
+
+// (fact attr value txid -- change)
+instruction!(FEED_CHANGE, b"\x8bFEED/CHANGE");
+// (change --)
+instruction!(ATTR_RECORD, b"\x8bATTR/RECORD");
+
+pub struct Handler<'a> {
+    /// Changes attached in every environment's write transaction
+    pending: HashMap<EnvId, Vec<Change>>,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> Dispatcher<'a> for Handler<'a> {
+    fn done(&mut self, _: &mut Env<'a>, pid: EnvId) {
+        self.discard(pid)
+    }
+
+    fn handle(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        self.handle_feed_change(env, instruction, pid)
+            .if_unhandled_try(|| self.handle_attr_record(env, instruction, pid))
+            .if_unhandled_try(|| Err(Error::UnknownInstruction))
+    }
+}
+
+impl<'a> Handler<'a> {
+    pub fn new() -> Self {
+        Handler {
+            pending: HashMap::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Changes of the environment's transaction, once it has been committed
+    pub fn committed(&mut self, pid: EnvId) -> Vec<Change> {
+        self.pending.remove(&pid).unwrap_or_default()
+    }
+
+    /// Forgets the changes of the environment's (uncommitted) transaction
+    pub fn discard(&mut self, pid: EnvId) {
+        self.pending.remove(&pid);
+    }
+
+    #[inline]
+    pub fn handle_feed_change(&mut self, env: &mut Env<'a>, instruction: &'a [u8], _: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, FEED_CHANGE);
+        let txid = stack_pop!(env);
+        let value = stack_pop!(env);
+        let attribute = stack_pop!(env);
+        let fact = stack_pop!(env);
+        let change = Change {
+            fact: fact.to_vec(),
+            attribute: attribute.to_vec(),
+            value: value.to_vec(),
+            txid: txid.to_vec(),
+        };
+        let slice = alloc_and_write!(change.to_bytes().as_slice(), env);
+        env.push(slice);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn handle_attr_record(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, ATTR_RECORD);
+        let change = stack_pop!(env);
+        match Change::from_bytes(change) {
+            Ok(change) => {
+                self.pending.entry(pid).or_default().push(change);
+                Ok(())
+            },
+            Err(_) => Err(error_invalid_value!(change)),
+        }
+    }
+}
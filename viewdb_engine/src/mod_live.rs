@@ -0,0 +1,77 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pumpkindb_engine::script::{Env, EnvId, Dispatcher, PassResult, Error, TryInstruction};
+
+use std::marker::PhantomData;
+
+use viewdb_query::Query;
+use viewdb_query::encoding::{Encode, Decode};
+
+use state::Shared;
+
+// (query -- subscription rows)
+instruction!(LIVE_SUBSCRIBE, b"\x8eLIVE/SUBSCRIBE");
+// (subscription --)
+instruction!(LIVE_UNSUBSCRIBE, b"\x90LIVE/UNSUBSCRIBE");
+
+pub struct Handler<'a> {
+    state: Shared,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> Dispatcher<'a> for Handler<'a> {
+    fn handle(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        self.handle_live_subscribe(env, instruction, pid)
+            .if_unhandled_try(|| self.handle_live_unsubscribe(env, instruction, pid))
+            .if_unhandled_try(|| Err(Error::UnknownInstruction))
+    }
+}
+
+impl<'a> Handler<'a> {
+    pub fn new(state: Shared) -> Self {
+        Handler {
+            state,
+            phantom: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn handle_live_subscribe(&mut self, env: &mut Env<'a>, instruction: &'a [u8], _: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, LIVE_SUBSCRIBE);
+        let query = stack_pop!(env);
+        let subscription = match Query::from_bytes(query) {
+            Ok(q) => self.state.lock().unwrap().subscribe(q),
+            Err(_) => return Err(error_invalid_value!(query)),
+        };
+        match subscription {
+            Ok((id, rows)) => {
+                let slice = alloc_and_write!(&id.to_be_bytes(), env);
+                env.push(slice);
+                let slice = alloc_and_write!(rows.to_bytes().as_slice(), env);
+                env.push(slice);
+                Ok(())
+            },
+            Err(_) => Err(error_invalid_value!(query)),
+        }
+    }
+
+    #[inline]
+    pub fn handle_live_unsubscribe(&mut self, env: &mut Env<'a>, instruction: &'a [u8], _: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, LIVE_UNSUBSCRIBE);
+        let id = stack_pop!(env);
+        let mut bytes = [0; 8];
+        if id.len() != bytes.len() {
+            return Err(error_invalid_value!(id));
+        }
+        bytes.copy_from_slice(id);
+        if self.state.lock().unwrap().unsubscribe(u64::from_be_bytes(bytes)) {
+            Ok(())
+        } else {
+            Err(error_invalid_value!(id))
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pumpkindb_engine::script::{Env, EnvId, Dispatcher, PassResult, Error, TryInstruction};
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use viewdb_query::encoding::Decode;
+use viewdb_query::reactor::Definition;
+
+// (name definition --)
+instruction!(REACTOR_RECORD, b"\x8eREACTOR/RECORD");
+
+pub struct Handler<'a> {
+    /// Reactors defined in every environment's write transaction
+    pending: HashMap<EnvId, Vec<Definition>>,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> Dispatcher<'a> for Handler<'a> {
+    fn done(&mut self, _: &mut Env<'a>, pid: EnvId) {
+        self.discard(pid)
+    }
+
+    fn handle(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        self.handle_reactor_record(env, instruction, pid)
+            .if_unhandled_try(|| Err(Error::UnknownInstruction))
+    }
+}
+
+impl<'a> Handler<'a> {
+    pub fn new() -> Self {
+        Handler {
+            pending: HashMap::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Reactors defined in the environment's transaction, once it has been
+    /// committed
+    pub fn committed(&mut self, pid: EnvId) -> Vec<Definition> {
+        self.pending.remove(&pid).unwrap_or_default()
+    }
+
+    /// Forgets the reactors defined in the environment's (uncommitted)
+    /// transaction
+    pub fn discard(&mut self, pid: EnvId) {
+        self.pending.remove(&pid);
+    }
+
+    #[inline]
+    pub fn handle_reactor_record(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, REACTOR_RECORD);
+        let definition = stack_pop!(env);
+        let name = stack_pop!(env);
+        match Definition::from_bytes(definition) {
+            Ok(mut reactor) => {
+                reactor.name = name.to_vec();
+                self.pending.entry(pid).or_default().push(reactor);
+                Ok(())
+            },
+            Err(_) => Err(error_invalid_value!(definition)),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pumpkindb_engine::script::{Env, EnvId, Dispatcher, PassResult, Error, TryInstruction};
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use viewdb_query::encoding::Decode;
+use viewdb_query::traits::Definition;
+
+// (name definition --)
+instruction!(TRAIT_RECORD, b"\x8cTRAIT/RECORD");
+
+pub struct Handler<'a> {
+    /// Traits defined in every environment's write transaction
+    pending: HashMap<EnvId, Vec<Definition>>,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> Dispatcher<'a> for Handler<'a> {
+    fn done(&mut self, _: &mut Env<'a>, pid: EnvId) {
+        self.discard(pid)
+    }
+
+    fn handle(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        self.handle_trait_record(env, instruction, pid)
+            .if_unhandled_try(|| Err(Error::UnknownInstruction))
+    }
+}
+
+impl<'a> Handler<'a> {
+    pub fn new() -> Self {
+        Handler {
+            pending: HashMap::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Traits defined in the environment's transaction, once it has been
+    /// committed
+    pub fn committed(&mut self, pid: EnvId) -> Vec<Definition> {
+        self.pending.remove(&pid).unwrap_or_default()
+    }
+
+    /// Forgets the traits defined in the environment's (uncommitted)
+    /// transaction
+    pub fn discard(&mut self, pid: EnvId) {
+        self.pending.remove(&pid);
+    }
+
+    #[inline]
+    pub fn handle_trait_record(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, TRAIT_RECORD);
+        let definition = stack_pop!(env);
+        let name = stack_pop!(env);
+        match Definition::from_bytes(definition) {
+            Ok(mut trait_) => {
+                trait_.name = name.to_vec();
+                self.pending.entry(pid).or_default().push(trait_);
+                Ok(())
+            },
+            Err(_) => Err(error_invalid_value!(definition)),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use pumpkindb_engine::script::{Env, EnvId, Dispatcher, PassResult, Error, TryInstruction};
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use viewdb_query::View;
+use viewdb_query::encoding::{Encode, Decode};
+
+use state::Shared;
+
+// (name definition txid --)
+instruction!(VIEW_RECORD, b"\x8bVIEW/RECORD");
+// (-- names)
+instruction!(VIEW_LIST, b"\x89VIEW/LIST");
+// (name -- definition)
+instruction!(VIEW_LATEST, b"\x8bVIEW/LATEST");
+// (name --)
+instruction!(MATVIEW_REBUILD, b"\x8fMATVIEW/REBUILD");
+
+pub struct Handler<'a> {
+    state: Shared,
+    /// Views defined in every environment's write transaction
+    pending: HashMap<EnvId, Vec<View<Vec<u8>>>>,
+    /// Views to materialize once every environment's write transaction
+    /// has been committed
+    rebuilds: HashMap<EnvId, Vec<Vec<u8>>>,
+    phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> Dispatcher<'a> for Handler<'a> {
+    fn done(&mut self, _: &mut Env<'a>, pid: EnvId) {
+        self.discard(pid)
+    }
+
+    fn handle(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        self.handle_view_record(env, instruction, pid)
+            .if_unhandled_try(|| self.handle_view_list(env, instruction, pid))
+            .if_unhandled_try(|| self.handle_view_latest(env, instruction, pid))
+            .if_unhandled_try(|| self.handle_matview_rebuild(env, instruction, pid))
+            .if_unhandled_try(|| Err(Error::UnknownInstruction))
+    }
+}
+
+impl<'a> Handler<'a> {
+    pub fn new(state: Shared) -> Self {
+        Handler {
+            state,
+            pending: HashMap::new(),
+            rebuilds: HashMap::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Views defined in the environment's transaction, once it has been
+    /// committed
+    pub fn committed(&mut self, pid: EnvId) -> Vec<View<Vec<u8>>> {
+        self.pending.remove(&pid).unwrap_or_default()
+    }
+
+    /// Views to materialize (see `State::materialize`), once the
+    /// environment's transaction has been committed
+    pub fn rebuilt(&mut self, pid: EnvId) -> Vec<Vec<u8>> {
+        self.rebuilds.remove(&pid).unwrap_or_default()
+    }
+
+    /// Forgets the views defined in the environment's (uncommitted)
+    /// transaction
+    pub fn discard(&mut self, pid: EnvId) {
+        self.pending.remove(&pid);
+        self.rebuilds.remove(&pid);
+    }
+
+    #[inline]
+    pub fn handle_view_record(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, VIEW_RECORD);
+        let txid = stack_pop!(env);
+        let definition = stack_pop!(env);
+        let name = stack_pop!(env);
+        match View::from_bytes(definition) {
+            Ok(mut view) => {
+                if view.query.validate().is_err() {
+                    return Err(error_invalid_value!(definition));
+                }
+                view.name = name.to_vec();
+                view.version = txid.to_vec();
+                self.pending.entry(pid).or_default().push(view);
+                Ok(())
+            },
+            Err(_) => Err(error_invalid_value!(definition)),
+        }
+    }
+
+    #[inline]
+    pub fn handle_view_list(&mut self, env: &mut Env<'a>, instruction: &'a [u8], _: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, VIEW_LIST);
+        let names = self.state.lock().unwrap().views();
+        let slice = alloc_and_write!(names.to_bytes().as_slice(), env);
+        env.push(slice);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn handle_view_latest(&mut self, env: &mut Env<'a>, instruction: &'a [u8], _: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, VIEW_LATEST);
+        let name = stack_pop!(env);
+        let view = self.state.lock().unwrap().view(name);
+        match view {
+            Some(view) => {
+                let slice = alloc_and_write!(view.to_bytes().as_slice(), env);
+                env.push(slice);
+                Ok(())
+            },
+            None => Err(error_invalid_value!(name)),
+        }
+    }
+
+    #[inline]
+    pub fn handle_matview_rebuild(&mut self, env: &mut Env<'a>, instruction: &'a [u8], pid: EnvId) -> PassResult<'a> {
+        instruction_is!(instruction, MATVIEW_REBUILD);
+        let name = stack_pop!(env);
+        let defined = self.pending.get(&pid).map_or(false, |views| views.iter().any(|v| v.name.as_slice() == name));
+        if !defined && self.state.lock().unwrap().view(name).is_none() {
+            return Err(error_invalid_value!(name));
+        }
+        self.rebuilds.entry(pid).or_default().push(name.to_vec());
+        Ok(())
+    }
+}
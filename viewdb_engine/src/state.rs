@@ -0,0 +1,758 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! State shared by all schedulers.
+//!
+//! Live queries are evaluated against a working set of the committed
+//! changes, which only holds the attributes subscribed queries read: the
+//! history of an attribute is loaded from storage once a query needs it
+//! (see `Store`), and dropped once no subscription does. The view
+//! definitions, the identifiers of all facts and the attribute statistics
+//! the planner uses are restored from storage on startup (see `restore`).
+//! All of them are brought up to date as transactions commit (see
+//! `committed`). Queries are resolved against the views and then compiled
+//! through a cache (see `compile`), with the traits they refer to expanded
+//! from their stored definitions; (re)defining a trait clears the cache
+//! (see `define_trait`).
+//!
+//! Reactors are restored and registered the same way, and the working set
+//! holds the attributes of their traits too. Native reactors run as soon as
+//! a transaction that triggers them commits; script reactors are taken (see
+//! `scripts`), run without holding the state, and then `settle`d. Dead
+//! letters are stored as they come about.
+//!
+//! Views the engine has been asked to materialize (see `materialize`) are
+//! maintained as transactions commit, and their changed groups stored,
+//! along with a marker that restores them.
+
+use std::sync::{Arc, Mutex};
+
+use sha1::Sha1;
+
+use viewdb_query::{Condition, Query, View, ViewResolver};
+use viewdb_query::view::Views;
+use viewdb_query::condition::analysis::Problem;
+use viewdb_query::encoding::{self, Encode, Decode};
+use viewdb_query::execution::{self, Executor, Row};
+use viewdb_query::execution::plan::Statistics;
+use viewdb_query::feed::Change;
+use viewdb_query::live::{Subscriptions, SubscriptionId, Delta};
+use viewdb_query::materialized::Materialized;
+use viewdb_query::reactor::{Reactors, Reactor, Definition, Invocation};
+use viewdb_query::traits::{self, Traits};
+use viewdb_query::working::WorkingSet;
+use viewdb_query::view;
+use viewdb_query::condition::cache::{self, Cache};
+use viewdb_query::condition::processing::{self, Processor, Pipeline, TraitsExpansion, PresentEqualCompaction,
+                                          ComparisonSuppression, BooleanLiteralSuppression, ImplicitFact};
+
+/// Processing passes of the queries the engine runs
+#[derive(Default)]
+pub struct Processing {
+    pub traits: Traits,
+}
+
+impl Processing {
+    pub fn new() -> Self {
+        Processing::default()
+    }
+
+    pub fn pipeline(&self) -> Pipeline<'_, Vec<u8>> {
+        Pipeline::new()
+            .with(TraitsExpansion::new(&self.traits))
+            .with(PresentEqualCompaction)
+            .with(ComparisonSuppression)
+            .with(BooleanLiteralSuppression)
+            .with(ImplicitFact)
+    }
+}
+
+impl Processor<Vec<u8>> for Processing {
+    fn process(&self, condition: Condition<Vec<u8>>) -> processing::Result<Vec<u8>> {
+        self.pipeline().process(condition)
+    }
+
+    fn name(&self) -> &str {
+        "Processing"
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Decoding(encoding::Error),
+    Invalid(Vec<Problem<Vec<u8>>>),
+    View(view::Error<Vec<u8>>),
+    Compilation(cache::Error<Vec<u8>>),
+    Execution(execution::Error<Vec<u8>>),
+    /// View that isn't defined
+    UnknownView(Vec<u8>),
+}
+
+impl From<encoding::Error> for Error {
+    fn from(error: encoding::Error) -> Self {
+        Error::Decoding(error)
+    }
+}
+
+impl From<view::Error<Vec<u8>>> for Error {
+    fn from(error: view::Error<Vec<u8>>) -> Self {
+        Error::View(error)
+    }
+}
+
+impl From<cache::Error<Vec<u8>>> for Error {
+    fn from(error: cache::Error<Vec<u8>>) -> Self {
+        Error::Compilation(error)
+    }
+}
+
+impl From<execution::Error<Vec<u8>>> for Error {
+    fn from(error: execution::Error<Vec<u8>>) -> Self {
+        Error::Execution(error)
+    }
+}
+
+/// `0x02 ++ sha1(name) ++ txid` -> definition (see `VIEW`)
+const VIEW_PREFIX: u8 = 0x02;
+/// `0x03 ++ sha1(name)` -> name, and `0x03 ++ sha1(name) ++ group` ->
+/// aggregated values (see `MATERIALIZE`)
+const MATVIEW_PREFIX: u8 = 0x03;
+/// `0x04 ++ txid ++ sha1(attr) ++ fact` -> change (see `FEED`)
+const FEED_PREFIX: u8 = 0x04;
+/// `0x05 ++ sha1(name) ++ txid` -> definition (see `REACTOR`)
+const REACTOR_PREFIX: u8 = 0x05;
+/// `0x06 ++ [reactor, fact]` -> last error (see `REACTOR`)
+const DEAD_LETTER_PREFIX: u8 = 0x06;
+/// `0x07 ++ sha1(name) ++ txid` -> definition (see `TRAIT`)
+const TRAIT_PREFIX: u8 = 0x07;
+
+/// Committed keys in storage
+pub trait Store : Send {
+    /// Calls `f` with every (key, value) pair under the prefix, in key order
+    fn scan(&self, prefix: u8, f: &mut dyn FnMut(&[u8], &[u8]));
+    /// Writes the pair in a transaction of its own
+    fn put(&self, key: &[u8], value: &[u8]);
+    /// Deletes the key in a transaction of its own
+    fn delete(&self, key: &[u8]);
+}
+
+/// Prefix of the groups of a materialized view
+fn matview_rows(name: &[u8]) -> Vec<u8> {
+    let mut sha1 = Sha1::new();
+    sha1.update(name);
+    let mut prefix = vec![MATVIEW_PREFIX];
+    prefix.extend_from_slice(&sha1.digest().bytes());
+    prefix
+}
+
+/// Stores the changed groups of a materialized view (see
+/// `Materialized::changes`)
+fn store_groups(store: &dyn Store, name: &[u8], changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) {
+    let prefix = matview_rows(name);
+    for (group, values) in changes {
+        let mut key = prefix.clone();
+        key.extend_from_slice(&group);
+        match values {
+            Some(values) => store.put(&key, &values),
+            None => store.delete(&key),
+        }
+    }
+}
+
+pub type Shared = Arc<Mutex<State>>;
+
+pub struct State {
+    store: Box<dyn Store>,
+    working: WorkingSet,
+    views: Views<Vec<u8>>,
+    statistics: Statistics,
+    cache: Cache<Vec<u8>, Processing>,
+    subscriptions: Subscriptions<Vec<u8>>,
+    reactors: Reactors<Vec<u8>>,
+    materialized: Vec<Materialized<Vec<u8>>>,
+}
+
+impl State {
+    pub fn new<S : Store + 'static>(store: S) -> Self {
+        State {
+            store: Box::new(store),
+            working: WorkingSet::new(),
+            views: Views::new(),
+            statistics: Statistics::new(),
+            cache: Cache::new(Processing::new()),
+            subscriptions: Subscriptions::new(),
+            reactors: Reactors::new(),
+            materialized: vec![],
+        }
+    }
+
+    pub fn shared(self) -> Shared {
+        Arc::new(Mutex::new(self))
+    }
+
+    /// Restores the view and reactor definitions, and the statistics and
+    /// facts of the change feed, from the store, returning the number of
+    /// views and changes
+    pub fn restore(&mut self) -> Result<(usize, usize), Error> {
+        let mut views = vec![];
+        self.store.scan(VIEW_PREFIX, &mut |key, definition| {
+            views.push(View::from_bytes(definition).map(|mut view| {
+                // 0x02 ++ sha1(name) ++ txid
+                view.version = key.get(21..).unwrap_or_default().to_vec();
+                view
+            }));
+        });
+        let restored = views.len();
+        for view in views {
+            self.define(view?)?;
+        }
+        let mut definitions = vec![];
+        // versions of a trait are scanned in TXID order, so the last one
+        // prevails
+        self.store.scan(TRAIT_PREFIX, &mut |_, definition| definitions.push(traits::Definition::from_bytes(definition)));
+        for definition in definitions {
+            self.define_trait(definition?);
+        }
+        let mut definitions = vec![];
+        self.store.scan(REACTOR_PREFIX, &mut |_, definition| definitions.push(Definition::from_bytes(definition)));
+        for definition in definitions {
+            // the feed is loaded next, with the reactors' attributes held
+            let reactor = Reactor::from(definition?);
+            for pattern in reactor.trait_.iter() {
+                self.working.hold(&pattern.0);
+            }
+            self.reactors.register(reactor);
+        }
+        let mut matviews = vec![];
+        self.store.scan(MATVIEW_PREFIX, &mut |key, value| matviews.push((key.to_vec(), value.to_vec())));
+        for (marker, name) in matviews.iter().filter(|(key, _)| key.len() == 21) {
+            let groups = matviews.iter().filter(|(key, _)| key.len() > 21 && key.starts_with(marker))
+                                 .map(|(key, group)| (&key[21..], group.as_slice()));
+            let view = self.resolve(name)?;
+            self.materialized.push(Materialized::load(view, groups)?);
+            for attribute in self.materialized.last().unwrap().view().query.attributes() {
+                self.working.hold(&attribute);
+            }
+        }
+        let changes = self.load(&[])?;
+        Ok((restored, changes))
+    }
+
+    /// Replays every change in the store (keeping the attachments of
+    /// `attributes`, once they're held), returning the number of changes
+    fn load(&mut self, attributes: &[Vec<u8>]) -> Result<usize, Error> {
+        let (working, statistics) = (&mut self.working, &mut self.statistics);
+        let mut count = 0;
+        let mut result = Ok(());
+        self.store.scan(FEED_PREFIX, &mut |_, change| {
+            match Change::from_bytes(change) {
+                Ok(ref change) if attributes.is_empty() => {
+                    statistics.record(change);
+                    working.replay(change);
+                },
+                Ok(ref change) if attributes.contains(&change.attribute) => working.replay(change),
+                Ok(_) => (),
+                Err(error) => result = Err(error),
+            }
+            count += 1;
+        });
+        result?;
+        Ok(count)
+    }
+
+    /// Holds the attributes in the working set, loading the history of
+    /// those it didn't hold yet
+    fn hold(&mut self, attributes: Vec<Vec<u8>>) -> Result<(), Error> {
+        let attributes: Vec<_> = attributes.into_iter().filter(|a| !self.working.holds(a)).collect();
+        if attributes.is_empty() {
+            return Ok(());
+        }
+        for attribute in attributes.iter() {
+            self.working.hold(attribute);
+        }
+        self.load(&attributes)?;
+        Ok(())
+    }
+
+    /// Drops the attributes neither subscriptions, reactors nor
+    /// materialized views read from the working set
+    fn release(&mut self) {
+        let mut needed = self.subscriptions.attributes();
+        needed.extend(self.reactors.attributes());
+        for view in self.materialized.iter() {
+            needed.extend(view.view().query.attributes());
+        }
+        for attribute in self.working.held() {
+            if !needed.contains(&attribute) {
+                self.working.release(&attribute);
+            }
+        }
+    }
+
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    /// Names of all defined views
+    pub fn views(&self) -> Vec<Vec<u8>> {
+        self.views.list().into_iter().cloned().collect()
+    }
+
+    /// Latest version of the view
+    pub fn view(&self, name: &[u8]) -> Option<View<Vec<u8>>> {
+        self.views.resolve(&name.to_vec())
+    }
+
+    /// Queries compiled so far
+    pub fn cache(&self) -> &Cache<Vec<u8>, Processing> {
+        &self.cache
+    }
+
+    /// Attachments of the attributes subscribed queries read
+    pub fn working(&self) -> &WorkingSet {
+        &self.working
+    }
+
+    fn define(&mut self, view: View<Vec<u8>>) -> Result<(), Error> {
+        self.views.insert(view).map_err(Error::Invalid)?;
+        // queries that refer to the view can't be compiled the same way
+        // again, so their entries would only take up space
+        self.cache.clear();
+        Ok(())
+    }
+
+    /// Defines the trait (replacing its previous definition) for the
+    /// queries compiled from now on; subscriptions and materialized views
+    /// keep the definition they were compiled with
+    pub fn define_trait(&mut self, definition: traits::Definition) {
+        self.cache.processor_mut().traits.define(definition);
+    }
+
+    /// Latest version of the view, with its query compiled
+    fn resolve(&mut self, name: &[u8]) -> Result<View<Vec<u8>>, Error> {
+        let mut view = self.view(name).ok_or_else(|| Error::UnknownView(name.to_vec()))?;
+        view.query = self.compile(view.query)?;
+        Ok(view)
+    }
+
+    /// Materializes the latest version of the view, or builds its
+    /// materialization again; from then on, it's maintained as
+    /// transactions commit
+    pub fn materialize(&mut self, name: &[u8]) -> Result<(), Error> {
+        let view = self.resolve(name)?;
+        self.hold(view.query.attributes())?;
+        let mut rebuilt = Materialized::new(view);
+        rebuilt.rebuild(&Executor::with_statistics(&self.working, &self.statistics))?;
+        let mut changes = rebuilt.changes();
+        match self.materialized.iter().position(|m| m.view().name.as_slice() == name) {
+            Some(i) => {
+                // groups of the previous materialization that are gone
+                for (group, _) in self.materialized.remove(i).entries() {
+                    if !changes.iter().any(|(g, _)| *g == group) {
+                        changes.push((group, None));
+                    }
+                }
+            },
+            None => self.store.put(&matview_rows(name), name),
+        }
+        store_groups(&*self.store, name, changes);
+        self.materialized.push(rebuilt);
+        Ok(())
+    }
+
+    /// Materialized views, by name
+    pub fn materialized(&self) -> Vec<&[u8]> {
+        self.materialized.iter().map(|m| m.view().name.as_slice()).collect()
+    }
+
+    /// Query with the views it refers to resolved, processed and validated
+    pub fn compile(&mut self, query: Query<Vec<u8>>) -> Result<Query<Vec<u8>>, Error> {
+        let query = view::resolve(query, &self.views)?;
+        Ok(self.cache.compile(query)?)
+    }
+
+    /// Text of the query's `EXPLAIN` (or, if `analyze`d, `EXPLAIN ANALYZE`)
+    pub fn explain(&mut self, query: Query<Vec<u8>>, analyze: bool) -> Result<String, Error> {
+        let query = view::resolve(query, &self.views)?;
+        let passes = self.cache.processor().pipeline().traced().run(query.condition.clone().normalize()).trace;
+        let query = self.cache.compile(query)?;
+        self.hold(query.attributes())?;
+        let explanation = {
+            let executor = Executor::with_statistics(&self.working, &self.statistics);
+            if analyze { executor.explain_analyze(&query) } else { executor.explain(&query) }
+        };
+        self.release();
+        Ok(explanation?.passes(passes).to_string())
+    }
+
+    /// Subscribes to the query, returning the subscription and the
+    /// initial result set
+    pub fn subscribe(&mut self, query: Query<Vec<u8>>) -> Result<(SubscriptionId, Vec<Row>), Error> {
+        let query = self.compile(query)?;
+        self.hold(query.attributes())?;
+        let subscription = {
+            let executor = Executor::with_statistics(&self.working, &self.statistics);
+            self.subscriptions.subscribe(&executor, b"live".to_vec(), query)
+        };
+        if subscription.is_err() {
+            self.release();
+        }
+        Ok(subscription?)
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let unsubscribed = self.subscriptions.unsubscribe(id);
+        self.release();
+        unsubscribed
+    }
+
+    /// Brings materialized views with a sliding window up to date, and
+    /// returns the deltas of the subscriptions with one whose results
+    /// changed as time passed; called every `TICK`
+    pub fn tick(&mut self) -> Result<Vec<(SubscriptionId, Delta)>, Error> {
+        let executor = Executor::with_statistics(&self.working, &self.statistics);
+        for materialized in self.materialized.iter_mut() {
+            materialized.tick(&executor)?;
+            let changes = materialized.changes();
+            store_groups(&*self.store, &materialized.view().name, changes);
+        }
+        Ok(self.subscriptions.tick(&executor)?)
+    }
+
+    /// Registers the reactor, replacing any reactor with the same name
+    pub fn register(&mut self, reactor: Reactor<Vec<u8>>) -> Result<(), Error> {
+        let attributes = reactor.trait_.iter().map(|p| p.0.clone()).collect();
+        self.reactors.register(reactor);
+        self.hold(attributes)
+    }
+
+    pub fn reactors(&self) -> &Reactors<Vec<u8>> {
+        &self.reactors
+    }
+
+    /// Records the view and reactor definitions and the changes of a
+    /// committed transaction, running the native reactors it triggered,
+    /// maintaining materialized views, and returning the delta of every
+    /// subscription the changes changed
+    pub fn committed(&mut self, views: Vec<View<Vec<u8>>>, reactors: Vec<Definition>, changes: &[Change])
+                     -> Result<Vec<(SubscriptionId, Delta)>, Error> {
+        let mut redefined = vec![];
+        for view in views {
+            if self.materialized.iter().any(|m| m.view().name == view.name) {
+                redefined.push(view.name.clone());
+            }
+            self.define(view)?;
+        }
+        for definition in reactors {
+            self.register(definition.into())?;
+        }
+        for change in changes {
+            self.statistics.record(change);
+            self.working.replay(change);
+        }
+        self.reactors.committed(&self.working, changes);
+        self.react();
+        for name in redefined {
+            self.materialize(&name)?;
+        }
+        let attachments: Vec<(&[u8], &[u8])> = changes.iter().map(|c| (c.fact.as_slice(), c.attribute.as_slice()))
+                                                      .collect();
+        let executor = Executor::with_statistics(&self.working, &self.statistics);
+        for materialized in self.materialized.iter_mut() {
+            materialized.attached(&executor, &attachments)?;
+            let changes = materialized.changes();
+            store_groups(&*self.store, &materialized.view().name, changes);
+        }
+        Ok(self.subscriptions.attached(&executor, &attachments)?)
+    }
+
+    /// Runs (or retries) the pending invocations of native reactors
+    pub fn react(&mut self) {
+        let buried = self.reactors.dead_letters().len();
+        self.reactors.run_native();
+        self.bury(buried);
+    }
+
+    /// Takes the pending invocations of script reactors, with their
+    /// programs, to be run without holding the state and then `settle`d
+    pub fn scripts(&mut self) -> Vec<(Invocation<Vec<u8>>, Vec<u8>)> {
+        self.reactors.take_scripts()
+    }
+
+    pub fn settle(&mut self, invocation: Invocation<Vec<u8>>, result: Result<(), String>) {
+        let buried = self.reactors.dead_letters().len();
+        self.reactors.settle(invocation, result);
+        self.bury(buried);
+    }
+
+    /// Stores the dead letters that came about since there were `buried`
+    fn bury(&mut self, buried: usize) {
+        for letter in self.reactors.dead_letters()[buried..].iter() {
+            let mut key = vec![DEAD_LETTER_PREFIX];
+            vec![letter.reactor.clone(), letter.fact.clone()].encode(&mut key);
+            self.store.put(&key, letter.error.as_ref().map(|e| e.as_bytes()).unwrap_or_default());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use viewdb_query::{Condition, Value, Query, View};
+    use viewdb_query::query::Aggregate;
+    use viewdb_query::encoding::Encode;
+    use viewdb_query::feed::Change;
+    use viewdb_query::live::Delta;
+    use viewdb_query::reactor::{Reactor, Action, Definition};
+    use viewdb_query::time::{self, Encoding, Window};
+    use viewdb_query::traits;
+
+    use state::{State, Store, matview_rows};
+
+    type Pairs = Vec<(Vec<u8>, Vec<u8>)>;
+
+    /// Sorted (key, value) pairs
+    #[derive(Clone)]
+    struct Stored(Arc<Mutex<Pairs>>);
+
+    impl Stored {
+        fn new(pairs: Pairs) -> Self {
+            Stored(Arc::new(Mutex::new(pairs)))
+        }
+    }
+
+    impl Store for Stored {
+        fn scan(&self, prefix: u8, f: &mut dyn FnMut(&[u8], &[u8])) {
+            for (key, value) in self.0.lock().unwrap().iter().filter(|(key, _)| key.first() == Some(&prefix)) {
+                f(key, value);
+            }
+        }
+
+        fn put(&self, key: &[u8], value: &[u8]) {
+            let mut pairs = self.0.lock().unwrap();
+            pairs.retain(|(k, _)| k.as_slice() != key);
+            pairs.push((key.to_vec(), value.to_vec()));
+            pairs.sort();
+        }
+
+        fn delete(&self, key: &[u8]) {
+            self.0.lock().unwrap().retain(|(k, _)| k.as_slice() != key);
+        }
+    }
+
+    fn change(fact: &[u8], attribute: &[u8], value: &[u8], txid: u8) -> Change {
+        Change { fact: fact.to_vec(), attribute: attribute.to_vec(), value: value.to_vec(), txid: vec![txid] }
+    }
+
+    fn stored(change: &Change) -> (Vec<u8>, Vec<u8>) {
+        let mut key = vec![0x04];
+        key.extend_from_slice(&change.txid);
+        key.extend_from_slice(&change.attribute);
+        key.extend_from_slice(&change.fact);
+        (key, change.to_bytes())
+    }
+
+    #[test]
+    pub fn live() {
+        let names = Query::new(Condition::fact(Condition::Equal(Value::Attribute(b"#name".to_vec()),
+                                                                Value::Binding(b"Name".to_vec()))))
+                    .select(b"Name".to_vec());
+        let view = View { name: b"names".to_vec(), version: vec![], query: names.clone() };
+        let mut key = vec![0x02; 21];
+        key.push(1);
+        let restored = [change(b"1", b"#name", b"Alice", 1), change(b"1", b"#email", b"alice@example.com", 1)];
+        let mut store = vec![(key, view.to_bytes())];
+        store.extend(restored.iter().map(stored));
+        let mut state = State::new(Stored::new(store));
+        assert_eq!(state.restore().unwrap(), (1, 2));
+        assert_eq!(state.views(), vec![b"names".to_vec()]);
+        assert_eq!(state.view(b"names").unwrap().version, vec![1]);
+        assert!(state.working().held().is_empty());
+
+        let query = Query::new(Condition::rule(b"names".to_vec(), vec![Value::Binding(b"N".to_vec())]))
+                    .select(b"N".to_vec());
+        let (id, rows) = state.subscribe(query.clone()).unwrap();
+        assert_eq!(rows, vec![vec![b"Alice".to_vec()]]);
+        assert_eq!(state.working().held(), vec![b"#name".to_vec()]);
+        state.compile(query.clone()).unwrap();
+        assert_eq!((state.cache().len(), state.cache().hits()), (1, 1));
+        let explanation = state.explain(names, true).unwrap();
+        assert!(explanation.contains("Fact scan (present <#name>, estimate=1)"), "{}", explanation);
+        assert!(explanation.contains("rows=1"), "{}", explanation);
+
+        // one delta for the whole transaction
+        let changes = [change(b"2", b"#name", b"Bob", 2), change(b"2", b"#age", b"42", 2),
+                       change(b"3", b"#name", b"Carol", 2)];
+        let deltas = state.committed(vec![], vec![], &changes).unwrap();
+        let delta = Delta { added: vec![vec![b"Bob".to_vec()], vec![b"Carol".to_vec()]], removed: vec![] };
+        assert_eq!(deltas, vec![(id, delta)]);
+        assert_eq!(state.statistics().estimate(Some(b"#name"), None), 3.0);
+        assert!(state.unsubscribe(id));
+        assert!(state.working().held().is_empty());
+        assert!(state.committed(vec![], vec![], &[change(b"4", b"#name", b"Dave", 3)]).unwrap().is_empty());
+        let view = View { name: b"ages".to_vec(), version: vec![4], query: Query::new(Condition::True) };
+        state.committed(vec![view], vec![], &[]).unwrap();
+        assert!(state.cache().is_empty());
+    }
+
+    #[test]
+    pub fn tick() {
+        let (now, old) = (time::now().to_string(), (time::now() - 7200).to_string());
+        let changes = [change(b"1", b"#seen", old.as_bytes(), 1), change(b"2", b"#seen", now.as_bytes(), 1)];
+        let mut state = State::new(Stored::new(changes.iter().map(stored).collect()));
+        // committed before the store is scanned
+        state.committed(vec![], vec![], &changes).unwrap();
+        let query = Query::new(Condition::fact(Condition::Equal(Value::Attribute(b"#seen".to_vec()),
+                                                                Value::Binding(b"Seen".to_vec()))))
+                    .select(b"Seen".to_vec())
+                    .window(Window::last(b"Seen".to_vec(), Encoding::Decimal, 3600));
+        let (_, rows) = state.subscribe(query).unwrap();
+        assert_eq!(rows, vec![vec![now.into_bytes()]]);
+        // nothing has been in the window for an hour yet
+        assert!(state.tick().unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn reactors() {
+        let store = Stored::new(vec![]);
+        let mut state = State::new(store.clone());
+        let profiles = Arc::new(Mutex::new(vec![]));
+        let p = profiles.clone();
+        let name_changed = vec![(b"#factType".to_vec(), Some(b"NameChanged".to_vec())), (b"#object".to_vec(), None)];
+        state.register(Reactor {
+            name: b"ProfileUpdated".to_vec(),
+            trait_: name_changed.iter().cloned().map(Into::into).collect::<Vec<_>>().into(),
+            action: Action::Native(Box::new(move |fact| {
+                p.lock().unwrap().push(fact.to_vec());
+                Ok(())
+            })),
+        }).unwrap();
+        let definition = Definition { name: b"Notify".to_vec(), trait_: name_changed, script: b"program".to_vec() };
+        let changes = [change(b"1", b"#factType", b"NameChanged", 1), change(b"1", b"#object", b"alice", 1)];
+        state.committed(vec![], vec![definition], &changes).unwrap();
+        assert_eq!(*profiles.lock().unwrap(), vec![b"1".to_vec()]);
+
+        // attaching to a fact that already has the trait doesn't trigger it
+        state.committed(vec![], vec![], &[change(b"1", b"#object", b"alice", 2)]).unwrap();
+        assert_eq!(profiles.lock().unwrap().len(), 1);
+
+        for attempt in 0..3 {
+            let mut scripts = state.scripts();
+            assert_eq!(scripts.len(), 1, "attempt {}", attempt);
+            let (invocation, program) = scripts.pop().unwrap();
+            assert_eq!((invocation.fact.as_slice(), program.as_slice()), (&b"1"[..], &b"program"[..]));
+            state.settle(invocation, Err("failed".to_string()));
+        }
+        assert!(state.scripts().is_empty());
+        assert_eq!(state.reactors().dead_letters().len(), 1);
+        let mut key = vec![0x06];
+        vec![b"Notify".to_vec(), b"1".to_vec()].encode(&mut key);
+        assert_eq!(*store.0.lock().unwrap(), vec![(key, b"failed".to_vec())]);
+    }
+
+    #[test]
+    pub fn materialized() {
+        let store = Stored::new(vec![]);
+        let mut state = State::new(store.clone());
+        let names = |condition| {
+            let query = Query::new(Condition::fact(Condition::Equal(Value::Attribute(b"#object".to_vec()),
+                                                                    Value::Binding(b"Person".to_vec()))
+                                                   .and(Condition::Equal(Value::Attribute(b"#value".to_vec()),
+                                                                         Value::Binding(b"Name".to_vec())))
+                                                   .and(condition)))
+                        .select(b"Person".to_vec()).select_aggregate(Aggregate::Count, b"Name".to_vec());
+            View { name: b"names".to_vec(), version: vec![], query }
+        };
+        // the changes are in the feed by the time they're committed
+        let commit = |state: &mut State, views, changes: &[Change]| {
+            {
+                let mut pairs = store.0.lock().unwrap();
+                pairs.extend(changes.iter().map(stored));
+                pairs.sort();
+            }
+            state.committed(views, vec![], changes).unwrap();
+        };
+        let matview = || -> Pairs {
+            store.0.lock().unwrap().iter().filter(|(key, _)| key[0] == 0x03).cloned().collect()
+        };
+        let group = |person: &[u8], names: Vec<Vec<u8>>| {
+            let mut key = matview_rows(b"names");
+            key.extend_from_slice(&vec![person.to_vec()].to_bytes());
+            (key, vec![names].to_bytes())
+        };
+        let marker = (matview_rows(b"names"), b"names".to_vec());
+
+        commit(&mut state, vec![names(Condition::True)],
+               &[change(b"1", b"#object", b"alice", 1), change(b"1", b"#value", b"Alice", 1)]);
+        assert!(state.materialize(b"unknown").is_err());
+        state.materialize(b"names").unwrap();
+        assert_eq!(matview(), vec![marker.clone(), group(b"alice", vec![b"Alice".to_vec()])]);
+
+        // attaching to a fact that is already part of a solution doesn't
+        // count it twice
+        commit(&mut state, vec![], &[change(b"2", b"#object", b"alice", 2), change(b"2", b"#value", b"Alicia", 2),
+                                     change(b"1", b"#value", b"Alice", 2)]);
+        let alice = group(b"alice", vec![b"Alice".to_vec(), b"Alicia".to_vec()]);
+        assert_eq!(matview(), vec![marker.clone(), alice.clone()]);
+
+        // restored from the store
+        {
+            let mut key = vec![0x02; 21];
+            key.push(1);
+            let mut pairs = store.0.lock().unwrap();
+            pairs.push((key, names(Condition::True).to_bytes()));
+            pairs.sort();
+        }
+        let mut state = State::new(store.clone());
+        state.restore().unwrap();
+        assert_eq!(state.materialized(), vec![&b"names"[..]]);
+        commit(&mut state, vec![], &[change(b"3", b"#object", b"bob", 3), change(b"3", b"#value", b"Bob", 3)]);
+        let bob = group(b"bob", vec![b"Bob".to_vec()]);
+        assert!(matview().contains(&alice));
+        assert!(matview().contains(&bob));
+
+        // redefining the view builds it again
+        let mut view = names(Condition::Equal(Value::Attribute(b"#value".to_vec()), Value::Data(b"Bob".to_vec())));
+        view.version = vec![3];
+        commit(&mut state, vec![view], &[]);
+        assert_eq!(matview(), vec![marker, bob]);
+    }
+
+    #[test]
+    pub fn traits() {
+        let name_changed = |patterns| traits::Definition { name: b"NameChanged".to_vec(), patterns };
+        let mut key = vec![0x07; 21];
+        key.push(1);
+        let definition = name_changed(vec![(b"#factType".to_vec(), Some(b"NameChanged".to_vec())),
+                                           (b"#object".to_vec(), None)]);
+        let changes = [change(b"1", b"#factType", b"NameChanged", 1), change(b"1", b"#object", b"alice", 1),
+                       change(b"2", b"#object", b"bob", 1)];
+        let mut store = vec![(key, definition.to_bytes())];
+        store.extend(changes.iter().map(stored));
+        let mut state = State::new(Stored::new(store));
+        state.restore().unwrap();
+
+        let objects = Query::new(Condition::trait_scope(b"NameChanged".to_vec(),
+                                                        Condition::Equal(Value::Attribute(b"#object".to_vec()),
+                                                                         Value::Binding(b"Object".to_vec()))))
+                      .select(b"Object".to_vec());
+        let (_, rows) = state.subscribe(objects.clone()).unwrap();
+        assert_eq!(rows, vec![vec![b"alice".to_vec()]]);
+        assert!(!state.cache().is_empty());
+
+        // queries compiled with the previous definition are dropped
+        state.define_trait(name_changed(vec![(b"#object".to_vec(), None)]));
+        assert!(state.cache().is_empty());
+        let (_, mut rows) = state.subscribe(objects).unwrap();
+        rows.sort();
+        assert_eq!(rows, vec![vec![b"alice".to_vec()], vec![b"bob".to_vec()]]);
+
+        let unknown = Query::new(Condition::trait_scope(b"Unknown".to_vec(), Condition::True));
+        assert!(state.compile(unknown).is_err());
+    }
+}
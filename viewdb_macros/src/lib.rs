@@ -0,0 +1,183 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! `condition!` and `query!`: conditions and queries written in the textual
+//! query syntax (see `viewdb_query::condition::syntax`), parsed at compile
+//! time.
+//!
+//! Procedural macros have to live in a crate of their own, so they are in
+//! `viewdb_macros` rather than `viewdb_query` (which doesn't re-export
+//! them); the expansion refers to `viewdb_query`, which has to be a
+//! dependency, too.
+//!
+//! ```
+//! #[macro_use]
+//! extern crate viewdb_macros;
+//! extern crate viewdb_query;
+//!
+//! use viewdb_query::{Condition, Query, Value};
+//!
+//! fn main() {
+//!     let query: Query<&'static str> = query!("SELECT ?Name, MAX(?Timestamp) WHERE
+//!                     Object(<https://viewdb.org/attributes#object> = $PersonId) AND
+//!                     NameChanged(<https://viewdb.org/attributes#value> = ?Name) AND
+//!                     Timestamp(<https://viewdb.org/attributes#timestamp> = ?Timestamp)");
+//!     assert_eq!(query.projection.len(), 2);
+//!
+//!     let name = condition!("NameChanged(<https://viewdb.org/attributes#value> = ?Name)");
+//!     assert_eq!(name, Condition::trait_scope("NameChanged",
+//!                                             Condition::Equal(Value::Attribute("https://viewdb.org/attributes#value"),
+//!                                                              Value::Binding("Name"))));
+//! }
+//! ```
+//!
+//! The result is a `Condition<&'static str>` (or `Query<&'static str>`),
+//! so every value has to be UTF-8. Malformed input fails the compilation,
+//! pointing at the literal.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+extern crate viewdb_query;
+
+use proc_macro2::TokenStream;
+use syn::LitStr;
+
+use viewdb_query::{Condition, Value, Query};
+use viewdb_query::condition::syntax::{self, Error};
+use viewdb_query::query::{Projection, Aggregate, Direction, Kind};
+
+fn text(bytes: &[u8]) -> Result<&str, String> {
+    ::std::str::from_utf8(bytes).map_err(|_| format!("{:?} is not UTF-8", bytes))
+}
+
+fn value_tokens(value: &Value<Vec<u8>>) -> Result<TokenStream, String> {
+    Ok(match *value {
+        Value::Data(ref v) => { let v = text(v)?; quote!(::viewdb_query::Value::Data(#v)) },
+        Value::Binding(ref v) => { let v = text(v)?; quote!(::viewdb_query::Value::Binding(#v)) },
+        Value::Attribute(ref v) => { let v = text(v)?; quote!(::viewdb_query::Value::Attribute(#v)) },
+        Value::AttributeTxid(ref v) => { let v = text(v)?; quote!(::viewdb_query::Value::AttributeTxid(#v)) },
+        Value::Parameter(ref v) => { let v = text(v)?; quote!(::viewdb_query::Value::Parameter(#v)) },
+    })
+}
+
+fn condition_tokens(condition: &Condition<Vec<u8>>) -> Result<TokenStream, String> {
+    Ok(match *condition {
+        Condition::Fact(ref c) => { let c = condition_tokens(c)?; quote!(::viewdb_query::Condition::fact(#c)) },
+        Condition::Not(ref c) => { let c = condition_tokens(c)?; quote!(::viewdb_query::Condition::not(#c)) },
+        Condition::And(ref c1, ref c2) => {
+            let (c1, c2) = (condition_tokens(c1)?, condition_tokens(c2)?);
+            quote!(#c1.and(#c2))
+        },
+        Condition::Or(ref c1, ref c2) => {
+            let (c1, c2) = (condition_tokens(c1)?, condition_tokens(c2)?);
+            quote!(#c1.or(#c2))
+        },
+        Condition::Trait(ref name, ref c) => {
+            let (name, c) = (text(name)?, condition_tokens(c)?);
+            quote!(::viewdb_query::Condition::trait_scope(#name, #c))
+        },
+        Condition::Rule(ref name, ref arguments) => {
+            let name = text(name)?;
+            let arguments = arguments.iter().map(value_tokens).collect::<Result<Vec<_>, _>>()?;
+            quote!(::viewdb_query::Condition::rule(#name, vec![#(#arguments),*]))
+        },
+        Condition::Present(ref v) => { let v = value_tokens(v)?; quote!(::viewdb_query::Condition::Present(#v)) },
+        Condition::Equal(ref v1, ref v2) => {
+            let (v1, v2) = (value_tokens(v1)?, value_tokens(v2)?);
+            quote!(::viewdb_query::Condition::Equal(#v1, #v2))
+        },
+        Condition::LessThan(ref v1, ref v2) => {
+            let (v1, v2) = (value_tokens(v1)?, value_tokens(v2)?);
+            quote!(::viewdb_query::Condition::LessThan(#v1, #v2))
+        },
+        Condition::GreaterThan(ref v1, ref v2) => {
+            let (v1, v2) = (value_tokens(v1)?, value_tokens(v2)?);
+            quote!(::viewdb_query::Condition::GreaterThan(#v1, #v2))
+        },
+        Condition::True => quote!(::viewdb_query::Condition::True),
+        Condition::False => quote!(::viewdb_query::Condition::False),
+    })
+}
+
+fn query_tokens(query: &Query<Vec<u8>>) -> Result<TokenStream, String> {
+    let mut tokens = {
+        let c = condition_tokens(&query.condition)?;
+        quote!(::viewdb_query::Query::<&'static str>::new(#c))
+    };
+    for projection in query.projection.iter() {
+        tokens = match *projection {
+            Projection::Binding(ref b) => { let b = text(b)?; quote!(#tokens.select(#b)) },
+            Projection::Aggregate(aggregate, ref b) => {
+                let b = text(b)?;
+                let aggregate = match aggregate {
+                    Aggregate::Count => quote!(Count),
+                    Aggregate::Min => quote!(Min),
+                    Aggregate::Max => quote!(Max),
+                };
+                quote!(#tokens.select_aggregate(::viewdb_query::query::Aggregate::#aggregate, #b))
+            },
+            Projection::Bucket(_, _, ref b) => return Err(format!("can't bucket {:?}", b)),
+        };
+    }
+    for key in query.order.iter() {
+        let b = text(&key.binding)?;
+        let direction = match key.direction {
+            Direction::Ascending => quote!(Ascending),
+            Direction::Descending => quote!(Descending),
+        };
+        if key.kind != Kind::Bytes {
+            return Err(format!("can't order by {:?}", key.kind));
+        }
+        tokens = quote!(#tokens.order_by(#b, ::viewdb_query::query::Direction::#direction,
+                                         ::viewdb_query::query::Kind::Bytes));
+    }
+    if let Some(limit) = query.limit {
+        tokens = quote!(#tokens.limit(#limit));
+    }
+    if query.offset > 0 {
+        let offset = query.offset;
+        tokens = quote!(#tokens.offset(#offset));
+    }
+    Ok(tokens)
+}
+
+fn expand<F>(input: proc_macro::TokenStream, f: F) -> proc_macro::TokenStream
+    where F : FnOnce(&str) -> Result<TokenStream, String> {
+    let literal = syn::parse_macro_input!(input as LitStr);
+    match f(&literal.value()) {
+        Ok(tokens) => tokens.into(),
+        // not `syn::Error::to_compile_error`, which doesn't resolve in 2015 edition crates
+        Err(message) => quote_spanned!(literal.span() => compile_error!(#message)).into(),
+    }
+}
+
+fn describe(input: &str, error: Error) -> String {
+    match error {
+        Error::UnexpectedEnd => "unexpected end of input".to_string(),
+        Error::Unexpected(position) => {
+            let rest: String = input.get(position..).unwrap_or("").chars().take(16).collect();
+            format!("unexpected input at offset {}: `{}`", position, rest)
+        },
+    }
+}
+
+/// `Condition<&'static str>` written in the query syntax
+#[proc_macro]
+pub fn condition(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input, |s| {
+        let c = syntax::parse(s).map_err(|e| describe(s, e)).and_then(|c| condition_tokens(&c))?;
+        Ok(quote!({ let condition: ::viewdb_query::Condition<&'static str> = #c; condition }))
+    })
+}
+
+/// `Query<&'static str>` written in the query syntax
+#[proc_macro]
+pub fn query(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(input, |s| syntax::parse_query(s).map_err(|e| describe(s, e)).and_then(|q| query_tokens(&q)))
+}
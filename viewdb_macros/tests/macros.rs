@@ -0,0 +1,39 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+extern crate viewdb_macros;
+extern crate viewdb_query;
+
+use viewdb_macros::{condition, query};
+use viewdb_query::{Condition, Value, Query};
+use viewdb_query::Condition::Equal;
+use viewdb_query::query::{Aggregate, Direction, Kind};
+
+#[test]
+fn condition() {
+    let cond = condition!("Object(<https://viewdb.org/attributes#object> = $PersonId) AND
+                           NOT FACT(<#factType> = \"AccountClosed\") OR TRUE");
+    let expected = Condition::trait_scope("Object", Equal(Value::Attribute("https://viewdb.org/attributes#object"),
+                                                          Value::Parameter("PersonId")))
+                   .and(Condition::not_exists(Equal(Value::Attribute("#factType"), Value::Data("AccountClosed"))))
+                   .or(Condition::True);
+    assert_eq!(cond, expected);
+    assert_eq!(condition!("FALSE"), Condition::False);
+}
+
+#[test]
+fn query() {
+    let query = query!("SELECT ?Name, MAX(?Timestamp) WHERE
+                        NameChanged(<#value> = ?Name) AND Timestamp(<#timestamp> = ?Timestamp)
+                        ORDER BY ?Name DESC LIMIT 1");
+    let expected = Query::new(Condition::trait_scope("NameChanged", Equal(Value::Attribute("#value"), Value::Binding("Name")))
+                              .and(Condition::trait_scope("Timestamp", Equal(Value::Attribute("#timestamp"),
+                                                                              Value::Binding("Timestamp")))))
+                   .select("Name").select_aggregate(Aggregate::Max, "Timestamp")
+                   .order_by("Name", Direction::Descending, Kind::Bytes)
+                   .limit(1);
+    assert_eq!(query, expected);
+}
@@ -0,0 +1,228 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Binding analysis (range restriction).
+//!
+//! A binding is *bound* by a condition if every solution of that condition
+//! assigns it a value: it is equated to an attribute, an attribute's TXID,
+//! data or another bound binding, or it is an argument of a rule. Bindings
+//! that are projected or compared have to be bound positively, otherwise
+//! the query can't be evaluated. A binding can't be tested with `Present`,
+//! which only takes an attribute.
+
+use super::{Condition, Value};
+use super::visit::{self, Visitor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem<T : AsRef<[u8]> + Clone> {
+    /// Binding is used but never bound
+    Unbound(T),
+    /// Binding is bound in one branch of an `Or` but not in the other
+    PartiallyBound(T),
+    /// Binding is only bound under a `Not`
+    OnlyNegated(T),
+    /// Binding used where only an attribute can be (`Present`)
+    InvalidBinding(T),
+}
+
+impl<T : AsRef<[u8]> + Clone> Problem<T> {
+    pub fn binding(&self) -> &T {
+        match *self {
+            Problem::Unbound(ref b) | Problem::PartiallyBound(ref b) | Problem::OnlyNegated(ref b) |
+            Problem::InvalidBinding(ref b) => b,
+        }
+    }
+}
+
+pub(crate) fn contains<T : AsRef<[u8]>>(set: &[T], binding: &T) -> bool {
+    set.iter().any(|b| b.as_ref() == binding.as_ref())
+}
+
+fn insert<T : AsRef<[u8]>>(set: &mut Vec<T>, binding: T) -> bool {
+    if contains(set, &binding) {
+        false
+    } else {
+        set.push(binding);
+        true
+    }
+}
+
+/// Bindings bound by every solution of the condition
+pub fn bound<T : AsRef<[u8]> + Clone>(condition: &Condition<T>) -> Vec<T> {
+    match *condition {
+        // only bound through propagation within a conjunction
+        Condition::Equal(Value::Binding(_), Value::Binding(_)) => vec![],
+        Condition::Equal(Value::Binding(ref b), _) | Condition::Equal(_, Value::Binding(ref b)) =>
+            vec![b.clone()],
+        Condition::And(_, _) => {
+            let parts = condition.conjuncts();
+            let mut result = vec![];
+            for part in parts.iter() {
+                for b in bound(part) {
+                    insert(&mut result, b);
+                }
+            }
+            // propagate `?A = ?B` equalities
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for part in parts.iter() {
+                    if let Condition::Equal(Value::Binding(ref b1), Value::Binding(ref b2)) = **part {
+                        if contains(&result, b1) {
+                            changed |= insert(&mut result, b2.clone());
+                        } else if contains(&result, b2) {
+                            changed |= insert(&mut result, b1.clone());
+                        }
+                    }
+                }
+            }
+            result
+        },
+        Condition::Or(ref c1, ref c2) => {
+            let b2 = bound(c2);
+            bound(c1).into_iter().filter(|b| contains(&b2, b)).collect()
+        },
+        Condition::Fact(ref c) | Condition::Trait(_, ref c) => bound(c),
+        Condition::Rule(_, ref arguments) => arguments.iter().filter_map(|v| match *v {
+            Value::Binding(ref b) => Some(b.clone()),
+            _ => None,
+        }).collect(),
+        _ => vec![],
+    }
+}
+
+/// Bindings that are bound somewhere, just not safely
+struct Unsafe<T : AsRef<[u8]> + Clone> {
+    partially: Vec<T>,
+    negated: Vec<T>,
+}
+
+impl<T : AsRef<[u8]> + Clone> Visitor<T> for Unsafe<T> {
+    fn visit_condition(&mut self, condition: &Condition<T>) {
+        match *condition {
+            Condition::Or(ref c1, ref c2) => {
+                let (b1, b2) = (bound(c1), bound(c2));
+                for b in b1.iter().filter(|b| !contains(&b2, b)).chain(b2.iter().filter(|b| !contains(&b1, b))) {
+                    insert(&mut self.partially, b.clone());
+                }
+            },
+            Condition::Not(ref c) => {
+                for b in bound(c) {
+                    insert(&mut self.negated, b);
+                }
+            },
+            _ => (),
+        }
+        visit::visit_condition(self, condition)
+    }
+}
+
+struct Check<'a, T : AsRef<[u8]> + Clone + 'a> {
+    unsafe_: &'a Unsafe<T>,
+    problems: Vec<Problem<T>>,
+}
+
+impl<'a, T : AsRef<[u8]> + Clone> Check<'a, T> {
+
+    fn require(&mut self, context: &[T], binding: &T) {
+        if contains(context, binding) ||
+           self.problems.iter().any(|p| p.binding().as_ref() == binding.as_ref()) {
+            return;
+        }
+        let problem = if contains(&self.unsafe_.partially, binding) {
+            Problem::PartiallyBound(binding.clone())
+        } else if contains(&self.unsafe_.negated, binding) {
+            Problem::OnlyNegated(binding.clone())
+        } else {
+            Problem::Unbound(binding.clone())
+        };
+        self.problems.push(problem);
+    }
+
+    fn check(&mut self, condition: &Condition<T>, context: &[T]) {
+        let mut context = context.to_vec();
+        for b in bound(condition) {
+            insert(&mut context, b);
+        }
+        match *condition {
+            Condition::And(_, _) => {
+                let parts = condition.conjuncts();
+                for part in parts {
+                    self.check(part, &context);
+                }
+            },
+            Condition::Or(ref c1, ref c2) => {
+                self.check(c1, &context);
+                self.check(c2, &context);
+            },
+            Condition::Not(ref c) | Condition::Fact(ref c) | Condition::Trait(_, ref c) =>
+                self.check(c, &context),
+            Condition::Equal(ref v1, ref v2) |
+            Condition::LessThan(ref v1, ref v2) |
+            Condition::GreaterThan(ref v1, ref v2) => {
+                for v in [v1, v2].iter() {
+                    if let Value::Binding(ref b) = **v {
+                        self.require(&context, b);
+                    }
+                }
+            },
+            Condition::Present(Value::Binding(ref b))
+                if !self.problems.iter().any(|p| matches!(*p, Problem::InvalidBinding(ref i) if i.as_ref() == b.as_ref())) =>
+                self.problems.push(Problem::InvalidBinding(b.clone())),
+            _ => (),
+        }
+    }
+}
+
+/// Reports bindings in `projection` or in comparisons that are not
+/// safely bound by the condition
+pub fn check<T : AsRef<[u8]> + Clone>(condition: &Condition<T>, projection: &[T]) -> Vec<Problem<T>> {
+    let mut unsafe_ = Unsafe { partially: vec![], negated: vec![] };
+    unsafe_.visit_condition(condition);
+    let mut check = Check { unsafe_: &unsafe_, problems: vec![] };
+    let top = bound(condition);
+    for b in projection {
+        check.require(&top, b);
+    }
+    check.check(condition, &[]);
+    check.problems
+}
+
+#[cfg(test)]
+mod tests {
+
+    use Condition::*;
+    use Value;
+    use condition::analysis::{bound, check, Problem};
+
+    #[test]
+    pub fn bound_bindings() {
+        let cond = Equal(Value::Attribute("a"), Value::Binding("A"))
+                   .and(Equal(Value::Binding("B"), Value::Binding("A")))
+                   .and(Equal(Value::Attribute("c"), Value::Binding("C"))
+                        .or(Equal(Value::Attribute("d"), Value::Binding("C"))
+                            .and(Equal(Value::Attribute("e"), Value::Binding("E")))));
+        assert_eq!(bound(&cond), vec!["A", "C", "B"]);
+    }
+
+    #[test]
+    pub fn problems() {
+        let cond = ::Condition::fact(Equal(Value::Attribute("a"), Value::Binding("A"))
+                   .or(Present(Value::Attribute("b")))
+                   .and(!Equal(Value::Attribute("c"), Value::Binding("C")))
+                   .and(LessThan(Value::Binding("D"), Value::Data("1"))));
+        assert_eq!(check(&cond, &["A", "C"]),
+                   vec![Problem::PartiallyBound("A"), Problem::OnlyNegated("C"), Problem::Unbound("D")]);
+        // bindings local to a negation are fine as long as they don't escape it
+        let cond = ::Condition::fact(Equal(Value::Attribute("a"), Value::Binding("A"))
+                   .and(!(Equal(Value::Attribute("c"), Value::Binding("C"))
+                          .and(LessThan(Value::Binding("C"), Value::Binding("A"))))));
+        assert!(check(&cond, &["A"]).is_empty());
+        let cond = ::Condition::fact(Equal(Value::Attribute("a"), Value::Binding("A"))
+                   .and(Present(Value::Binding("A"))));
+        assert_eq!(check(&cond, &["A"]), vec![Problem::InvalidBinding("A")]);
+    }
+}
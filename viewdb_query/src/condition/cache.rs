@@ -0,0 +1,142 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Compiled queries, keyed by their normalized condition.
+//!
+//! Repeated queries (say, a view queried over and over again) skip trait
+//! expansion, the other processing passes and binding analysis: the cache
+//! owns the pipeline its queries are processed with, and keeps them
+//! processed and validated, ready for the executor (which then only picks
+//! index lookups and join order, as they depend on the values bound). A
+//! compiled query is only valid as long as the trait definitions it was
+//! expanded with, so the cache has to be cleared whenever a trait is
+//! (re)defined.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use super::Condition;
+use super::analysis::Problem;
+use super::processing::{self, Processor};
+use query::Query;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error<T : AsRef<[u8]> + Clone> {
+    Processing(processing::Error<T>),
+    /// Processed query failed binding analysis
+    Invalid(Vec<Problem<T>>),
+}
+
+impl<T : AsRef<[u8]> + Clone> From<processing::Error<T>> for Error<T> {
+    fn from(error: processing::Error<T>) -> Self {
+        Error::Processing(error)
+    }
+}
+
+/// Queries (with their condition normalized) and their compiled form
+type Entries<T> = Vec<(Query<T>, Query<T>)>;
+
+pub struct Cache<T : AsRef<[u8]> + Clone + Eq + Hash, P : Processor<T>> {
+    processor: P,
+    /// By normalized condition
+    entries: HashMap<Condition<T>, Entries<T>>,
+    hits: usize,
+    misses: usize,
+}
+
+impl<T : AsRef<[u8]> + Clone + Eq + Hash, P : Processor<T>> Cache<T, P> {
+    pub fn new(processor: P) -> Self {
+        Cache { processor, entries: HashMap::new(), hits: 0, misses: 0 }
+    }
+
+    /// Processed and validated query, compiling it on a miss; failures
+    /// aren't cached
+    pub fn compile(&mut self, mut query: Query<T>) -> Result<Query<T>, Error<T>> {
+        query.condition = query.condition.normalize();
+        if let Some((_, compiled)) = self.entries.get(&query.condition)
+                                              .and_then(|entries| entries.iter().find(|&(q, _)| *q == query)) {
+            self.hits += 1;
+            return Ok(compiled.clone());
+        }
+        self.misses += 1;
+        let compiled = query.clone().process(&self.processor)?;
+        compiled.validate().map_err(Error::Invalid)?;
+        self.entries.entry(query.condition.clone()).or_default().push((query, compiled.clone()));
+        Ok(compiled)
+    }
+
+    pub fn processor(&self) -> &P {
+        &self.processor
+    }
+
+    /// Processor, to be changed (say, with a trait redefined); clears the
+    /// cache, as its queries were compiled with the processor as it was
+    pub fn processor_mut(&mut self) -> &mut P {
+        self.clear();
+        &mut self.processor
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Number of compiled queries
+    pub fn len(&self) -> usize {
+        self.entries.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashSet;
+
+    use {Condition, Value, Query};
+    use Condition::*;
+    use condition::analysis::Problem;
+    use condition::cache::{Cache, Error};
+    use condition::processing::{Pipeline, ImplicitFact, BooleanLiteralSuppression};
+
+    #[test]
+    pub fn cache() {
+        let a = Equal(Value::Attribute("#object"), Value::Binding("Person"));
+        let b = Present(Value::Attribute("#value"));
+        let c = Condition::True.or(Condition::False);
+        let one = a.clone().and(b.clone()).and(c.clone());
+        let other = c.and(a.and(b));
+        assert_ne!(one, other);
+        assert_eq!(one.clone().normalize(), other.clone().normalize());
+        let set: HashSet<Condition<&str>> = vec![one.clone().normalize(), other.clone().normalize()].into_iter().collect();
+        assert_eq!(set.len(), 1);
+
+        let pipeline = Pipeline::new().with(BooleanLiteralSuppression).with(ImplicitFact);
+        let mut cache = Cache::new(pipeline);
+        let compiled = cache.compile(Query::new(one).select("Person")).unwrap();
+        assert_eq!(compiled.condition, Condition::fact(Present(Value::Attribute("#value"))
+                                                       .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))));
+        assert_eq!(cache.compile(Query::new(other.clone()).select("Person")), Ok(compiled));
+        assert_eq!((cache.len(), cache.hits(), cache.misses()), (1, 1, 1));
+        // same condition, another query
+        assert_eq!(cache.compile(Query::new(other.clone()).select("Name")),
+                   Err(Error::Invalid(vec![Problem::Unbound("Name")])));
+        assert!(cache.compile(Query::new(other).select("Person").limit(1)).is_ok());
+        assert_eq!((cache.len(), cache.hits(), cache.misses()), (2, 1, 3));
+        cache.processor_mut();
+        assert!(cache.is_empty());
+    }
+}
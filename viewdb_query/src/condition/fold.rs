@@ -0,0 +1,140 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Rewriting traversal of a `Condition` tree.
+//!
+//! Like `visit::Visitor`, every method defaults to rebuilding the node from
+//! its folded children (through the free functions of this module). Values
+//! fold into a `Value`, so a fold can turn, say, a binding into data.
+
+use super::{Condition, Value};
+
+pub trait Fold<T : AsRef<[u8]> + Clone> {
+    fn fold_condition(&mut self, condition: Condition<T>) -> Condition<T> {
+        fold_condition(self, condition)
+    }
+
+    /// Called with the body of every `Condition::Fact`; everything folded
+    /// until it returns belongs to that fact scope.
+    fn fold_fact(&mut self, body: Condition<T>) -> Condition<T> {
+        fold_fact(self, body)
+    }
+
+    fn fold_trait(&mut self, name: T, body: Condition<T>) -> Condition<T> {
+        fold_trait(self, name, body)
+    }
+
+    fn fold_rule(&mut self, name: T, arguments: Vec<Value<T>>) -> Condition<T> {
+        fold_rule(self, name, arguments)
+    }
+
+    fn fold_value(&mut self, value: Value<T>) -> Value<T> {
+        fold_value(self, value)
+    }
+
+    fn fold_data(&mut self, data: T) -> Value<T> {
+        Value::Data(data)
+    }
+
+    fn fold_binding(&mut self, binding: T) -> Value<T> {
+        Value::Binding(binding)
+    }
+
+    fn fold_attribute(&mut self, attribute: T) -> Value<T> {
+        Value::Attribute(attribute)
+    }
+
+    fn fold_attribute_txid(&mut self, attribute: T) -> Value<T> {
+        Value::AttributeTxid(attribute)
+    }
+
+    fn fold_parameter(&mut self, parameter: T) -> Value<T> {
+        Value::Parameter(parameter)
+    }
+}
+
+pub fn fold_condition<T, F>(folder: &mut F, condition: Condition<T>) -> Condition<T>
+    where T : AsRef<[u8]> + Clone, F : Fold<T> + ?Sized {
+    match condition {
+        Condition::Fact(c) => folder.fold_fact(*c),
+        Condition::Not(c) => !folder.fold_condition(*c),
+        Condition::And(c1, c2) => {
+            let c1 = folder.fold_condition(*c1);
+            c1.and(folder.fold_condition(*c2))
+        },
+        Condition::Or(c1, c2) => {
+            let c1 = folder.fold_condition(*c1);
+            c1.or(folder.fold_condition(*c2))
+        },
+        Condition::Trait(name, c) => folder.fold_trait(name, *c),
+        Condition::Rule(name, arguments) => folder.fold_rule(name, arguments),
+        Condition::Present(v) => Condition::Present(folder.fold_value(v)),
+        Condition::Equal(v1, v2) => {
+            let v1 = folder.fold_value(v1);
+            Condition::Equal(v1, folder.fold_value(v2))
+        },
+        Condition::LessThan(v1, v2) => {
+            let v1 = folder.fold_value(v1);
+            Condition::LessThan(v1, folder.fold_value(v2))
+        },
+        Condition::GreaterThan(v1, v2) => {
+            let v1 = folder.fold_value(v1);
+            Condition::GreaterThan(v1, folder.fold_value(v2))
+        },
+        c @ Condition::True | c @ Condition::False => c,
+    }
+}
+
+pub fn fold_fact<T, F>(folder: &mut F, body: Condition<T>) -> Condition<T>
+    where T : AsRef<[u8]> + Clone, F : Fold<T> + ?Sized {
+    Condition::fact(folder.fold_condition(body))
+}
+
+pub fn fold_trait<T, F>(folder: &mut F, name: T, body: Condition<T>) -> Condition<T>
+    where T : AsRef<[u8]> + Clone, F : Fold<T> + ?Sized {
+    Condition::trait_scope(name, folder.fold_condition(body))
+}
+
+pub fn fold_rule<T, F>(folder: &mut F, name: T, arguments: Vec<Value<T>>) -> Condition<T>
+    where T : AsRef<[u8]> + Clone, F : Fold<T> + ?Sized {
+    Condition::Rule(name, arguments.into_iter().map(|v| folder.fold_value(v)).collect())
+}
+
+pub fn fold_value<T, F>(folder: &mut F, value: Value<T>) -> Value<T>
+    where T : AsRef<[u8]> + Clone, F : Fold<T> + ?Sized {
+    match value {
+        Value::Data(v) => folder.fold_data(v),
+        Value::Binding(v) => folder.fold_binding(v),
+        Value::Attribute(v) => folder.fold_attribute(v),
+        Value::AttributeTxid(v) => folder.fold_attribute_txid(v),
+        Value::Parameter(v) => folder.fold_parameter(v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use Condition::*;
+    use Value;
+    use condition::fold::Fold;
+
+    struct Substitute(&'static str, &'static str);
+
+    impl Fold<&'static str> for Substitute {
+        fn fold_binding(&mut self, binding: &'static str) -> Value<&'static str> {
+            if binding == self.0 { Value::Data(self.1) } else { Value::Binding(binding) }
+        }
+    }
+
+    #[test]
+    pub fn substitution() {
+        let cond = ::Condition::fact(Equal(Value::Attribute("a"), Value::Binding("A"))
+                                     .and(!LessThan(Value::Binding("B"), Value::Binding("A"))));
+        let expected = ::Condition::fact(Equal(Value::Attribute("a"), Value::Data("1"))
+                                         .and(!LessThan(Value::Binding("B"), Value::Data("1"))));
+        assert_eq!(Substitute("A", "1").fold_condition(cond), expected);
+    }
+}
@@ -4,15 +4,17 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd)]
 pub enum Value<T : AsRef<[u8]> + Clone> {
     Data(T),
     Binding(T),
     Attribute(T),
     AttributeTxid(T),
+    /// Placeholder supplied at execution time (see `prepared`)
+    Parameter(T),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Condition<T : AsRef<[u8]> + Clone> {
     // Fact scoping
     Fact(Box<Condition<T>>),
@@ -22,6 +24,8 @@ pub enum Condition<T : AsRef<[u8]> + Clone> {
     Or(Box<Condition<T>>, Box<Condition<T>>),
     // Trait scoping
     Trait(T, Box<Condition<T>>),
+    // Rule invocation
+    Rule(T, Vec<Value<T>>),
     // Conditions
     Present(Value<T>),
     Equal(Value<T>, Value<T>),
@@ -31,6 +35,28 @@ pub enum Condition<T : AsRef<[u8]> + Clone> {
 }
 
 pub mod processing;
+pub mod visit;
+pub mod fold;
+pub mod analysis;
+pub mod syntax;
+pub mod cache;
+
+use encoding::Encode;
+
+/// Operands of a chain of the same operator (`And` or `Or`), left to right
+fn operands<T : AsRef<[u8]> + Clone>(condition: Condition<T>, and: bool, result: &mut Vec<Condition<T>>) {
+    match condition {
+        Condition::And(c1, c2) if and => {
+            operands(*c1, and, result);
+            operands(*c2, and, result);
+        },
+        Condition::Or(c1, c2) if !and => {
+            operands(*c1, and, result);
+            operands(*c2, and, result);
+        },
+        c => result.push(c),
+    }
+}
 
 impl<T : AsRef<[u8]> + Clone> Condition<T> {
 
@@ -59,6 +85,55 @@ impl<T : AsRef<[u8]> + Clone> Condition<T> {
         Condition::Fact(Box::new(c))
     }
 
+    /// `NOT EXISTS`: no fact satisfies `c` (anti-join over the bindings
+    /// `c` shares with the enclosing condition)
+    #[inline]
+    pub fn not_exists(c: Condition<T>) -> Self {
+        Condition::Not(Box::new(Condition::fact(c)))
+    }
+
+    #[inline]
+    pub fn rule(name: T, arguments: Vec<Value<T>>) -> Self {
+        Condition::Rule(name, arguments)
+    }
+
+    /// Operands of a (nested) `And`, left to right
+    pub fn conjuncts(&self) -> Vec<&Condition<T>> {
+        match *self {
+            Condition::And(ref c1, ref c2) => {
+                let mut result = c1.conjuncts();
+                result.extend(c2.conjuncts());
+                result
+            },
+            ref c => vec![c],
+        }
+    }
+
+    /// Canonical form: the operands of every `And` and `Or` chain are
+    /// sorted (by their encoding) and nested to the left, so that
+    /// conditions that only differ in the order of these operands are
+    /// equal
+    pub fn normalize(self) -> Self {
+        let and = match self {
+            Condition::And(_, _) => true,
+            Condition::Or(_, _) => false,
+            Condition::Fact(c) => return Condition::fact(c.normalize()),
+            Condition::Not(c) => return Condition::not(c.normalize()),
+            Condition::Trait(t, c) => return Condition::trait_scope(t, c.normalize()),
+            c => return c,
+        };
+        let mut parts = vec![];
+        operands(self, and, &mut parts);
+        let mut parts: Vec<(Vec<u8>, Condition<T>)> = parts.into_iter()
+            .map(|c| c.normalize())
+            .map(|c| (c.to_bytes(), c))
+            .collect();
+        parts.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut parts = parts.into_iter().map(|(_, c)| c);
+        let first = parts.next().unwrap();
+        parts.fold(first, |c1, c2| if and { c1.and(c2) } else { c1.or(c2) })
+    }
+
 }
 
 use std::ops::Not;
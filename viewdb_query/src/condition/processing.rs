@@ -5,49 +5,68 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use super::{Condition, Value};
+use super::visit::{self, Visitor};
 use super::super::{TraitPattern, TraitResolver};
 
 use std::marker::PhantomData;
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error<T : AsRef<[u8]> + Clone> {
+    /// Trait resolver has no definition for the trait
+    UnknownTrait(T),
+    /// Values that can't be compared with each other
+    TypeMismatch(Value<T>, Value<T>),
+}
+
+/// Processing never drops a condition: a condition that was eliminated
+/// as trivially true is returned as `Condition::True`.
+pub type Result<T> = ::std::result::Result<Condition<T>, Error<T>>;
+
 pub trait Processor<T> where T: AsRef<[u8]> + Clone {
-    fn process(&self, condition: Condition<T>) -> Option<Condition<T>>;
+    fn process(&self, condition: Condition<T>) -> Result<T>;
+
+    /// Name used to identify the processor in a `Pipeline` trace
+    fn name(&self) -> &str {
+        "Processor"
+    }
 }
 
 pub trait ProcessorExtension<T> where T: AsRef<[u8]> + Clone {
-    fn after_that<P: Processor<T>>(self, processor: P) -> Option<Condition<T>>;
+    fn after_that<P: Processor<T>>(self, processor: P) -> Result<T>;
 }
 
-impl<T> ProcessorExtension<T> for Option<Condition<T>> where T: AsRef<[u8]> + Clone {
-    fn after_that<P: Processor<T>>(self, processor: P) -> Option<Condition<T>> {
+impl<T> ProcessorExtension<T> for Result<T> where T: AsRef<[u8]> + Clone {
+    fn after_that<P: Processor<T>>(self, processor: P) -> Result<T> {
         self.and_then(|x| processor.process(x))
     }
 }
 
 pub trait Recursive<T> : Processor<T> where T: AsRef<[u8]> + Clone {
-    fn process_recursively(&self, condition: Condition<T>) -> Option<Condition<T>> {
+    fn process_recursively(&self, condition: Condition<T>) -> Result<T> {
         match condition {
-            Condition::Fact(c) => Some(Condition::fact(try_opt!(self.process_recursively(try_opt!(self.process(*c)))))),
-            Condition::Trait(t, c) => Some(Condition::trait_scope(t, try_opt!(self.process_recursively(try_opt!(self.process(*c)))))),
+            Condition::Fact(c) => Ok(Condition::fact(self.process_recursively(self.process(*c)?)?)),
+            Condition::Trait(t, c) => Ok(Condition::trait_scope(t, self.process_recursively(self.process(*c)?)?)),
             Condition::And(c1, c2) => {
-                match self.process(*c1).and_then(|v| self.process_recursively(v)) {
-                    None => self.process(*c2).and_then(|v| self.process_recursively(v)),
-                    Some(c) => Some(match self.process(*c2).and_then(|v| self.process_recursively(v)) {
-                        None => c,
-                        Some(c_) => c.and(c_)
-                    })
-                }
+                let c1 = self.process(*c1).and_then(|v| self.process_recursively(v))?;
+                let c2 = self.process(*c2).and_then(|v| self.process_recursively(v))?;
+                Ok(match (c1, c2) {
+                    (Condition::True, c) | (c, Condition::True) => c,
+                    (c1, c2) => c1.and(c2),
+                })
             },
             Condition::Or(c1, c2) => {
-                match self.process(*c1).and_then(|v| self.process_recursively(v)) {
-                    None => self.process(*c2).and_then(|v| self.process_recursively(v)),
-                    Some(c) => Some(match self.process(*c2).and_then(|v| self.process_recursively(v)) {
-                        None => c,
-                        Some(c_) => c.or(c_)
-                    })
-                }
+                let c1 = self.process(*c1).and_then(|v| self.process_recursively(v))?;
+                let c2 = self.process(*c2).and_then(|v| self.process_recursively(v))?;
+                Ok(match (c1, c2) {
+                    (Condition::True, _) | (_, Condition::True) => Condition::True,
+                    (c1, c2) => c1.or(c2),
+                })
             },
-            Condition::Not(c) => Some(!try_opt!(self.process_recursively(try_opt!(self.process(*c))))),
-            _ => Some(condition),
+            Condition::Not(c) => Ok(match self.process_recursively(self.process(*c)?)? {
+                Condition::True => Condition::False,
+                c => !c,
+            }),
+            _ => Ok(condition),
         }
     }
 }
@@ -63,11 +82,14 @@ impl<T: AsRef<[u8]> + Clone + PartialOrd, R : TraitResolver<T>> TraitsExpansion<
 }
 
 impl<T: AsRef<[u8]> + Clone + PartialOrd, R : TraitResolver<T>> Processor<T> for TraitsExpansion<T, R> {
-    fn process(&self, condition: Condition<T>) -> Option<Condition<T>> {
+    fn process(&self, condition: Condition<T>) -> Result<T> {
         match condition {
             Condition::Trait(name, boxed) => {
-                let trait_def = self.0.resolve(name);
-                let mut cond = try_opt!(self.process(*boxed));
+                let trait_def = match self.0.resolve(name.clone()) {
+                    Some(trait_def) => trait_def,
+                    None => return Err(Error::UnknownTrait(name)),
+                };
+                let mut cond = self.process(*boxed)?;
                 for pattern in trait_def.iter() {
                     match pattern {
                         &TraitPattern(ref attr, None) => {
@@ -78,83 +100,122 @@ impl<T: AsRef<[u8]> + Clone + PartialOrd, R : TraitResolver<T>> Processor<T> for
                         }
                     }
                 }
-                Some(cond)
+                Ok(cond)
             },
             c => self.process_recursively(c),
         }
     }
+
+    fn name(&self) -> &str {
+        "TraitsExpansion"
+    }
 }
 
 pub struct PresentEqualCompaction;
 impl<T: AsRef<[u8]> + Clone + PartialOrd> Recursive<T> for PresentEqualCompaction {}
 
 impl<T: AsRef<[u8]> + Clone + PartialOrd> Processor<T> for PresentEqualCompaction {
-    fn process(&self, condition: Condition<T>) -> Option<Condition<T>> {
-        fn contains_equal<T: AsRef<[u8]> + Clone + PartialOrd>(cond: &Condition<T>, attr: &Value<T>) -> bool {
-            match cond {
-                &Condition::Equal(ref a, _) if a == attr => true,
-                &Condition::Fact(ref c) => contains_equal(c, attr),
-                &Condition::And(ref c1, ref c2) => contains_equal(c1, attr) || contains_equal(c2, attr),
-                &Condition::Or(ref c1, ref c2) => contains_equal(c1, attr) && contains_equal(c2, attr),
-                _ => false,
+    fn process(&self, condition: Condition<T>) -> Result<T> {
+        /// Whether the attribute is equated to something in every solution
+        /// of the condition (in some conjunct, or in every disjunct)
+        struct Equality<'v, T : AsRef<[u8]> + Clone + 'v> { attr: &'v Value<T>, found: bool }
+        impl<'v, T: AsRef<[u8]> + Clone + PartialOrd> Visitor<T> for Equality<'v, T> {
+            fn visit_condition(&mut self, condition: &Condition<T>) {
+                match *condition {
+                    Condition::Equal(ref a, _) if a == self.attr => self.found = true,
+                    Condition::Or(ref c1, ref c2) =>
+                        self.found |= contains_equal(c1, self.attr) && contains_equal(c2, self.attr),
+                    Condition::Fact(_) | Condition::And(_, _) => visit::visit_condition(self, condition),
+                    _ => (),
+                }
             }
         }
+        fn contains_equal<T: AsRef<[u8]> + Clone + PartialOrd>(cond: &Condition<T>, attr: &Value<T>) -> bool {
+            let mut equality = Equality { attr, found: false };
+            equality.visit_condition(cond);
+            equality.found
+        }
         match condition {
             Condition::And(c1, c2) => {
                 match (*c1, *c2) {
                     (Condition::Present(a1), cond) | (cond, Condition::Present(a1)) =>
                         if contains_equal(&cond, &a1) {
-                            Some(cond)
+                            Ok(cond)
                         } else {
-                            Some(Condition::Present(a1).and(try_opt!(self.process(cond))))
+                            Ok(Condition::Present(a1).and(self.process(cond)?))
                         },
                     (c1, c2) =>
-                        Some(try_opt!(self.process(c1)).and(try_opt!(self.process(c2)))),
+                        Ok(self.process(c1)?.and(self.process(c2)?)),
                 }
             },
             c => self.process_recursively(c),
         }
     }
+
+    fn name(&self) -> &str {
+        "PresentEqualCompaction"
+    }
 }
 
 pub struct ComparisonSuppression;
 impl<T: AsRef<[u8]> + Clone + PartialOrd> Recursive<T> for ComparisonSuppression {}
 
 impl<T: AsRef<[u8]> + Clone + PartialOrd> Processor<T> for ComparisonSuppression {
-    fn process(&self, condition: Condition<T>) -> Option<Condition<T>> {
+    fn process(&self, condition: Condition<T>) -> Result<T> {
         match condition {
-            Condition::Equal(Value::Data(ref v1), Value::Data(ref v2)) if v1 == v2 => None,
-            Condition::Equal(Value::Data(_), Value::Data(_)) => Some(Condition::False),
-            Condition::GreaterThan(Value::Data(ref v1), Value::Data(ref v2)) if v1 > v2 => None,
-            Condition::GreaterThan(Value::Data(_), Value::Data(_)) => Some(Condition::False),
-            Condition::LessThan(Value::Data(ref v1), Value::Data(ref v2)) if v1 < v2 => None,
-            Condition::LessThan(Value::Data(_), Value::Data(_)) => Some(Condition::False),
-            Condition::Equal(Value::Attribute(ref a1), Value::Attribute(ref a2)) if a1 == a2 => None,
-            Condition::Equal(Value::Binding(ref b1), Value::Attribute(ref b2)) if b1 == b2 => None,
+            // typed data (see `datum`) of different types
+            Condition::Equal(Value::Data(v1), Value::Data(v2)) |
+            Condition::GreaterThan(Value::Data(v1), Value::Data(v2)) |
+            Condition::LessThan(Value::Data(v1), Value::Data(v2)) if v1.partial_cmp(&v2).is_none() =>
+                Err(Error::TypeMismatch(Value::Data(v1), Value::Data(v2))),
+            Condition::Equal(Value::Data(ref v1), Value::Data(ref v2)) if v1 == v2 => Ok(Condition::True),
+            Condition::Equal(Value::Data(_), Value::Data(_)) => Ok(Condition::False),
+            Condition::GreaterThan(Value::Data(ref v1), Value::Data(ref v2)) if v1 > v2 => Ok(Condition::True),
+            Condition::GreaterThan(Value::Data(_), Value::Data(_)) => Ok(Condition::False),
+            Condition::LessThan(Value::Data(ref v1), Value::Data(ref v2)) if v1 < v2 => Ok(Condition::True),
+            Condition::LessThan(Value::Data(_), Value::Data(_)) => Ok(Condition::False),
+            Condition::Equal(Value::Attribute(ref a1), Value::Attribute(ref a2)) if a1 == a2 => Ok(Condition::True),
+            Condition::Equal(Value::Binding(ref b1), Value::Attribute(ref b2)) if b1 == b2 => Ok(Condition::True),
+            // an attribute value and an attribute's transaction ID are never comparable
+            Condition::Equal(v1 @ Value::Attribute(_), v2 @ Value::AttributeTxid(_)) |
+            Condition::Equal(v1 @ Value::AttributeTxid(_), v2 @ Value::Attribute(_)) |
+            Condition::LessThan(v1 @ Value::Attribute(_), v2 @ Value::AttributeTxid(_)) |
+            Condition::LessThan(v1 @ Value::AttributeTxid(_), v2 @ Value::Attribute(_)) |
+            Condition::GreaterThan(v1 @ Value::Attribute(_), v2 @ Value::AttributeTxid(_)) |
+            Condition::GreaterThan(v1 @ Value::AttributeTxid(_), v2 @ Value::Attribute(_)) =>
+                Err(Error::TypeMismatch(v1, v2)),
             c => self.process_recursively(c),
         }
     }
+
+    fn name(&self) -> &str {
+        "ComparisonSuppression"
+    }
 }
 
 pub struct BooleanLiteralSuppression;
 impl<T: AsRef<[u8]> + Clone + PartialOrd> Recursive<T> for BooleanLiteralSuppression {}
 
 impl<T: AsRef<[u8]> + Clone + PartialOrd> Processor<T> for BooleanLiteralSuppression {
-    fn process(&self, condition: Condition<T>) -> Option<Condition<T>> {
+    fn process(&self, condition: Condition<T>) -> Result<T> {
         match condition {
             Condition::And(a, b) =>
                 if *a == Condition::False || *b == Condition::False {
-                    None
+                    Ok(Condition::False)
                 } else if *a == Condition::True {
-                    Some(*b)
+                    Ok(*b)
                 } else if *b == Condition::True {
-                    Some(*a)
+                    Ok(*a)
                 } else {
-                    Some(a.and(*b))
+                    Ok(a.and(*b))
                 },
             c => self.process_recursively(c),
         }
     }
+
+    fn name(&self) -> &str {
+        "BooleanLiteralSuppression"
+    }
 }
 
 
@@ -162,22 +223,152 @@ pub struct ImplicitFact;
 impl<T: AsRef<[u8]> + Clone + PartialOrd> Recursive<T> for ImplicitFact {}
 
 impl<T: AsRef<[u8]> + Clone + PartialOrd> Processor<T> for ImplicitFact {
-    fn process(&self, condition: Condition<T>) -> Option<Condition<T>> {
-        fn contains_fact<T: AsRef<[u8]> + Clone + PartialOrd>(cond: &Condition<T>) -> bool {
-            match cond {
-                &Condition::Fact(_) => true,
-                &Condition::And(ref c1, ref c2) => contains_fact(c1) || contains_fact(c2),
-                &Condition::Or(ref c1, ref c2) => contains_fact(c1) || contains_fact(c2),
-                &Condition::Not(ref c) => contains_fact(c),
-                &Condition::Trait(_, ref c) => contains_fact(c),
-                _ => false,
+    fn process(&self, condition: Condition<T>) -> Result<T> {
+        #[derive(Default)]
+        struct Scoping { fact: bool, attribute: bool }
+        impl<T: AsRef<[u8]> + Clone> Visitor<T> for Scoping {
+            fn visit_fact(&mut self, _body: &Condition<T>) {
+                self.fact = true;
             }
+            fn visit_attribute(&mut self, _attribute: &T) {
+                self.attribute = true;
+            }
+            fn visit_attribute_txid(&mut self, _attribute: &T) {
+                self.attribute = true;
+            }
+        }
+        fn scoping<T: AsRef<[u8]> + Clone>(condition: &Condition<T>) -> Scoping {
+            let mut scoping = Scoping::default();
+            scoping.visit_condition(condition);
+            scoping
         }
-        if !contains_fact(&condition) {
-            Some(Condition::fact(condition))
-        } else {
-            Some(condition)
+        // (a condition without attributes, such as one made of rules only,
+        // doesn't range over facts)
+        let scope = scoping(&condition);
+        if !scope.fact && scope.attribute {
+            return Ok(Condition::fact(condition));
         }
+        // attributes next to explicit fact scopes (such as `NOT EXISTS`)
+        // still belong to an implicit fact of their own
+        let (unscoped, scoped): (Vec<_>, Vec<_>) = condition.conjuncts().into_iter().partition(|c| {
+            let scoping = scoping(c);
+            !scoping.fact && scoping.attribute
+        });
+        if unscoped.is_empty() {
+            return Ok(condition);
+        }
+        let mut unscoped = unscoped.into_iter().cloned();
+        let first = unscoped.next().unwrap();
+        let implicit = Condition::fact(unscoped.fold(first, |c1, c2| c1.and(c2)));
+        Ok(scoped.into_iter().cloned().fold(implicit, |c1, c2| c1.and(c2)))
+    }
+
+    fn name(&self) -> &str {
+        "ImplicitFact"
+    }
+}
+
+/// A rewrite recorded by a traced `Pipeline`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rewrite<T : AsRef<[u8]> + Clone> {
+    pub iteration: usize,
+    pub processor: String,
+    pub before: Condition<T>,
+    pub after: Condition<T>,
+}
+
+/// Result of running a `Pipeline`
+#[derive(Debug)]
+pub struct Outcome<T : AsRef<[u8]> + Clone> {
+    pub condition: Result<T>,
+    pub iterations: usize,
+    /// `false` if the iteration cap was hit or a processor has failed
+    /// before reaching a fixpoint
+    pub converged: bool,
+    pub trace: Vec<Rewrite<T>>,
+}
+
+pub const DEFAULT_MAX_ITERATIONS: usize = 16;
+
+/// Runs a sequence of processors repeatedly until the condition
+/// stops changing (or the iteration cap is reached).
+pub struct Pipeline<'a, T : AsRef<[u8]> + Clone> {
+    processors: Vec<Box<dyn Processor<T> + 'a>>,
+    max_iterations: usize,
+    tracing: bool,
+}
+
+impl<'a, T : AsRef<[u8]> + Clone + PartialEq> Pipeline<'a, T> {
+    pub fn new() -> Self {
+        Pipeline {
+            processors: vec![],
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            tracing: false,
+        }
+    }
+
+    pub fn with<P : Processor<T> + 'a>(mut self, processor: P) -> Self {
+        self.processors.push(Box::new(processor));
+        self
+    }
+
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Record every rewrite into `Outcome::trace`
+    pub fn traced(mut self) -> Self {
+        self.tracing = true;
+        self
+    }
+
+    pub fn run(&self, condition: Condition<T>) -> Outcome<T> {
+        let mut trace = vec![];
+        let mut current = condition;
+        let mut iterations = 0;
+        while iterations < self.max_iterations {
+            iterations += 1;
+            let pass_start = current.clone();
+            for processor in self.processors.iter() {
+                let before = if self.tracing { Some(current.clone()) } else { None };
+                let after = match processor.process(current) {
+                    Ok(c) => c,
+                    Err(e) => return Outcome { condition: Err(e), iterations, converged: false, trace },
+                };
+                if let Some(before) = before {
+                    if after != before {
+                        trace.push(Rewrite {
+                            iteration: iterations,
+                            processor: processor.name().to_string(),
+                            before,
+                            after: after.clone(),
+                        });
+                    }
+                }
+                current = after;
+            }
+            if current == pass_start {
+                return Outcome { condition: Ok(current), iterations, converged: true, trace };
+            }
+        }
+        Outcome { condition: Ok(current), iterations, converged: false, trace }
+    }
+}
+
+impl<'a, T : AsRef<[u8]> + Clone + PartialEq> Default for Pipeline<'a, T> {
+    fn default() -> Self {
+        Pipeline::new()
+    }
+}
+
+impl<'a, T : AsRef<[u8]> + Clone + PartialEq> Processor<T> for Pipeline<'a, T> {
+    fn process(&self, condition: Condition<T>) -> Result<T> {
+        self.run(condition).condition
+    }
+
+    fn name(&self) -> &str {
+        "Pipeline"
     }
 }
 
@@ -186,11 +377,79 @@ mod tests {
 
     use Condition::*;
     use Value;
-    use condition::processing::{Processor, ImplicitFact};
+    use condition::processing::{Processor, Error, ImplicitFact, Pipeline, BooleanLiteralSuppression,
+                                PresentEqualCompaction, ComparisonSuppression};
 
     #[test]
     pub fn implicit_fact() {
         let cond = Equal(Value::Attribute("a"), Value::Data("1"));
-        assert_matches!(ImplicitFact.process(cond), Some(Fact(_)));
+        assert_matches!(ImplicitFact.process(cond), Ok(Fact(_)));
+        let cond = Equal(Value::Attribute("a"), Value::Binding("A"))
+                   .and(::Condition::not_exists(Equal(Value::Attribute("b"), Value::Binding("A"))));
+        assert_eq!(ImplicitFact.process(cond),
+                   Ok(::Condition::fact(Equal(Value::Attribute("a"), Value::Binding("A")))
+                      .and(::Condition::not_exists(Equal(Value::Attribute("b"), Value::Binding("A"))))));
+        // without attributes, there's no fact to scope
+        let cond = ::Condition::rule("linked", vec![Value::Binding("A"), Value::Binding("B")])
+                   .and(LessThan(Value::Binding("A"), Value::Data("1")));
+        assert_eq!(ImplicitFact.process(cond.clone()), Ok(cond));
+        assert_eq!(ImplicitFact.process(True::<&str>), Ok(True));
+    }
+
+    #[test]
+    pub fn pipeline_fixpoint() {
+        // compacting `Present` leaves `And(Equal, True)` behind, which only
+        // the next pass of `BooleanLiteralSuppression` can clean up
+        let cond = Present(Value::Attribute("a"))
+                   .and(Equal(Value::Attribute("a"), Value::Data("1")).and(True));
+        let pipeline = Pipeline::new()
+                       .with(BooleanLiteralSuppression)
+                       .with(PresentEqualCompaction)
+                       .traced();
+        let outcome = pipeline.run(cond.clone());
+        assert!(outcome.converged);
+        assert_eq!(outcome.condition, Ok(Equal(Value::Attribute("a"), Value::Data("1"))));
+        assert_eq!(outcome.iterations, 3);
+        let processors: Vec<_> = outcome.trace.iter().map(|r| r.processor.as_str()).collect();
+        assert_eq!(processors, vec!["PresentEqualCompaction", "BooleanLiteralSuppression"]);
+
+        let capped = pipeline.max_iterations(1).run(cond);
+        assert!(!capped.converged);
+    }
+
+    #[test]
+    pub fn present_equal_compaction() {
+        let a = || Value::Attribute("a");
+        let cond = Present(a()).and(Equal(a(), Value::Data("1")).or(Equal(a(), Value::Data("2"))));
+        assert_eq!(PresentEqualCompaction.process(cond),
+                   Ok(Equal(a(), Value::Data("1")).or(Equal(a(), Value::Data("2")))));
+        let cond = Present(a()).and(Equal(a(), Value::Data("1")).or(Present(Value::Attribute("b"))));
+        assert_eq!(PresentEqualCompaction.process(cond.clone()), Ok(cond));
+        let cond = Present(a()).and(::Condition::not(Equal(a(), Value::Data("1"))));
+        assert_eq!(PresentEqualCompaction.process(cond.clone()), Ok(cond));
+    }
+
+    #[test]
+    pub fn trivially_true_is_not_a_failure() {
+        let cond = Equal(Value::Data("1"), Value::Data("1"))
+                   .and(Equal(Value::Attribute("a"), Value::Binding("A")));
+        assert_eq!(ComparisonSuppression.process(cond),
+                   Ok(Equal(Value::Attribute("a"), Value::Binding("A"))));
+        let cond = Equal(Value::Data("1"), Value::Data("1")).or(Present(Value::Attribute("a")));
+        assert_eq!(ComparisonSuppression.process(cond), Ok(True));
+    }
+
+    #[test]
+    pub fn false_conjunction() {
+        let cond = Present(Value::Attribute("a")).and(False);
+        assert_eq!(BooleanLiteralSuppression.process(cond), Ok(False));
+    }
+
+    #[test]
+    pub fn processing_errors() {
+        let cond = Present(Value::Binding("A"));
+        assert_eq!(ComparisonSuppression.process(cond.clone()), Ok(cond));
+        let cond = LessThan(Value::AttributeTxid("a"), Value::Attribute("b"));
+        assert_matches!(ComparisonSuppression.process(cond), Err(Error::TypeMismatch(_, _)));
     }
 }
\ No newline at end of file
@@ -0,0 +1,559 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Textual query syntax.
+//!
+//! ```text
+//! NameChanged(<https://viewdb.org/attributes#value> = ?Name) AND
+//! NOT FACT(<#factType> = "AccountClosed" AND <#object> = ?Person) OR
+//! RULE linked(?Person, "a") AND TXID(<#value>) < "\x00\x10"
+//! ```
+//!
+//! Data is quoted (`"..."`), attributes are in angle brackets, bindings
+//! start with `?` and parameters with `$`. Bytes that aren't printable UTF-8 are escaped (`\xNN`
+//! for a byte that isn't part of a UTF-8 character, `\u{NNNN}` for a
+//! control character). `NOT` binds tighter than `AND`, which binds tighter
+//! than `OR`; both are left-associative, and printing only parenthesizes
+//! what parsing wouldn't group the same way, so a printed condition parses
+//! back into the same tree.
+//!
+//! A query wraps a condition the way the README does:
+//!
+//! ```text
+//! SELECT ?Name, MAX(?Timestamp) WHERE <condition>
+//!        ORDER BY ?Name DESC LIMIT 10 OFFSET 20
+//! ```
+//!
+//! Literals can also be parsed at compile time, with the `condition!` and
+//! `query!` macros of the separate `viewdb_macros` crate.
+
+use std::fmt;
+use std::str::{self, FromStr};
+
+use super::{Condition, Value};
+use query::{Query, Projection, Aggregate, Direction, Kind};
+
+const KEYWORDS: &[&str] = &["AND", "OR", "NOT", "TRUE", "FALSE", "FACT", "PRESENT", "RULE", "TXID"];
+
+fn identifier(s: &[u8]) -> bool {
+    match s.split_first() {
+        Some((first, rest)) => (first.is_ascii_alphabetic() || *first == b'_') &&
+                               rest.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_'),
+        None => false,
+    }
+}
+
+/// Writes bytes between delimiters, escaping what wouldn't read back
+fn quote(f: &mut fmt::Formatter, bytes: &[u8], open: char, close: char) -> fmt::Result {
+    write!(f, "{}", open)?;
+    let mut bytes = bytes;
+    while !bytes.is_empty() {
+        let (valid, invalid) = match str::from_utf8(bytes) {
+            Ok(s) => (s, 0),
+            Err(e) => (str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(), e.error_len().unwrap_or(bytes.len() - e.valid_up_to())),
+        };
+        for c in valid.chars() {
+            match c {
+                '\\' => write!(f, "\\\\")?,
+                c if c == close => write!(f, "\\{}", c)?,
+                c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        for b in &bytes[valid.len()..valid.len() + invalid] {
+            write!(f, "\\x{:02x}", b)?;
+        }
+        bytes = &bytes[valid.len() + invalid..];
+    }
+    write!(f, "{}", close)
+}
+
+fn name(f: &mut fmt::Formatter, name: &[u8]) -> fmt::Result {
+    match str::from_utf8(name) {
+        Ok(s) if identifier(name) && !KEYWORDS.contains(&s) => write!(f, "{}", s),
+        _ => quote(f, name, '"', '"'),
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> fmt::Display for Value<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Data(ref d) => quote(f, d.as_ref(), '"', '"'),
+            Value::Binding(ref b) if identifier(b.as_ref()) => write!(f, "?{}", str::from_utf8(b.as_ref()).unwrap()),
+            Value::Binding(ref b) => {
+                write!(f, "?")?;
+                quote(f, b.as_ref(), '"', '"')
+            },
+            Value::Parameter(ref p) if identifier(p.as_ref()) => write!(f, "${}", str::from_utf8(p.as_ref()).unwrap()),
+            Value::Parameter(ref p) => {
+                write!(f, "$")?;
+                quote(f, p.as_ref(), '"', '"')
+            },
+            Value::Attribute(ref a) => quote(f, a.as_ref(), '<', '>'),
+            Value::AttributeTxid(ref a) => {
+                write!(f, "TXID(")?;
+                quote(f, a.as_ref(), '<', '>')?;
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+const OR: u8 = 1;
+const AND: u8 = 2;
+const UNARY: u8 = 3;
+
+impl<T : AsRef<[u8]> + Clone> Condition<T> {
+    /// Writes the condition where an operator of at least `precedence` is
+    /// expected
+    fn write(&self, f: &mut fmt::Formatter, precedence: u8) -> fmt::Result {
+        match *self {
+            Condition::Or(_, _) if precedence > OR => {
+                write!(f, "(")?;
+                self.write(f, OR)?;
+                write!(f, ")")
+            },
+            Condition::And(_, _) if precedence > AND => {
+                write!(f, "(")?;
+                self.write(f, AND)?;
+                write!(f, ")")
+            },
+            Condition::Or(ref c1, ref c2) => {
+                c1.write(f, OR)?;
+                write!(f, " OR ")?;
+                c2.write(f, AND)
+            },
+            Condition::And(ref c1, ref c2) => {
+                c1.write(f, AND)?;
+                write!(f, " AND ")?;
+                c2.write(f, UNARY)
+            },
+            Condition::Not(ref c) => {
+                write!(f, "NOT ")?;
+                c.write(f, UNARY)
+            },
+            Condition::Fact(ref c) => write!(f, "FACT({})", c),
+            Condition::Trait(ref t, ref c) => {
+                name(f, t.as_ref())?;
+                write!(f, "({})", c)
+            },
+            Condition::Rule(ref r, ref arguments) => {
+                write!(f, "RULE ")?;
+                name(f, r.as_ref())?;
+                write!(f, "(")?;
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ")")
+            },
+            Condition::Present(ref v) => write!(f, "PRESENT({})", v),
+            Condition::Equal(ref v1, ref v2) => write!(f, "{} = {}", v1, v2),
+            Condition::LessThan(ref v1, ref v2) => write!(f, "{} < {}", v1, v2),
+            Condition::GreaterThan(ref v1, ref v2) => write!(f, "{} > {}", v1, v2),
+            Condition::True => write!(f, "TRUE"),
+            Condition::False => write!(f, "FALSE"),
+        }
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> fmt::Display for Condition<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, OR)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    UnexpectedEnd,
+    /// Unexpected input at the given byte offset
+    Unexpected(usize),
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.input.len() && self.input[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.position).cloned()
+    }
+
+    fn unexpected(&self) -> Error {
+        if self.position < self.input.len() { Error::Unexpected(self.position) } else { Error::UnexpectedEnd }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), Error> {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    /// Identifier at the current position, if any (not consumed)
+    fn identifier(&mut self) -> Option<&'a [u8]> {
+        self.skip_whitespace();
+        let rest = &self.input[self.position..];
+        let len = rest.iter().position(|c| !(c.is_ascii_alphanumeric() || *c == b'_')).unwrap_or(rest.len());
+        if len > 0 && identifier(&rest[..len]) { Some(&rest[..len]) } else { None }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if self.identifier() == Some(keyword.as_bytes()) {
+            self.position += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn hex(&mut self, digits: &[u8]) -> Result<u32, Error> {
+        str::from_utf8(digits).ok().and_then(|s| u32::from_str_radix(s, 16).ok()).ok_or(Error::Unexpected(self.position))
+    }
+
+    /// Delimited, escaped bytes
+    fn quoted(&mut self, open: u8, close: u8) -> Result<Vec<u8>, Error> {
+        self.expect(open)?;
+        let mut result = vec![];
+        loop {
+            match self.input.get(self.position).cloned() {
+                None => return Err(Error::UnexpectedEnd),
+                Some(c) if c == close => {
+                    self.position += 1;
+                    return Ok(result);
+                },
+                Some(b'\\') => {
+                    let escape = self.position;
+                    match self.input.get(self.position + 1).cloned() {
+                        Some(b'x') if self.position + 4 <= self.input.len() => {
+                            let b = self.hex(&self.input[self.position + 2..self.position + 4])?;
+                            result.push(b as u8);
+                            self.position += 4;
+                        },
+                        Some(b'u') if self.input.get(self.position + 2) == Some(&b'{') => {
+                            let end = self.input[self.position..].iter().position(|c| *c == b'}')
+                                          .ok_or(Error::UnexpectedEnd)? + self.position;
+                            let c = self.hex(&self.input[self.position + 3..end])?;
+                            let c = ::std::char::from_u32(c).ok_or(Error::Unexpected(escape))?;
+                            let mut buf = [0; 4];
+                            result.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                            self.position = end + 1;
+                        },
+                        Some(c) if c == b'\\' || c == close => {
+                            result.push(c);
+                            self.position += 2;
+                        },
+                        Some(_) => return Err(Error::Unexpected(escape)),
+                        None => return Err(Error::UnexpectedEnd),
+                    }
+                },
+                Some(c) => {
+                    result.push(c);
+                    self.position += 1;
+                },
+            }
+        }
+    }
+
+    /// Trait or rule name
+    fn name(&mut self) -> Result<Vec<u8>, Error> {
+        match self.identifier() {
+            Some(name) => {
+                self.position += name.len();
+                Ok(name.to_vec())
+            },
+            None => self.quoted(b'"', b'"'),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value<Vec<u8>>, Error> {
+        match self.peek() {
+            Some(b'"') => Ok(Value::Data(self.quoted(b'"', b'"')?)),
+            Some(b'<') => Ok(Value::Attribute(self.quoted(b'<', b'>')?)),
+            Some(b'?') => {
+                self.position += 1;
+                Ok(Value::Binding(self.name()?))
+            },
+            Some(b'$') => {
+                self.position += 1;
+                Ok(Value::Parameter(self.name()?))
+            },
+            _ if self.keyword("TXID") => {
+                self.expect(b'(')?;
+                let attribute = self.quoted(b'<', b'>')?;
+                self.expect(b')')?;
+                Ok(Value::AttributeTxid(attribute))
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn comparison(&mut self, v1: Value<Vec<u8>>) -> Result<Condition<Vec<u8>>, Error> {
+        let op = self.peek();
+        self.position += 1;
+        match op {
+            Some(b'=') => Ok(Condition::Equal(v1, self.value()?)),
+            Some(b'<') => Ok(Condition::LessThan(v1, self.value()?)),
+            Some(b'>') => Ok(Condition::GreaterThan(v1, self.value()?)),
+            _ => {
+                self.position -= 1;
+                Err(self.unexpected())
+            },
+        }
+    }
+
+    fn parenthesized(&mut self) -> Result<Condition<Vec<u8>>, Error> {
+        self.expect(b'(')?;
+        let c = self.or()?;
+        self.expect(b')')?;
+        Ok(c)
+    }
+
+    fn primary(&mut self) -> Result<Condition<Vec<u8>>, Error> {
+        match self.peek() {
+            Some(b'(') => self.parenthesized(),
+            Some(b'"') => {
+                let data = self.quoted(b'"', b'"')?;
+                if self.peek() == Some(b'(') {
+                    Ok(Condition::trait_scope(data, self.parenthesized()?))
+                } else {
+                    self.comparison(Value::Data(data))
+                }
+            },
+            _ if self.keyword("TRUE") => Ok(Condition::True),
+            _ if self.keyword("FALSE") => Ok(Condition::False),
+            _ if self.keyword("FACT") => Ok(Condition::fact(self.parenthesized()?)),
+            _ if self.keyword("PRESENT") => {
+                self.expect(b'(')?;
+                let v = self.value()?;
+                self.expect(b')')?;
+                Ok(Condition::Present(v))
+            },
+            _ if self.keyword("RULE") => {
+                let name = self.name()?;
+                self.expect(b'(')?;
+                let mut arguments = vec![];
+                if self.peek() != Some(b')') {
+                    arguments.push(self.value()?);
+                    while self.peek() == Some(b',') {
+                        self.position += 1;
+                        arguments.push(self.value()?);
+                    }
+                }
+                self.expect(b')')?;
+                Ok(Condition::rule(name, arguments))
+            },
+            _ => match self.identifier() {
+                Some(name) if !KEYWORDS.iter().any(|k| k.as_bytes() == name) => {
+                    self.position += name.len();
+                    Ok(Condition::trait_scope(name.to_vec(), self.parenthesized()?))
+                },
+                _ => {
+                    let v = self.value()?;
+                    self.comparison(v)
+                },
+            },
+        }
+    }
+
+    fn unary(&mut self) -> Result<Condition<Vec<u8>>, Error> {
+        if self.keyword("NOT") {
+            Ok(Condition::not(self.unary()?))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn and(&mut self) -> Result<Condition<Vec<u8>>, Error> {
+        let mut c = self.unary()?;
+        while self.keyword("AND") {
+            c = c.and(self.unary()?);
+        }
+        Ok(c)
+    }
+
+    fn or(&mut self) -> Result<Condition<Vec<u8>>, Error> {
+        let mut c = self.and()?;
+        while self.keyword("OR") {
+            c = c.or(self.and()?);
+        }
+        Ok(c)
+    }
+
+    fn require(&mut self, keyword: &str) -> Result<(), Error> {
+        if self.keyword(keyword) { Ok(()) } else { Err(self.unexpected()) }
+    }
+
+    fn binding(&mut self) -> Result<Vec<u8>, Error> {
+        self.expect(b'?')?;
+        self.name()
+    }
+
+    fn number(&mut self) -> Result<usize, Error> {
+        self.skip_whitespace();
+        let rest = &self.input[self.position..];
+        let len = rest.iter().position(|c| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let number = str::from_utf8(&rest[..len]).unwrap().parse().map_err(|_| self.unexpected())?;
+        self.position += len;
+        Ok(number)
+    }
+
+    fn projection(&mut self) -> Result<Projection<Vec<u8>>, Error> {
+        let aggregate = [("COUNT", Aggregate::Count), ("MIN", Aggregate::Min), ("MAX", Aggregate::Max)].iter()
+                        .find(|&&(keyword, _)| self.keyword(keyword)).map(|&(_, aggregate)| aggregate);
+        match aggregate {
+            Some(aggregate) => {
+                self.expect(b'(')?;
+                let binding = self.binding()?;
+                self.expect(b')')?;
+                Ok(Projection::Aggregate(aggregate, binding))
+            },
+            None => Ok(Projection::Binding(self.binding()?)),
+        }
+    }
+
+    fn query(&mut self) -> Result<Query<Vec<u8>>, Error> {
+        self.require("SELECT")?;
+        let mut projection = vec![self.projection()?];
+        while self.peek() == Some(b',') {
+            self.position += 1;
+            projection.push(self.projection()?);
+        }
+        self.require("WHERE")?;
+        let mut query = Query::new(self.or()?);
+        query.projection = projection;
+        if self.keyword("ORDER") {
+            self.require("BY")?;
+            loop {
+                let binding = self.binding()?;
+                let direction = if self.keyword("DESC") {
+                    Direction::Descending
+                } else {
+                    self.keyword("ASC");
+                    Direction::Ascending
+                };
+                query = query.order_by(binding, direction, Kind::Bytes);
+                if self.peek() != Some(b',') {
+                    break;
+                }
+                self.position += 1;
+            }
+        }
+        if self.keyword("LIMIT") {
+            query = query.limit(self.number()?);
+        }
+        if self.keyword("OFFSET") {
+            query = query.offset(self.number()?);
+        }
+        Ok(query)
+    }
+
+    fn end(&mut self) -> Result<(), Error> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(Error::Unexpected(self.position)),
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Condition<Vec<u8>>, Error> {
+    let mut parser = Parser { input: input.as_bytes(), position: 0 };
+    let condition = parser.or()?;
+    parser.end()?;
+    Ok(condition)
+}
+
+pub fn parse_query(input: &str) -> Result<Query<Vec<u8>>, Error> {
+    let mut parser = Parser { input: input.as_bytes(), position: 0 };
+    let query = parser.query()?;
+    parser.end()?;
+    Ok(query)
+}
+
+impl FromStr for Condition<Vec<u8>> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse(s)
+    }
+}
+
+impl FromStr for Query<Vec<u8>> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        parse_query(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value};
+    use Condition::*;
+    use condition::syntax::{parse, parse_query, Error};
+    use encoding::{Encode, Decode};
+    use query::{Query, Aggregate, Direction, Kind};
+
+    fn bytes(c: &Condition<&'static str>) -> Condition<Vec<u8>> {
+        Condition::decode(&mut c.to_bytes().as_slice()).unwrap()
+    }
+
+    #[test]
+    pub fn print() {
+        let cond = Condition::trait_scope("NameChanged", Equal(Value::Attribute("#value"), Value::Binding("Name")))
+                   .and(Condition::not_exists(Equal(Value::Attribute("#object"), Value::Data("say \"hi\"\n"))))
+                   .or(Condition::rule("linked", vec![Value::Binding("Person"), Value::Data("a")])
+                       .and(LessThan(Value::AttributeTxid("#value"), Value::Data("1"))));
+        assert_eq!(cond.to_string(),
+                   "NameChanged(<#value> = ?Name) AND NOT FACT(<#object> = \"say \\\"hi\\\"\\u{a}\") OR \
+                    RULE linked(?Person, \"a\") AND TXID(<#value>) < \"1\"");
+        let nested = Present(Value::Attribute("a")).and(Condition::True.or(Condition::False).and(Condition::True));
+        assert_eq!(nested.to_string(), "PRESENT(<a>) AND ((TRUE OR FALSE) AND TRUE)");
+    }
+
+    #[test]
+    pub fn round_trip() {
+        let conditions = [
+            Condition::trait_scope("AND", Condition::True).or(Condition::True.or(Condition::False)),
+            !!Condition::fact(GreaterThan(Value::Binding("odd name"), Value::Attribute("a>b\\c"))),
+            Condition::rule("r", vec![]).and(Equal(Value::Data("\u{0}é"), Value::Binding("_x1"))),
+            Equal(Value::Parameter("PersonId"), Value::Parameter("1")),
+        ];
+        for cond in conditions.iter() {
+            assert_eq!(parse(&cond.to_string()), Ok(bytes(cond)));
+        }
+        let binary = Equal(Value::Data(vec![0xff, b'a', 0xc3]), Value::Attribute(vec![0x80]));
+        assert_eq!(parse(&binary.to_string()), Ok(binary));
+        assert_eq!(parse("<a> = "), Err(Error::UnexpectedEnd));
+        assert_eq!(parse("<a> = ?B )"), Err(Error::Unexpected(9)));
+    }
+
+    #[test]
+    pub fn query() {
+        let query = parse_query("SELECT ?Name, MAX(?Timestamp) WHERE Object(<#object> = $PersonId) AND \
+                                 NameChanged(<#value> = ?Name) ORDER BY ?Name DESC LIMIT 10 OFFSET 2").unwrap();
+        let expected = Query::new(Condition::trait_scope("Object", Equal(Value::Attribute("#object"), Value::Parameter("PersonId")))
+                                  .and(Condition::trait_scope("NameChanged", Equal(Value::Attribute("#value"), Value::Binding("Name")))))
+                       .select("Name").select_aggregate(Aggregate::Max, "Timestamp")
+                       .order_by("Name", Direction::Descending, Kind::Bytes)
+                       .limit(10).offset(2);
+        assert_eq!(query.to_bytes(), expected.to_bytes());
+        assert_eq!(parse_query("SELECT ?A WHERE TRUE LIMIT"), Err(Error::UnexpectedEnd));
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Read-only traversal of a `Condition` tree.
+//!
+//! Every method has a default implementation that walks into the children
+//! (through the free functions of this module), so a visitor only needs to
+//! override the nodes it is interested in. An overriding method can call the
+//! corresponding `visit::*` function to keep walking.
+
+use super::{Condition, Value};
+
+pub trait Visitor<T : AsRef<[u8]> + Clone> {
+    fn visit_condition(&mut self, condition: &Condition<T>) {
+        visit_condition(self, condition)
+    }
+
+    /// Called with the body of every `Condition::Fact`; everything visited
+    /// until it returns belongs to that fact scope.
+    fn visit_fact(&mut self, body: &Condition<T>) {
+        visit_fact(self, body)
+    }
+
+    fn visit_trait(&mut self, name: &T, body: &Condition<T>) {
+        visit_trait(self, name, body)
+    }
+
+    fn visit_rule(&mut self, name: &T, arguments: &[Value<T>]) {
+        visit_rule(self, name, arguments)
+    }
+
+    fn visit_value(&mut self, value: &Value<T>) {
+        visit_value(self, value)
+    }
+
+    fn visit_data(&mut self, _data: &T) {}
+    fn visit_binding(&mut self, _binding: &T) {}
+    fn visit_attribute(&mut self, _attribute: &T) {}
+    fn visit_attribute_txid(&mut self, _attribute: &T) {}
+    fn visit_parameter(&mut self, _parameter: &T) {}
+}
+
+pub fn visit_condition<T, V>(visitor: &mut V, condition: &Condition<T>)
+    where T : AsRef<[u8]> + Clone, V : Visitor<T> + ?Sized {
+    match *condition {
+        Condition::Fact(ref c) => visitor.visit_fact(c),
+        Condition::Not(ref c) => visitor.visit_condition(c),
+        Condition::And(ref c1, ref c2) | Condition::Or(ref c1, ref c2) => {
+            visitor.visit_condition(c1);
+            visitor.visit_condition(c2);
+        },
+        Condition::Trait(ref name, ref c) => visitor.visit_trait(name, c),
+        Condition::Rule(ref name, ref arguments) => visitor.visit_rule(name, arguments),
+        Condition::Present(ref v) => visitor.visit_value(v),
+        Condition::Equal(ref v1, ref v2) |
+        Condition::LessThan(ref v1, ref v2) |
+        Condition::GreaterThan(ref v1, ref v2) => {
+            visitor.visit_value(v1);
+            visitor.visit_value(v2);
+        },
+        Condition::True | Condition::False => (),
+    }
+}
+
+pub fn visit_fact<T, V>(visitor: &mut V, body: &Condition<T>)
+    where T : AsRef<[u8]> + Clone, V : Visitor<T> + ?Sized {
+    visitor.visit_condition(body)
+}
+
+pub fn visit_trait<T, V>(visitor: &mut V, _name: &T, body: &Condition<T>)
+    where T : AsRef<[u8]> + Clone, V : Visitor<T> + ?Sized {
+    visitor.visit_condition(body)
+}
+
+pub fn visit_rule<T, V>(visitor: &mut V, _name: &T, arguments: &[Value<T>])
+    where T : AsRef<[u8]> + Clone, V : Visitor<T> + ?Sized {
+    for argument in arguments {
+        visitor.visit_value(argument);
+    }
+}
+
+pub fn visit_value<T, V>(visitor: &mut V, value: &Value<T>)
+    where T : AsRef<[u8]> + Clone, V : Visitor<T> + ?Sized {
+    match *value {
+        Value::Data(ref v) => visitor.visit_data(v),
+        Value::Binding(ref v) => visitor.visit_binding(v),
+        Value::Attribute(ref v) => visitor.visit_attribute(v),
+        Value::AttributeTxid(ref v) => visitor.visit_attribute_txid(v),
+        Value::Parameter(ref v) => visitor.visit_parameter(v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use Condition::*;
+    use Value;
+    use condition::visit::{self, Visitor};
+
+    /// Collects attributes along with the depth of their fact scope
+    struct Attributes(usize, Vec<(&'static str, usize)>);
+
+    impl Visitor<&'static str> for Attributes {
+        fn visit_fact(&mut self, body: &::Condition<&'static str>) {
+            self.0 += 1;
+            visit::visit_fact(self, body);
+            self.0 -= 1;
+        }
+
+        fn visit_attribute(&mut self, attribute: &&'static str) {
+            self.1.push((attribute, self.0));
+        }
+    }
+
+    #[test]
+    pub fn fact_scopes() {
+        let cond = Present(Value::Attribute("a"))
+                   .and(::Condition::fact(Equal(Value::Attribute("b"), Value::Binding("B"))))
+                   .and(::Condition::not(::Condition::fact(Present(Value::AttributeTxid("c")))));
+        let mut attributes = Attributes(0, vec![]);
+        attributes.visit_condition(&cond);
+        assert_eq!(attributes.1, vec![("a", 0), ("b", 1)]);
+    }
+}
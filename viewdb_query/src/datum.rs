@@ -0,0 +1,306 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Typed values.
+//!
+//! `Datum` can be used instead of raw bytes (`Condition<Datum>`), so that
+//! constants compare according to their type rather than lexically. Its
+//! bytes are an order-preserving encoding (integers are big-endian with
+//! the sign bit flipped, timestamps are big-endian seconds), so a typed
+//! attribute's values have to be recorded in that encoding for execution
+//! to compare them the same way.
+//!
+//! Data written as text (untyped, as the parser produces it) is given a
+//! type by `TypeInference`: the type of the attribute it is compared with,
+//! directly or through a binding.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use condition::{Condition, Value};
+use condition::fold::{self, Fold};
+use condition::visit::{self, Visitor};
+use condition::processing::{Processor, Error, Result};
+use time::Encoding;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    /// Untyped
+    Bytes,
+    String,
+    Integer,
+    /// Seconds since the Unix epoch
+    Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Datum {
+    type_: Type,
+    bytes: Vec<u8>,
+}
+
+impl Datum {
+    pub fn bytes<B : Into<Vec<u8>>>(bytes: B) -> Self {
+        Datum { type_: Type::Bytes, bytes: bytes.into() }
+    }
+
+    pub fn string<S : Into<String>>(string: S) -> Self {
+        Datum { type_: Type::String, bytes: string.into().into_bytes() }
+    }
+
+    pub fn integer(integer: i64) -> Self {
+        Datum { type_: Type::Integer, bytes: Encoding::BigEndian.encode((integer as u64) ^ (1 << 63)) }
+    }
+
+    pub fn timestamp(timestamp: u64) -> Self {
+        Datum { type_: Type::Timestamp, bytes: Encoding::BigEndian.encode(timestamp) }
+    }
+
+    pub fn type_(&self) -> Type {
+        self.type_
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self.type_ {
+            Type::String => ::std::str::from_utf8(&self.bytes).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self.type_ {
+            Type::Integer => Encoding::BigEndian.decode(&self.bytes).map(|n| (n ^ (1 << 63)) as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_timestamp(&self) -> Option<u64> {
+        match self.type_ {
+            Type::Timestamp => Encoding::BigEndian.decode(&self.bytes),
+            _ => None,
+        }
+    }
+
+    /// Datum of the given type written as text (decimal numbers), if it is
+    /// untyped or already of that type
+    pub fn typed(self, type_: Type) -> Option<Self> {
+        if self.type_ == type_ {
+            return Some(self);
+        }
+        if self.type_ != Type::Bytes {
+            return None;
+        }
+        let text = String::from_utf8(self.bytes).ok()?;
+        match type_ {
+            Type::Bytes => Some(Datum::bytes(text)),
+            Type::String => Some(Datum::string(text)),
+            Type::Integer => text.parse().ok().map(Datum::integer),
+            Type::Timestamp => text.parse().ok().map(Datum::timestamp),
+        }
+    }
+}
+
+impl AsRef<[u8]> for Datum {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Data of different types don't compare
+impl PartialOrd for Datum {
+    fn partial_cmp(&self, other: &Datum) -> Option<Ordering> {
+        if self.type_ == other.type_ {
+            Some(self.bytes.cmp(&other.bytes))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> From<&'a str> for Datum {
+    fn from(s: &'a str) -> Self {
+        Datum::bytes(s)
+    }
+}
+
+impl From<Vec<u8>> for Datum {
+    fn from(bytes: Vec<u8>) -> Self {
+        Datum::bytes(bytes)
+    }
+}
+
+/// Attribute definitions
+pub trait AttributeTypes {
+    fn type_of(&self, attribute: &[u8]) -> Option<Type>;
+}
+
+impl AttributeTypes for HashMap<Vec<u8>, Type> {
+    fn type_of(&self, attribute: &[u8]) -> Option<Type> {
+        self.get(attribute).cloned()
+    }
+}
+
+/// Types of the bindings equated to typed attributes
+struct Bindings<'a, A : AttributeTypes + 'a> {
+    types: &'a A,
+    bindings: Vec<(Datum, Type)>,
+}
+
+impl<'a, A : AttributeTypes> Visitor<Datum> for Bindings<'a, A> {
+    fn visit_condition(&mut self, condition: &Condition<Datum>) {
+        if let Condition::Equal(Value::Attribute(ref a), Value::Binding(ref b)) |
+               Condition::Equal(Value::Binding(ref b), Value::Attribute(ref a)) = *condition {
+            if let Some(type_) = self.types.type_of(a.as_ref()) {
+                self.bindings.push((b.clone(), type_));
+            }
+        }
+        visit::visit_condition(self, condition)
+    }
+}
+
+struct Typing<'a, A : AttributeTypes + 'a> {
+    types: &'a A,
+    bindings: Vec<(Datum, Type)>,
+    error: Option<Error<Datum>>,
+}
+
+impl<'a, A : AttributeTypes> Typing<'a, A> {
+    fn type_of(&self, value: &Value<Datum>) -> Option<Type> {
+        match *value {
+            Value::Attribute(ref a) => self.types.type_of(a.as_ref()),
+            Value::Binding(ref b) => self.bindings.iter().find(|&(binding, _)| binding == b).map(|&(_, t)| t),
+            _ => None,
+        }
+    }
+
+    /// Gives the data the other value's type
+    fn operands(&mut self, v1: Value<Datum>, v2: Value<Datum>) -> (Value<Datum>, Value<Datum>) {
+        let type_ = |typing: &Self, v1: &Value<Datum>, v2: &Value<Datum>| match (v1, typing.type_of(v2)) {
+            (&Value::Data(_), Some(type_)) => Some(type_),
+            _ => None,
+        };
+        match (type_(self, &v1, &v2), type_(self, &v2, &v1)) {
+            (Some(t), _) => (self.typed(v1, t, &v2), v2),
+            (_, Some(t)) => {
+                let v2 = self.typed(v2, t, &v1);
+                (v1, v2)
+            },
+            _ => (v1, v2),
+        }
+    }
+
+    fn typed(&mut self, data: Value<Datum>, type_: Type, other: &Value<Datum>) -> Value<Datum> {
+        match data {
+            Value::Data(d) => match d.clone().typed(type_) {
+                Some(d) => Value::Data(d),
+                None => {
+                    if self.error.is_none() {
+                        self.error = Some(Error::TypeMismatch(Value::Data(d.clone()), other.clone()));
+                    }
+                    Value::Data(d)
+                },
+            },
+            v => v,
+        }
+    }
+}
+
+impl<'a, A : AttributeTypes> Fold<Datum> for Typing<'a, A> {
+    fn fold_condition(&mut self, condition: Condition<Datum>) -> Condition<Datum> {
+        match condition {
+            Condition::Equal(v1, v2) => {
+                let (v1, v2) = self.operands(v1, v2);
+                Condition::Equal(v1, v2)
+            },
+            Condition::LessThan(v1, v2) => {
+                let (v1, v2) = self.operands(v1, v2);
+                Condition::LessThan(v1, v2)
+            },
+            Condition::GreaterThan(v1, v2) => {
+                let (v1, v2) = self.operands(v1, v2);
+                Condition::GreaterThan(v1, v2)
+            },
+            c => fold::fold_condition(self, c),
+        }
+    }
+}
+
+/// Types the data compared with typed attributes (or bindings equated to
+/// them); data that isn't of (or doesn't parse as) the type is a
+/// `TypeMismatch`
+pub struct TypeInference<A : AttributeTypes>(pub A);
+
+impl<A : AttributeTypes> Processor<Datum> for TypeInference<A> {
+    fn process(&self, condition: Condition<Datum>) -> Result<Datum> {
+        let mut bindings = Bindings { types: &self.0, bindings: vec![] };
+        bindings.visit_condition(&condition);
+        let mut typing = Typing { types: &self.0, bindings: bindings.bindings, error: None };
+        let condition = typing.fold_condition(condition);
+        match typing.error {
+            Some(error) => Err(error),
+            None => Ok(condition),
+        }
+    }
+
+    fn name(&self) -> &str {
+        "TypeInference"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::HashMap;
+
+    use {Condition, Value};
+    use Condition::*;
+    use condition::processing::{Processor, ProcessorExtension, ComparisonSuppression, Error};
+    use datum::{Datum, Type, TypeInference};
+
+    fn attribute(a: &str) -> Value<Datum> {
+        Value::Attribute(a.into())
+    }
+
+    #[test]
+    pub fn datum() {
+        assert!(Datum::integer(-1) < Datum::integer(0));
+        assert!(Datum::integer(9) < Datum::integer(10));
+        assert_eq!(Datum::integer(-42).as_integer(), Some(-42));
+        assert_eq!(Datum::from("10").typed(Type::Timestamp), Some(Datum::timestamp(10)));
+        assert_eq!(Datum::integer(1).partial_cmp(&Datum::string("1")), None);
+
+        let suppression = ComparisonSuppression;
+        assert_eq!(suppression.process(LessThan(Value::Data(Datum::integer(9)), Value::Data(Datum::integer(10)))),
+                   Ok(True));
+        assert_eq!(suppression.process(LessThan(Value::Data(Datum::from("9")), Value::Data(Datum::from("10")))),
+                   Ok(False));
+        assert_eq!(suppression.process(LessThan(Value::Data(Datum::integer(1)), Value::Data(Datum::string("1")))),
+                   Err(Error::TypeMismatch(Value::Data(Datum::integer(1)), Value::Data(Datum::string("1")))));
+        assert_eq!(suppression.process(Equal(Value::Data(Datum::integer(1)), Value::Data(Datum::string("1")))),
+                   Err(Error::TypeMismatch(Value::Data(Datum::integer(1)), Value::Data(Datum::string("1")))));
+        assert_eq!(suppression.process(Equal(Value::Data(Datum::integer(1)), Value::Data(Datum::integer(2)))), Ok(False));
+        assert_eq!(Datum::timestamp(10).as_timestamp(), Some(10));
+    }
+
+    #[test]
+    pub fn inference() {
+        let mut types = HashMap::new();
+        types.insert(b"#age".to_vec(), Type::Integer);
+        let inference = TypeInference(types);
+        let cond = Condition::fact(Equal(attribute("#age"), Value::Binding("Age".into()))
+                                   .and(GreaterThan(Value::Binding("Age".into()), Value::Data("18".into())))
+                                   .and(Equal(attribute("#name"), Value::Data("Bob".into()))));
+        let expected = Condition::fact(Equal(attribute("#age"), Value::Binding("Age".into()))
+                                       .and(GreaterThan(Value::Binding("Age".into()), Value::Data(Datum::integer(18))))
+                                       .and(Equal(attribute("#name"), Value::Data("Bob".into()))));
+        assert_eq!(inference.process(cond), Ok(expected));
+        assert_eq!(inference.process(LessThan(Value::Data("9".into()), attribute("#age")))
+                            .after_that(ComparisonSuppression),
+                   Ok(LessThan(Value::Data(Datum::integer(9)), attribute("#age"))));
+        assert_eq!(inference.process(Equal(attribute("#age"), Value::Data("old".into()))),
+                   Err(Error::TypeMismatch(Value::Data("old".into()), attribute("#age"))));
+    }
+}
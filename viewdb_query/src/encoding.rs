@@ -0,0 +1,573 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Binary encoding of queries (and result rows), so that they can be stored
+//! in ViewDB.
+//!
+//! Every node is a tag byte followed by its operands; byte strings are
+//! prefixed with their length (4 bytes, big endian), and so are lists.
+
+use condition::{Condition, Value};
+use query::{Query, Projection, Aggregate, Rule, Direction, Kind};
+use view::View;
+use time::{Bucket, Encoding, Window, Range};
+use sequence::{Sequence, Order};
+use execution::Row;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    UnexpectedEnd,
+    UnknownTag(u8),
+}
+
+pub trait Encode {
+    fn encode(&self, buf: &mut Vec<u8>);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        self.encode(&mut buf);
+        buf
+    }
+}
+
+pub trait Decode : Sized {
+    fn decode(buf: &mut &[u8]) -> Result<Self, Error>;
+
+    /// Decodes a value that takes up the whole of `bytes`
+    fn from_bytes(mut bytes: &[u8]) -> Result<Self, Error> {
+        let value = Self::decode(&mut bytes)?;
+        if bytes.is_empty() {
+            Ok(value)
+        } else {
+            Err(Error::UnknownTag(bytes[0]))
+        }
+    }
+}
+
+fn encode_len(len: usize, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(len as u32).to_be_bytes());
+}
+
+fn encode_bytes(bytes: &[u8], buf: &mut Vec<u8>) {
+    encode_len(bytes.len(), buf);
+    buf.extend_from_slice(bytes);
+}
+
+fn decode_u8(buf: &mut &[u8]) -> Result<u8, Error> {
+    match buf.split_first() {
+        Some((&b, rest)) => {
+            *buf = rest;
+            Ok(b)
+        },
+        None => Err(Error::UnexpectedEnd),
+    }
+}
+
+fn decode_len(buf: &mut &[u8]) -> Result<usize, Error> {
+    let mut len = 0usize;
+    for _ in 0..4 {
+        len = (len << 8) | decode_u8(buf)? as usize;
+    }
+    Ok(len)
+}
+
+fn decode_bytes(buf: &mut &[u8]) -> Result<Vec<u8>, Error> {
+    let len = decode_len(buf)?;
+    if buf.len() < len {
+        return Err(Error::UnexpectedEnd);
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(bytes.to_vec())
+}
+
+const DATA: u8 = 0x00;
+const BINDING: u8 = 0x01;
+const ATTRIBUTE: u8 = 0x02;
+const ATTRIBUTE_TXID: u8 = 0x03;
+const PARAMETER: u8 = 0x04;
+
+impl<T : AsRef<[u8]> + Clone> Encode for Value<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let (tag, bytes) = match *self {
+            Value::Data(ref v) => (DATA, v),
+            Value::Binding(ref v) => (BINDING, v),
+            Value::Attribute(ref v) => (ATTRIBUTE, v),
+            Value::AttributeTxid(ref v) => (ATTRIBUTE_TXID, v),
+            Value::Parameter(ref v) => (PARAMETER, v),
+        };
+        buf.push(tag);
+        encode_bytes(bytes.as_ref(), buf);
+    }
+}
+
+impl Decode for Value<Vec<u8>> {
+    fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+        let tag = decode_u8(buf)?;
+        let bytes = decode_bytes(buf)?;
+        match tag {
+            DATA => Ok(Value::Data(bytes)),
+            BINDING => Ok(Value::Binding(bytes)),
+            ATTRIBUTE => Ok(Value::Attribute(bytes)),
+            ATTRIBUTE_TXID => Ok(Value::AttributeTxid(bytes)),
+            PARAMETER => Ok(Value::Parameter(bytes)),
+            tag => Err(Error::UnknownTag(tag)),
+        }
+    }
+}
+
+const FACT: u8 = 0x01;
+const NOT: u8 = 0x02;
+const AND: u8 = 0x03;
+const OR: u8 = 0x04;
+const TRAIT: u8 = 0x05;
+const RULE: u8 = 0x06;
+const PRESENT: u8 = 0x10;
+const EQUAL: u8 = 0x11;
+const LESS_THAN: u8 = 0x12;
+const GREATER_THAN: u8 = 0x13;
+const TRUE: u8 = 0x20;
+const FALSE: u8 = 0x21;
+
+impl<T : AsRef<[u8]> + Clone> Encode for Condition<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match *self {
+            Condition::Fact(ref c) => {
+                buf.push(FACT);
+                c.encode(buf);
+            },
+            Condition::Not(ref c) => {
+                buf.push(NOT);
+                c.encode(buf);
+            },
+            Condition::And(ref c1, ref c2) | Condition::Or(ref c1, ref c2) => {
+                buf.push(if let Condition::And(_, _) = *self { AND } else { OR });
+                c1.encode(buf);
+                c2.encode(buf);
+            },
+            Condition::Trait(ref name, ref c) => {
+                buf.push(TRAIT);
+                encode_bytes(name.as_ref(), buf);
+                c.encode(buf);
+            },
+            Condition::Rule(ref name, ref arguments) => {
+                buf.push(RULE);
+                encode_bytes(name.as_ref(), buf);
+                encode_len(arguments.len(), buf);
+                for argument in arguments {
+                    argument.encode(buf);
+                }
+            },
+            Condition::Present(ref v) => {
+                buf.push(PRESENT);
+                v.encode(buf);
+            },
+            Condition::Equal(ref v1, ref v2) |
+            Condition::LessThan(ref v1, ref v2) |
+            Condition::GreaterThan(ref v1, ref v2) => {
+                buf.push(match *self {
+                    Condition::Equal(_, _) => EQUAL,
+                    Condition::LessThan(_, _) => LESS_THAN,
+                    _ => GREATER_THAN,
+                });
+                v1.encode(buf);
+                v2.encode(buf);
+            },
+            Condition::True => buf.push(TRUE),
+            Condition::False => buf.push(FALSE),
+        }
+    }
+}
+
+impl Decode for Condition<Vec<u8>> {
+    fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+        match decode_u8(buf)? {
+            FACT => Ok(Condition::fact(Condition::decode(buf)?)),
+            NOT => Ok(Condition::not(Condition::decode(buf)?)),
+            AND => {
+                let c1 = Condition::decode(buf)?;
+                Ok(c1.and(Condition::decode(buf)?))
+            },
+            OR => {
+                let c1 = Condition::decode(buf)?;
+                Ok(c1.or(Condition::decode(buf)?))
+            },
+            TRAIT => {
+                let name = decode_bytes(buf)?;
+                Ok(Condition::trait_scope(name, Condition::decode(buf)?))
+            },
+            RULE => {
+                let name = decode_bytes(buf)?;
+                let len = decode_len(buf)?;
+                let mut arguments = vec![];
+                for _ in 0..len {
+                    arguments.push(Value::decode(buf)?);
+                }
+                Ok(Condition::Rule(name, arguments))
+            },
+            PRESENT => Ok(Condition::Present(Value::decode(buf)?)),
+            tag @ EQUAL | tag @ LESS_THAN | tag @ GREATER_THAN => {
+                let v1 = Value::decode(buf)?;
+                let v2 = Value::decode(buf)?;
+                Ok(match tag {
+                    EQUAL => Condition::Equal(v1, v2),
+                    LESS_THAN => Condition::LessThan(v1, v2),
+                    _ => Condition::GreaterThan(v1, v2),
+                })
+            },
+            TRUE => Ok(Condition::True),
+            FALSE => Ok(Condition::False),
+            tag => Err(Error::UnknownTag(tag)),
+        }
+    }
+}
+
+const PROJECT_BINDING: u8 = 0x00;
+const PROJECT_COUNT: u8 = 0x01;
+const PROJECT_MIN: u8 = 0x02;
+const PROJECT_MAX: u8 = 0x03;
+const PROJECT_BUCKET: u8 = 0x04;
+
+const BIG_ENDIAN: u8 = 0x00;
+const DECIMAL: u8 = 0x01;
+
+const ASCENDING: u8 = 0x00;
+const DESCENDING: u8 = 0x01;
+
+const KIND_BYTES: u8 = 0x00;
+const KIND_NUMBER: u8 = 0x01;
+
+const ORDER_TXID: u8 = 0x00;
+const ORDER_TIMESTAMP: u8 = 0x01;
+
+const BUCKET_SECONDS: u8 = 0x00;
+const BUCKET_HOUR: u8 = 0x01;
+const BUCKET_DAY: u8 = 0x02;
+const BUCKET_MONTH: u8 = 0x03;
+
+fn encode_u64(n: u64, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&n.to_be_bytes());
+}
+
+fn decode_u64(buf: &mut &[u8]) -> Result<u64, Error> {
+    let mut n = 0;
+    for _ in 0..8 {
+        n = (n << 8) | u64::from(decode_u8(buf)?);
+    }
+    Ok(n)
+}
+
+fn encode_encoding(encoding: Encoding, buf: &mut Vec<u8>) {
+    buf.push(match encoding {
+        Encoding::BigEndian => BIG_ENDIAN,
+        Encoding::Decimal => DECIMAL,
+    });
+}
+
+fn decode_encoding(buf: &mut &[u8]) -> Result<Encoding, Error> {
+    match decode_u8(buf)? {
+        BIG_ENDIAN => Ok(Encoding::BigEndian),
+        DECIMAL => Ok(Encoding::Decimal),
+        tag => Err(Error::UnknownTag(tag)),
+    }
+}
+
+const RANGE_FIXED: u8 = 0x00;
+const RANGE_LAST: u8 = 0x01;
+
+fn encode_bound(bound: Option<u64>, buf: &mut Vec<u8>) {
+    match bound {
+        Some(n) => {
+            buf.push(1);
+            encode_u64(n, buf);
+        },
+        None => buf.push(0),
+    }
+}
+
+fn decode_bound(buf: &mut &[u8]) -> Result<Option<u64>, Error> {
+    match decode_u8(buf)? {
+        0 => Ok(None),
+        1 => Ok(Some(decode_u64(buf)?)),
+        tag => Err(Error::UnknownTag(tag)),
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> Encode for Query<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_len(self.projection.len(), buf);
+        for projection in self.projection.iter() {
+            buf.push(match *projection {
+                Projection::Binding(_) => PROJECT_BINDING,
+                Projection::Aggregate(Aggregate::Count, _) => PROJECT_COUNT,
+                Projection::Aggregate(Aggregate::Min, _) => PROJECT_MIN,
+                Projection::Aggregate(Aggregate::Max, _) => PROJECT_MAX,
+                Projection::Bucket(_, _, _) => PROJECT_BUCKET,
+            });
+            if let Projection::Bucket(bucket, encoding, _) = *projection {
+                match bucket {
+                    Bucket::Seconds(n) => {
+                        buf.push(BUCKET_SECONDS);
+                        encode_u64(n, buf);
+                    },
+                    Bucket::Hour => buf.push(BUCKET_HOUR),
+                    Bucket::Day => buf.push(BUCKET_DAY),
+                    Bucket::Month => buf.push(BUCKET_MONTH),
+                }
+                encode_encoding(encoding, buf);
+            }
+            encode_bytes(projection.binding().as_ref(), buf);
+        }
+        self.condition.encode(buf);
+        encode_len(self.rules.len(), buf);
+        for rule in self.rules.iter() {
+            encode_bytes(rule.name.as_ref(), buf);
+            encode_len(rule.parameters.len(), buf);
+            for parameter in rule.parameters.iter() {
+                encode_bytes(parameter.as_ref(), buf);
+            }
+            rule.body.encode(buf);
+        }
+        encode_len(self.views.len(), buf);
+        for view in self.views.iter() {
+            view.encode(buf);
+        }
+        encode_len(self.windows.len(), buf);
+        for window in self.windows.iter() {
+            encode_bytes(window.binding.as_ref(), buf);
+            encode_encoding(window.encoding, buf);
+            match window.range {
+                Range::Fixed(from, until) => {
+                    buf.push(RANGE_FIXED);
+                    encode_bound(from, buf);
+                    encode_bound(until, buf);
+                },
+                Range::Last(length) => {
+                    buf.push(RANGE_LAST);
+                    encode_u64(length, buf);
+                },
+            }
+        }
+        encode_len(self.sequences.len(), buf);
+        for sequence in self.sequences.iter() {
+            match sequence.order {
+                Order::Txid(ref a) => {
+                    buf.push(ORDER_TXID);
+                    encode_bytes(a.as_ref(), buf);
+                },
+                Order::Timestamp(ref a, encoding) => {
+                    buf.push(ORDER_TIMESTAMP);
+                    encode_bytes(a.as_ref(), buf);
+                    encode_encoding(encoding, buf);
+                },
+            }
+            encode_len(sequence.steps.len(), buf);
+            for (body, key) in sequence.steps.iter() {
+                body.encode(buf);
+                encode_bytes(key.as_ref(), buf);
+            }
+            encode_bound(sequence.within, buf);
+        }
+        encode_len(self.order.len(), buf);
+        for key in self.order.iter() {
+            encode_bytes(key.binding.as_ref(), buf);
+            buf.push(match key.direction {
+                Direction::Ascending => ASCENDING,
+                Direction::Descending => DESCENDING,
+            });
+            match key.kind {
+                Kind::Bytes => buf.push(KIND_BYTES),
+                Kind::Number(encoding) => {
+                    buf.push(KIND_NUMBER);
+                    encode_encoding(encoding, buf);
+                },
+            }
+        }
+        encode_bound(self.limit.map(|limit| limit as u64), buf);
+        encode_u64(self.offset as u64, buf);
+    }
+}
+
+impl Decode for Query<Vec<u8>> {
+    fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+        let mut projection = vec![];
+        for _ in 0..decode_len(buf)? {
+            let tag = decode_u8(buf)?;
+            let bucket = if tag == PROJECT_BUCKET {
+                let bucket = match decode_u8(buf)? {
+                    BUCKET_SECONDS => Bucket::Seconds(decode_u64(buf)?),
+                    BUCKET_HOUR => Bucket::Hour,
+                    BUCKET_DAY => Bucket::Day,
+                    BUCKET_MONTH => Bucket::Month,
+                    tag => return Err(Error::UnknownTag(tag)),
+                };
+                Some((bucket, decode_encoding(buf)?))
+            } else {
+                None
+            };
+            let binding = decode_bytes(buf)?;
+            projection.push(match (tag, bucket) {
+                (_, Some((bucket, encoding))) => Projection::Bucket(bucket, encoding, binding),
+                (PROJECT_BINDING, None) => Projection::Binding(binding),
+                (PROJECT_COUNT, None) => Projection::Aggregate(Aggregate::Count, binding),
+                (PROJECT_MIN, None) => Projection::Aggregate(Aggregate::Min, binding),
+                (PROJECT_MAX, None) => Projection::Aggregate(Aggregate::Max, binding),
+                (tag, None) => return Err(Error::UnknownTag(tag)),
+            });
+        }
+        let mut query = Query::new(Condition::decode(buf)?);
+        query.projection = projection;
+        for _ in 0..decode_len(buf)? {
+            let name = decode_bytes(buf)?;
+            let mut parameters = vec![];
+            for _ in 0..decode_len(buf)? {
+                parameters.push(decode_bytes(buf)?);
+            }
+            query.rules.push(Rule::new(name, parameters, Condition::decode(buf)?));
+        }
+        for _ in 0..decode_len(buf)? {
+            query.views.push(View::decode(buf)?);
+        }
+        for _ in 0..decode_len(buf)? {
+            let binding = decode_bytes(buf)?;
+            let encoding = decode_encoding(buf)?;
+            let window = match decode_u8(buf)? {
+                RANGE_FIXED => {
+                    let from = decode_bound(buf)?;
+                    Window::new(binding, encoding, from, decode_bound(buf)?)
+                },
+                RANGE_LAST => Window::last(binding, encoding, decode_u64(buf)?),
+                tag => return Err(Error::UnknownTag(tag)),
+            };
+            query.windows.push(window);
+        }
+        for _ in 0..decode_len(buf)? {
+            let order = match decode_u8(buf)? {
+                ORDER_TXID => Order::Txid(decode_bytes(buf)?),
+                ORDER_TIMESTAMP => {
+                    let attribute = decode_bytes(buf)?;
+                    Order::Timestamp(attribute, decode_encoding(buf)?)
+                },
+                tag => return Err(Error::UnknownTag(tag)),
+            };
+            let mut sequence = Sequence::new(order);
+            for _ in 0..decode_len(buf)? {
+                let body = Condition::decode(buf)?;
+                sequence.steps.push((body, decode_bytes(buf)?));
+            }
+            sequence.within = decode_bound(buf)?;
+            // the steps are already part of the decoded condition
+            query.sequences.push(sequence);
+        }
+        for _ in 0..decode_len(buf)? {
+            let binding = decode_bytes(buf)?;
+            let direction = match decode_u8(buf)? {
+                ASCENDING => Direction::Ascending,
+                DESCENDING => Direction::Descending,
+                tag => return Err(Error::UnknownTag(tag)),
+            };
+            let kind = match decode_u8(buf)? {
+                KIND_BYTES => Kind::Bytes,
+                KIND_NUMBER => Kind::Number(decode_encoding(buf)?),
+                tag => return Err(Error::UnknownTag(tag)),
+            };
+            query = query.order_by(binding, direction, kind);
+        }
+        query.limit = decode_bound(buf)?.map(|limit| limit as usize);
+        query.offset = decode_u64(buf)? as usize;
+        Ok(query)
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> Encode for View<T> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_bytes(self.name.as_ref(), buf);
+        encode_bytes(&self.version, buf);
+        self.query.encode(buf);
+    }
+}
+
+impl Decode for View<Vec<u8>> {
+    fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+        let name = decode_bytes(buf)?;
+        let version = decode_bytes(buf)?;
+        Ok(View { name, version, query: Query::decode(buf)? })
+    }
+}
+
+impl Encode for Row {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_len(self.len(), buf);
+        for column in self.iter() {
+            encode_bytes(column, buf);
+        }
+    }
+}
+
+impl Decode for Row {
+    fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+        let mut row = vec![];
+        for _ in 0..decode_len(buf)? {
+            row.push(decode_bytes(buf)?);
+        }
+        Ok(row)
+    }
+}
+
+impl Encode for Vec<Row> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        encode_len(self.len(), buf);
+        for row in self.iter() {
+            row.encode(buf);
+        }
+    }
+}
+
+impl Decode for Vec<Row> {
+    fn decode(buf: &mut &[u8]) -> Result<Self, Error> {
+        let mut rows = vec![];
+        for _ in 0..decode_len(buf)? {
+            rows.push(Row::decode(buf)?);
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query, Rule};
+    use query::{Aggregate, Direction, Kind};
+    use time::{Bucket, Encoding, Window};
+    use sequence::{Sequence, Order};
+    use encoding::{Encode, Decode, Error};
+
+    fn bytes(c: Condition<&'static str>) -> Condition<Vec<u8>> {
+        Condition::decode(&mut c.to_bytes().as_slice()).unwrap()
+    }
+
+    #[test]
+    pub fn round_trip() {
+        let cond = Condition::fact(Condition::Equal(Value::Attribute("#object"), Value::Binding("Person"))
+                                   .and(!Condition::LessThan(Value::AttributeTxid("#value"), Value::Data("1")))
+                                   .or(Condition::rule("linked", vec![Value::Binding("Person"), Value::Data("a")])));
+        let query = Query::new(cond)
+                    .select("Person")
+                    .select_aggregate(Aggregate::Count, "Person")
+                    .select_bucket(Bucket::Seconds(60), Encoding::Decimal, "Timestamp")
+                    .window(Window::new("Timestamp", Encoding::Decimal, Some(1), None))
+                    .window(Window::last("Timestamp", Encoding::Decimal, 3600))
+                    .sequence(Sequence::new(Order::Txid("#factType")).then(Condition::True, "T").within(60))
+                    .order_by("Person", Direction::Descending, Kind::Number(Encoding::BigEndian))
+                    .limit(10)
+                    .offset(5)
+                    .rule(Rule::new("linked", vec!["A", "B"], Condition::True));
+        let bytes_query = Query::from_bytes(&query.to_bytes()).unwrap();
+        assert_eq!(bytes_query.condition, bytes(query.condition.clone()));
+        assert_eq!(bytes_query.to_bytes(), query.to_bytes());
+        assert_eq!(Query::from_bytes(&query.to_bytes()[1..]), Err(Error::UnexpectedEnd));
+    }
+}
@@ -0,0 +1,295 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! `EXPLAIN` and `EXPLAIN ANALYZE`.
+//!
+//! The physical plan mirrors what `Executor::eval` does with a condition:
+//! conjunctions are joins (in the order the planner picked), fact scopes
+//! are index scans feeding their body, and everything else is a filter.
+//! When analyzed, every operator is timed and its output rows are counted
+//! over all of its invocations (`loops`); timings include the operator's
+//! inputs. In ViewDB, explanations are rendered as text (see `EXPLAIN` in
+//! the engine).
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use condition::{Condition, Value};
+use condition::analysis;
+use condition::processing::Rewrite;
+use query::{Query, Projection};
+use super::{Executor, FactSource, Error, Row};
+use super::rules::Relations;
+use super::plan::{self, Index};
+
+/// How a fact scope's facts are found
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access<T : AsRef<[u8]> + Clone> {
+    Lookup(T, Value<T>),
+    Present(T),
+    Scan,
+}
+
+impl<'c, T : AsRef<[u8]> + Clone> From<Index<'c, T>> for Access<T> {
+    fn from(index: Index<'c, T>) -> Self {
+        match index {
+            Index::Lookup(a, v) => Access::Lookup(a.clone(), v.clone()),
+            Index::Present(a) => Access::Present(a.clone()),
+            Index::Scan => Access::Scan,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node<T : AsRef<[u8]> + Clone> {
+    /// Projection (and grouping) of the solutions
+    Project(Vec<Projection<T>>),
+    /// Solutions in a window of a timestamp binding
+    Window(T),
+    /// Solutions matching a sequence's order
+    Sequence(usize),
+    /// Inputs evaluated one after the other
+    Join,
+    Union,
+    AntiJoin,
+    /// Fact scope, with the estimated number of facts (with statistics)
+    Scan(Access<T>, Option<f64>),
+    Rule(T),
+    Filter(Condition<T>),
+}
+
+/// Measurements of an operator
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Actual {
+    pub loops: usize,
+    pub rows: usize,
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operator<T : AsRef<[u8]> + Clone> {
+    pub node: Node<T>,
+    pub inputs: Vec<Operator<T>>,
+    /// Only with `EXPLAIN ANALYZE`
+    pub actual: Option<Actual>,
+}
+
+impl<T : AsRef<[u8]> + Clone> Operator<T> {
+    fn new(node: Node<T>, inputs: Vec<Operator<T>>) -> Self {
+        Operator { node, inputs, actual: None }
+    }
+}
+
+#[derive(Debug)]
+pub struct Explanation<T : AsRef<[u8]> + Clone> {
+    /// Condition after every processing pass that changed it (see
+    /// `Pipeline::traced`)
+    pub passes: Vec<Rewrite<T>>,
+    pub plan: Operator<T>,
+    /// Only with `EXPLAIN ANALYZE`
+    pub rows: Option<Vec<Row>>,
+}
+
+impl<T : AsRef<[u8]> + Clone> Explanation<T> {
+    pub fn passes(mut self, passes: Vec<Rewrite<T>>) -> Self {
+        self.passes = passes;
+        self
+    }
+}
+
+/// Measurements of the evaluated conditions, by address
+#[derive(Debug, Default)]
+pub(crate) struct Profile(RefCell<HashMap<usize, Actual>>);
+
+impl Profile {
+    pub(crate) fn record<T : AsRef<[u8]> + Clone>(&self, condition: &Condition<T>, rows: usize, time: Duration) {
+        let mut actuals = self.0.borrow_mut();
+        let actual = actuals.entry(condition as *const Condition<T> as usize).or_default();
+        actual.loops += 1;
+        actual.rows += rows;
+        actual.time += time;
+    }
+
+    fn get<T : AsRef<[u8]> + Clone>(&self, condition: &Condition<T>) -> Option<Actual> {
+        self.0.borrow().get(&(condition as *const Condition<T> as usize)).cloned()
+    }
+}
+
+fn operator<'a, S, T>(executor: &Executor<'a, S>, condition: &Condition<T>, bound: &mut Vec<T>, relations: &Relations<T>)
+                      -> Operator<T>
+    where S : FactSource + 'a, T : AsRef<[u8]> + Clone {
+    let mut operator = match *condition {
+        Condition::And(_, _) => {
+            let parts = executor.order(condition, bound.clone(), relations);
+            let inputs = parts.into_iter().map(|part| operator(executor, part, bound, relations)).collect();
+            Operator::new(Node::Join, inputs)
+        },
+        Condition::Or(ref c1, ref c2) => {
+            let mut bound2 = bound.clone();
+            let inputs = vec![operator(executor, c1, bound, relations), operator(executor, c2, &mut bound2, relations)];
+            Operator::new(Node::Union, inputs)
+        },
+        Condition::Not(ref c) => Operator::new(Node::AntiJoin, vec![operator(executor, c, &mut bound.clone(), relations)]),
+        Condition::Fact(ref body) => {
+            let index = plan::index(body, |b| analysis::contains(bound, b), executor.statistics);
+            let estimate = executor.statistics.map(|statistics| index.cost(statistics));
+            let node = Node::Scan(index.into(), estimate);
+            Operator::new(node, vec![operator(executor, body, bound, relations)])
+        },
+        Condition::Rule(ref name, _) => Operator::new(Node::Rule(name.clone()), vec![]),
+        _ => Operator::new(Node::Filter(condition.clone()), vec![]),
+    };
+    for b in analysis::bound(condition) {
+        if !analysis::contains(bound, &b) {
+            bound.push(b);
+        }
+    }
+    if let Some(ref profile) = executor.profile {
+        operator.actual = Some(profile.get(condition).unwrap_or_default());
+    }
+    operator
+}
+
+impl<'a, S : FactSource + 'a> Executor<'a, S> {
+    /// Physical plan of the query (which is expected to be processed)
+    pub fn explain<T : AsRef<[u8]> + Clone>(&self, query: &Query<T>) -> Result<Explanation<T>, Error<T>> {
+        let relations = self.relations(query)?;
+        let mut plan = operator(self, &query.condition, &mut vec![], &relations);
+        for (i, _) in query.sequences.iter().enumerate() {
+            plan = Operator::new(Node::Sequence(i), vec![plan]);
+        }
+        for window in query.windows.iter() {
+            plan = Operator::new(Node::Window(window.binding.clone()), vec![plan]);
+        }
+        let plan = Operator::new(Node::Project(query.projection.clone()), vec![plan]);
+        Ok(Explanation { passes: vec![], plan, rows: None })
+    }
+
+    /// Executes the query, measuring every operator of its plan
+    pub fn explain_analyze<T : AsRef<[u8]> + Clone>(&self, query: &Query<T>) -> Result<Explanation<T>, Error<T>> {
+        let executor = Executor { source: self.source, statistics: self.statistics, profile: Some(Profile::default()),
+                                  now: self.now };
+        let rows = executor.execute(query)?;
+        let mut explanation = executor.explain(query)?;
+        explanation.rows = Some(rows);
+        Ok(explanation)
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> fmt::Display for Access<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Access::Lookup(ref a, ref v) => write!(f, "lookup {} = {}", Value::Attribute(a.clone()), v),
+            Access::Present(ref a) => write!(f, "present {}", Value::Attribute(a.clone())),
+            Access::Scan => write!(f, "all facts"),
+        }
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> Operator<T> {
+    fn write(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(f, "{:1$}", "", depth * 2)?;
+        match self.node {
+            Node::Project(ref projection) => {
+                write!(f, "Project")?;
+                for p in projection {
+                    write!(f, " {}", Value::Binding(p.binding().clone()))?;
+                }
+            },
+            Node::Window(ref b) => write!(f, "Window {}", Value::Binding(b.clone()))?,
+            Node::Sequence(i) => write!(f, "Sequence #{}", i)?,
+            Node::Join => write!(f, "Join")?,
+            Node::Union => write!(f, "Union")?,
+            Node::AntiJoin => write!(f, "Anti-join")?,
+            Node::Scan(ref access, None) => write!(f, "Fact scan ({})", access)?,
+            Node::Scan(ref access, Some(estimate)) => write!(f, "Fact scan ({}, estimate={})", access, estimate)?,
+            Node::Rule(ref name) => write!(f, "{}", Condition::Rule(name.clone(), vec![]))?,
+            Node::Filter(ref condition) => write!(f, "Filter {}", condition)?,
+        }
+        if let Some(actual) = self.actual {
+            write!(f, " (loops={} rows={} time={:?})", actual.loops, actual.rows, actual.time)?;
+        }
+        writeln!(f)?;
+        for input in self.inputs.iter() {
+            input.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> fmt::Display for Explanation<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for pass in self.passes.iter() {
+            writeln!(f, "Pass {} {}: {}", pass.iteration, pass.processor, pass.after)?;
+        }
+        self.plan.write(f, 0)?;
+        if let Some(ref rows) = self.rows {
+            writeln!(f, "Rows: {}", rows.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query};
+    use Condition::Equal;
+    use query::Rule;
+    use condition::processing::{Pipeline, ImplicitFact};
+    use execution::Executor;
+    use execution::explain::{Node, Access};
+    use execution::plan::Statistics;
+    use execution::tests::{people, fact_type};
+
+    #[test]
+    pub fn explain() {
+        let facts = people();
+        let statistics = Statistics::collect(&facts);
+        let cond = fact_type("NameChanged")
+                   .and(Equal(Value::Attribute("#object"), Value::Data("bob")))
+                   .and(Equal(Value::Attribute("#value"), Value::Binding("Name")));
+        let outcome = Pipeline::new().with(ImplicitFact).traced().run(cond);
+        let query = Query::new(outcome.condition.unwrap()).select("Name");
+        let executor = Executor::with_statistics(&facts, &statistics);
+
+        let explanation = executor.explain(&query).unwrap().passes(outcome.trace);
+        assert_eq!(explanation.passes.len(), 1);
+        let scan = &explanation.plan.inputs[0];
+        assert_eq!(scan.node, Node::Scan(Access::Lookup("#object", Value::Data("bob")), Some(2.0)));
+        assert_eq!(scan.actual, None);
+        assert!(explanation.to_string().contains("Fact scan (lookup <#object> = \"bob\", estimate=2)"));
+
+        let explanation = executor.explain_analyze(&query).unwrap();
+        assert_eq!(explanation.rows, Some(vec![vec![b"Bob".to_vec()]]));
+        let scan = &explanation.plan.inputs[0];
+        assert_eq!(scan.actual.map(|a| (a.loops, a.rows)), Some((1, 1)));
+        assert_eq!(scan.inputs[0].actual.map(|a| (a.loops, a.rows)), Some((2, 1)));
+        assert!(explanation.to_string().contains("Rows: 1"));
+    }
+
+    #[test]
+    pub fn rule_order() {
+        let facts = people();
+        let statistics = Statistics::collect(&facts);
+        let values = Rule::new("Values", vec!["V"], Condition::fact(Equal(Value::Attribute("#value"), Value::Binding("V"))));
+        // the rule has 4 tuples, "bob" has 2 facts: the scan goes first
+        let cond = Condition::fact(Equal(Value::Attribute("#object"), Value::Data("bob"))
+                                   .and(Equal(Value::Attribute("#value"), Value::Binding("Name"))))
+                   .and(Condition::Rule("Values", vec![Value::Binding("Name")]));
+        let query = Query::new(cond).rule(values).select("Name");
+        let executor = Executor::with_statistics(&facts, &statistics);
+        let explanation = executor.explain_analyze(&query).unwrap();
+        assert_eq!(explanation.rows, Some(vec![vec![b"Bob".to_vec()]]));
+        let join = &explanation.plan.inputs[0];
+        assert_eq!(join.node, Node::Join);
+        assert_eq!(join.inputs[0].node, Node::Scan(Access::Lookup("#object", Value::Data("bob")), Some(2.0)));
+        assert_eq!(join.inputs[1].node, Node::Rule("Values"));
+        assert_eq!(join.inputs[1].actual.map(|a| (a.loops, a.rows)), Some((1, 1)));
+    }
+}
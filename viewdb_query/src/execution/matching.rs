@@ -0,0 +1,85 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Client-side evaluation against a single fact.
+//!
+//! The fact's attributes are a one-fact `FactSource`, so a condition
+//! matches exactly when the executor would find it for that fact: every
+//! `Condition::Fact` scope (and the condition itself) ranges over it.
+
+use condition::Condition;
+use query::Query;
+use super::{Executor, FactSource, Attachment, Bindings, Error, rules};
+
+/// The one fact (with an empty identifier)
+struct Attributes<'f, A : AsRef<[u8]> + 'f>(&'f [(A, Attachment)]);
+
+impl<'f, A : AsRef<[u8]>> FactSource for Attributes<'f, A> {
+    fn facts(&self) -> Vec<Vec<u8>> {
+        vec![vec![]]
+    }
+
+    fn facts_with(&self, attribute: &[u8], value: Option<&[u8]>) -> Vec<Vec<u8>> {
+        if self.0.iter().any(|(a, attachment)| a.as_ref() == attribute &&
+                                                        value.map(|v| attachment.value == v).unwrap_or(true)) {
+            self.facts()
+        } else {
+            vec![]
+        }
+    }
+
+    fn attachments(&self, _fact: &[u8], attribute: &[u8]) -> Vec<Attachment> {
+        let mut result: Vec<_> = self.0.iter().filter(|&(a, _)| a.as_ref() == attribute)
+                                     .map(|(_, attachment)| attachment.clone()).collect();
+        result.sort_by(|a1, a2| a1.txid.cmp(&a2.txid));
+        result
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> Condition<T> {
+    /// Bindings of the first solution of the (processed) condition for a
+    /// fact with the given attributes, if it matches
+    pub fn matches<A : AsRef<[u8]>>(&self, fact_attributes: &[(A, Attachment)])
+                                    -> Result<Option<Bindings<T>>, Error<T>> {
+        let source = Attributes(fact_attributes);
+        let executor = Executor::new(&source);
+        let query = Query::new(self.clone());
+        let relations = rules::evaluate(&executor, &query)?;
+        Ok(executor.eval(&query.condition, Some(&[]), vec![vec![]], &relations)?.into_iter().next())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value};
+    use Condition::*;
+    use execution::{Attachment, Error};
+    use execution::tests::fact_type;
+
+    fn attribute(attribute: &'static str, value: &str, txid: u8) -> (&'static str, Attachment) {
+        (attribute, Attachment { value: value.as_bytes().to_vec(), txid: vec![txid] })
+    }
+
+    #[test]
+    pub fn matches() {
+        let fact = [attribute("#factType", "NameChanged", 0), attribute("#object", "alice", 1),
+                    attribute("#value", "Alicia", 3), attribute("#value", "Alice", 2)];
+        let cond = fact_type("NameChanged").and(Present(Value::Attribute("#object")))
+                   .and(Equal(Value::Attribute("#object"), Value::Binding("Person")));
+        assert_eq!(cond.matches(&fact), Ok(Some(vec![("Person", b"alice".to_vec())])));
+        assert_eq!(Condition::fact(cond).matches(&fact), Ok(Some(vec![("Person", b"alice".to_vec())])));
+
+        let cond = Equal(Value::Attribute("#value"), Value::Binding("Name"))
+                   .and(Equal(Value::AttributeTxid("#value"), Value::Binding("Txid")))
+                   .and(LessThan(Value::Binding("Name"), Value::Data("Alicia")));
+        assert_eq!(cond.matches(&fact), Ok(Some(vec![("Name", b"Alice".to_vec()), ("Txid", vec![2])])));
+
+        assert_eq!(fact_type("EmailChanged").or(Present(Value::Attribute("#email"))).matches(&fact), Ok(None));
+        assert_eq!(Condition::not(fact_type("EmailChanged")).matches(&fact), Ok(Some(vec![])));
+        assert_eq!(Condition::trait_scope("Named", True).matches(&fact), Err(Error::UnexpandedTrait("Named")));
+    }
+}
@@ -0,0 +1,550 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Query execution.
+//!
+//! The executor evaluates a processed condition (traits expanded, fact
+//! scopes explicit) against a `FactSource`. Every `Condition::Fact` scope
+//! ranges over facts independently; scopes are joined through the bindings
+//! they share. Bindings are passed sideways: once a scope has bound `?P`,
+//! the following scopes look up their candidate facts by `?P`'s value.
+//!
+//! Negation is stratified: within a conjunction, `Not` (and so `NOT EXISTS`)
+//! is evaluated only after every positive conjunct next to it, so the
+//! bindings it shares with them are already bound and it acts as an
+//! anti-join. Bindings that are still unbound at that point are local to
+//! the negation.
+//!
+//! With `Statistics` (see `plan`), index lookups and the order of binding
+//! conjuncts are chosen by estimated cost instead.
+
+use std::collections::HashSet;
+use std::time::Instant;
+
+use condition::{Condition, Value};
+use condition::analysis::{self, Problem};
+use query::{Query, Projection, Aggregate};
+use time::{self, Encoding};
+use sequence;
+
+mod rules;
+use self::rules::Relations;
+mod order;
+pub use self::order::{Cursor, Page};
+pub mod plan;
+use self::plan::{Index, Statistics};
+pub mod explain;
+mod matching;
+use self::explain::Profile;
+
+/// A value attached to a fact under some attribute
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Attachment {
+    pub value: Vec<u8>,
+    pub txid: Vec<u8>,
+}
+
+/// Access to recorded facts
+pub trait FactSource {
+    /// Identifiers of all recorded facts
+    fn facts(&self) -> Vec<Vec<u8>>;
+    /// Identifiers of facts that have `attribute` attached (with the given
+    /// value, if any)
+    fn facts_with(&self, attribute: &[u8], value: Option<&[u8]>) -> Vec<Vec<u8>>;
+    /// Values attached to the fact under `attribute`, in TXID order
+    fn attachments(&self, fact: &[u8], attribute: &[u8]) -> Vec<Attachment>;
+    /// Distinct values attached under `attribute`, in bytewise order, if the
+    /// source keeps them indexed (ordered results are then found value by
+    /// value instead of sorted)
+    fn values(&self, _attribute: &[u8]) -> Option<Vec<Vec<u8>>> {
+        None
+    }
+}
+
+/// Result row, one value per projected binding
+pub type Row = Vec<Vec<u8>>;
+
+/// Values of bound bindings
+pub type Bindings<T> = Vec<(T, Vec<u8>)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error<T : AsRef<[u8]> + Clone> {
+    /// Query failed binding analysis
+    Invalid(Vec<Problem<T>>),
+    /// Condition still contains a trait scope
+    UnexpandedTrait(T),
+    /// Attribute is referenced outside of a fact scope
+    NoFactScope(T),
+    /// Condition refers to a rule that is not defined
+    UnknownRule(T),
+    /// Rule is referred to with the wrong number of arguments
+    RuleArity(T),
+    /// Rule depends on itself through a negation
+    NegatedRecursion(T),
+    /// Results are ordered by a binding that is not projected
+    NotProjected(T),
+    /// Parameter that hasn't been supplied a value
+    UnboundParameter(T),
+    /// Cursor that isn't a position in a result
+    InvalidCursor,
+    /// Sequence pattern that can't be matched as given
+    Sequence(sequence::Error<T>),
+}
+
+pub(crate) type Env<T> = Bindings<T>;
+
+pub(crate) fn lookup<'e, T : AsRef<[u8]>>(env: &'e Env<T>, binding: &T) -> Option<&'e [u8]> {
+    env.iter().find(|&(b, _)| b.as_ref() == binding.as_ref()).map(|(_, v)| v.as_slice())
+}
+
+/// Bindings of the environment, in an order that doesn't depend on the
+/// order they were bound in
+fn canonical<T : AsRef<[u8]>>(env: &Env<T>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut bindings: Vec<_> = env.iter().map(|(b, v)| (b.as_ref().to_vec(), v.clone())).collect();
+    bindings.sort();
+    bindings
+}
+
+pub(crate) fn encode_count(count: usize) -> Vec<u8> {
+    Encoding::BigEndian.encode(count as u64)
+}
+
+pub struct Executor<'a, S : FactSource + 'a> {
+    source: &'a S,
+    statistics: Option<&'a Statistics>,
+    /// Measurements for `EXPLAIN ANALYZE`
+    profile: Option<Profile>,
+    /// Time sliding windows are evaluated at (the current time if `None`)
+    now: Option<u64>,
+}
+
+impl<'a, S : FactSource + 'a> Executor<'a, S> {
+    pub fn new(source: &'a S) -> Self {
+        Executor { source, statistics: None, profile: None, now: None }
+    }
+
+    /// Executor that plans with the statistics of the source's facts
+    pub fn with_statistics(source: &'a S, statistics: &'a Statistics) -> Self {
+        Executor { source, statistics: Some(statistics), profile: None, now: None }
+    }
+
+    /// Evaluates sliding windows at `now` instead of the current time
+    pub fn at(mut self, now: u64) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    pub fn execute<T : AsRef<[u8]> + Clone>(&self, query: &Query<T>) -> Result<Vec<Row>, Error<T>> {
+        Ok(self.page(query, None)?.rows)
+    }
+
+    /// Page of the results, starting after the cursor (or at the query's
+    /// offset)
+    pub fn page<T : AsRef<[u8]> + Clone>(&self, query: &Query<T>, cursor: Option<&Cursor>) -> Result<Page, Error<T>> {
+        order::page(self, query, cursor)
+    }
+
+    /// Solutions of the query's condition, optionally with one of its fact
+    /// scopes (`Condition::Fact` node) ranging over a single fact
+    pub(crate) fn solutions<'q, T>(&self, query: &'q Query<T>, pinned: Option<(&'q Condition<T>, &[u8])>)
+                                   -> Result<Vec<Env<T>>, Error<T>> where T : AsRef<[u8]> + Clone {
+        let mut relations = self.relations(query)?;
+        if let Some((scope, fact)) = pinned {
+            relations.pin(scope, fact);
+        }
+        self.solve(query, vec![vec![]], &relations)
+    }
+
+    /// Solutions of the query's condition that extend `env`
+    pub(crate) fn solutions_from<T>(&self, query: &Query<T>, env: Env<T>) -> Result<Vec<Env<T>>, Error<T>>
+        where T : AsRef<[u8]> + Clone {
+        let relations = self.relations(query)?;
+        self.solve(query, vec![env], &relations)
+    }
+
+    /// Validates the query and evaluates its rules
+    pub(crate) fn relations<'q, T : AsRef<[u8]> + Clone>(&self, query: &'q Query<T>) -> Result<Relations<'q, T>, Error<T>> {
+        query.validate().map_err(Error::Invalid)?;
+        for sequence in query.sequences.iter() {
+            sequence.validate().map_err(Error::Sequence)?;
+        }
+        rules::evaluate(self, query)
+    }
+
+    /// Solutions extending `envs` that fall in the query's windows and match
+    /// its sequences
+    fn solve<T : AsRef<[u8]> + Clone>(&self, query: &Query<T>, envs: Vec<Env<T>>, relations: &Relations<T>)
+                                      -> Result<Vec<Env<T>>, Error<T>> {
+        let envs = self.eval(&query.condition, None, envs, relations)?;
+        let now = self.now.unwrap_or_else(time::now);
+        Ok(envs.into_iter()
+               .filter(|env| query.windows.iter().all(|w| lookup(env, &w.binding).map(|v| w.contains(v, now)).unwrap_or(false)))
+               .filter(|env| query.sequences.iter().all(|s| s.matches(env)))
+               .collect())
+    }
+
+    fn values<T : AsRef<[u8]> + Clone>(&self, value: &Value<T>, env: &Env<T>, fact: Option<&[u8]>)
+                                       -> Result<Option<Vec<Vec<u8>>>, Error<T>> {
+        match *value {
+            Value::Data(ref d) => Ok(Some(vec![d.as_ref().to_vec()])),
+            Value::Binding(ref b) => Ok(lookup(env, b).map(|v| vec![v.to_vec()])),
+            Value::Attribute(ref a) => match fact {
+                Some(f) => Ok(Some(self.source.attachments(f, a.as_ref()).into_iter().map(|a| a.value).collect())),
+                None => Err(Error::NoFactScope(a.clone())),
+            },
+            Value::Parameter(ref p) => Err(Error::UnboundParameter(p.clone())),
+            Value::AttributeTxid(ref a) => match fact {
+                Some(f) => Ok(Some(self.source.attachments(f, a.as_ref()).into_iter().map(|a| a.txid).collect())),
+                None => Err(Error::NoFactScope(a.clone())),
+            },
+        }
+    }
+
+    /// Looks the fact scope's facts up through the index the plan picks
+    fn candidates<T : AsRef<[u8]> + Clone>(&self, body: &Condition<T>, env: &Env<T>) -> Vec<Vec<u8>> {
+        match plan::index(body, |b| lookup(env, b).is_some(), self.statistics) {
+            Index::Lookup(a, Value::Data(d)) => self.source.facts_with(a.as_ref(), Some(d.as_ref())),
+            Index::Lookup(a, Value::Binding(b)) => self.source.facts_with(a.as_ref(), lookup(env, b)),
+            Index::Lookup(_, _) => unreachable!(),
+            Index::Present(a) => self.source.facts_with(a.as_ref(), None),
+            Index::Scan => self.source.facts(),
+        }
+    }
+
+    /// Estimated cost of evaluating a binding conjunct after `bound`
+    fn cost<T : AsRef<[u8]> + Clone>(&self, condition: &Condition<T>, bound: &[T], relations: &Relations<T>) -> f64 {
+        match (condition, self.statistics) {
+            (Condition::Fact(body), Some(statistics)) =>
+                plan::index(body, |b| analysis::contains(bound, b), Some(statistics)).cost(statistics),
+            (Condition::Rule(name, _), _) => relations.tuples(condition, name).map(|t| t.len()).unwrap_or(0) as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// Evaluation order of a conjunction's parts, given the bindings bound
+    /// before it (the order `EXPLAIN` shows, too)
+    pub(crate) fn order<'c, T>(&self, conjunction: &'c Condition<T>, bound: Vec<T>, relations: &Relations<T>)
+                               -> Vec<&'c Condition<T>> where T : AsRef<[u8]> + Clone {
+        plan::order(conjunction.conjuncts(), bound, self.statistics, |c, bound| self.cost(c, bound, relations))
+    }
+
+    fn compare<T, F>(&self, v1: &Value<T>, v2: &Value<T>, fact: Option<&[u8]>, envs: Vec<Env<T>>, f: F)
+                     -> Result<Vec<Env<T>>, Error<T>>
+        where T : AsRef<[u8]> + Clone, F : Fn(&[u8], &[u8]) -> bool {
+        let mut result = vec![];
+        for env in envs {
+            match (self.values(v1, &env, fact)?, self.values(v2, &env, fact)?) {
+                (Some(a), Some(b)) => if a.iter().any(|x| b.iter().any(|y| f(x, y))) {
+                    result.push(env)
+                },
+                (None, _) | (_, None) => {
+                    let binding = match (v1, v2) {
+                        (Value::Binding(b), _) if lookup(&env, b).is_none() => b,
+                        (_, Value::Binding(b)) => b,
+                        _ => unreachable!(),
+                    };
+                    return Err(Error::Invalid(vec![Problem::Unbound(binding.clone())]))
+                },
+            }
+        }
+        Ok(result)
+    }
+
+    pub(crate) fn eval<T : AsRef<[u8]> + Clone>(&self, condition: &Condition<T>, fact: Option<&[u8]>, envs: Vec<Env<T>>,
+                                                relations: &Relations<T>) -> Result<Vec<Env<T>>, Error<T>> {
+        match self.profile {
+            Some(ref profile) => {
+                let start = Instant::now();
+                let result = self.operator(condition, fact, envs, relations)?;
+                profile.record(condition, result.len(), start.elapsed());
+                Ok(result)
+            },
+            None => self.operator(condition, fact, envs, relations),
+        }
+    }
+
+    fn operator<T : AsRef<[u8]> + Clone>(&self, condition: &Condition<T>, fact: Option<&[u8]>, envs: Vec<Env<T>>,
+                                         relations: &Relations<T>) -> Result<Vec<Env<T>>, Error<T>> {
+        if envs.is_empty() {
+            return Ok(envs);
+        }
+        match *condition {
+            Condition::True => Ok(envs),
+            Condition::False => Ok(vec![]),
+            Condition::And(_, _) => {
+                let bound = envs[0].iter().map(|(b, _)| b.clone()).collect();
+                let mut envs = envs;
+                for part in self.order(condition, bound, relations) {
+                    envs = self.eval(part, fact, envs, relations)?;
+                }
+                Ok(envs)
+            },
+            Condition::Or(ref c1, ref c2) => {
+                let mut result = self.eval(c1, fact, envs.clone(), relations)?;
+                // a solution of both branches is still one solution
+                let first: HashSet<_> = result.iter().map(canonical).collect();
+                result.extend(self.eval(c2, fact, envs, relations)?.into_iter()
+                                  .filter(|env| !first.contains(&canonical(env))));
+                Ok(result)
+            },
+            Condition::Not(ref c) => {
+                let mut result = vec![];
+                for env in envs {
+                    if self.eval(c, fact, vec![env.clone()], relations)?.is_empty() {
+                        result.push(env);
+                    }
+                }
+                Ok(result)
+            },
+            Condition::Fact(ref c) => {
+                let mut result = vec![];
+                for env in envs {
+                    let candidates = match relations.pinned(condition) {
+                        Some(f) => vec![f.to_vec()],
+                        None => self.candidates(c, &env),
+                    };
+                    for f in candidates {
+                        result.extend(self.eval(c, Some(&f), vec![env.clone()], relations)?);
+                    }
+                }
+                Ok(result)
+            },
+            Condition::Trait(ref name, _) => Err(Error::UnexpandedTrait(name.clone())),
+            Condition::Rule(ref name, ref arguments) => {
+                let tuples = relations.tuples(condition, name).ok_or_else(|| Error::UnknownRule(name.clone()))?;
+                let mut result = vec![];
+                for env in envs {
+                    let mut values = vec![];
+                    for argument in arguments {
+                        values.push(self.values(argument, &env, fact)?);
+                    }
+                    'tuples: for tuple in tuples {
+                        let mut env = env.clone();
+                        for ((argument, values), column) in arguments.iter().zip(values.iter()).zip(tuple) {
+                            match (argument, values) {
+                                (_, Some(values)) => if !values.contains(column) {
+                                    continue 'tuples;
+                                },
+                                (Value::Binding(b), None) => match lookup(&env, b).map(|v| v == column.as_slice()) {
+                                    Some(false) => continue 'tuples,
+                                    Some(true) => (),
+                                    None => env.push((b.clone(), column.clone())),
+                                },
+                                _ => unreachable!(),
+                            }
+                        }
+                        result.push(env);
+                    }
+                }
+                Ok(result)
+            },
+            Condition::Present(ref v) => {
+                let mut result = vec![];
+                for env in envs {
+                    if self.values(v, &env, fact)?.map(|v| !v.is_empty()).unwrap_or(false) {
+                        result.push(env);
+                    }
+                }
+                Ok(result)
+            },
+            Condition::Equal(ref v1, ref v2) => {
+                let mut result = vec![];
+                for env in envs {
+                    match (self.values(v1, &env, fact)?, self.values(v2, &env, fact)?) {
+                        (Some(a), Some(b)) => if a.iter().any(|x| b.contains(x)) {
+                            result.push(env)
+                        },
+                        (None, Some(values)) | (Some(values), None) => {
+                            let binding = match (v1, v2) {
+                                (Value::Binding(b), _) if lookup(&env, b).is_none() => b.clone(),
+                                (_, Value::Binding(b)) => b.clone(),
+                                _ => unreachable!(),
+                            };
+                            let mut seen = HashSet::new();
+                            for value in values.into_iter().filter(|v| seen.insert(v.clone())) {
+                                let mut env = env.clone();
+                                env.push((binding.clone(), value));
+                                result.push(env);
+                            }
+                        },
+                        (None, None) => match *v1 {
+                            Value::Binding(ref b) => return Err(Error::Invalid(vec![Problem::Unbound(b.clone())])),
+                            _ => unreachable!(),
+                        },
+                    }
+                }
+                Ok(result)
+            },
+            Condition::LessThan(ref v1, ref v2) => self.compare(v1, v2, fact, envs, |a, b| a < b),
+            Condition::GreaterThan(ref v1, ref v2) => self.compare(v1, v2, fact, envs, |a, b| a > b),
+        }
+    }
+}
+
+/// Value of a projected column (the bucket's start for `Projection::Bucket`)
+pub(crate) fn column<T : AsRef<[u8]> + Clone>(projection: &Projection<T>, env: &Env<T>) -> Vec<u8> {
+    let value = lookup(env, projection.binding()).map(|v| v.to_vec()).unwrap_or_default();
+    match *projection {
+        Projection::Bucket(bucket, encoding, _) =>
+            encoding.decode(&value).map(|t| encoding.encode(bucket.start(t))).unwrap_or_default(),
+        _ => value,
+    }
+}
+
+pub(crate) fn project<T : AsRef<[u8]> + Clone>(projection: &[Projection<T>], envs: Vec<Env<T>>) -> Vec<Row> {
+    let grouped = projection.iter().any(|p| matches!(*p, Projection::Aggregate(_, _)));
+    let mut rows = vec![];
+    let mut seen = HashSet::new();
+    if !grouped {
+        for env in envs.iter() {
+            let row: Row = projection.iter().map(|p| column(p, env)).collect();
+            if seen.insert(row.clone()) {
+                rows.push(row);
+            }
+        }
+        return rows;
+    }
+    // group by the non-aggregated bindings
+    let mut groups: Vec<(Row, Vec<&Env<T>>)> = vec![];
+    for env in envs.iter() {
+        let key: Row = projection.iter().filter_map(|p| match *p {
+            Projection::Aggregate(_, _) => None,
+            _ => Some(column(p, env)),
+        }).collect();
+        match groups.iter().position(|(k, _)| *k == key) {
+            Some(i) => groups[i].1.push(env),
+            None => groups.push((key, vec![env])),
+        }
+    }
+    for (key, envs) in groups {
+        let mut key = key.into_iter();
+        let row = projection.iter().map(|p| match *p {
+            Projection::Aggregate(aggregate, _) => {
+                let values = envs.iter().map(|env| column(p, env));
+                match aggregate {
+                    // every solution counts, even one with the same value as another
+                    Aggregate::Count => encode_count(values.count()),
+                    Aggregate::Min => values.min().unwrap_or_default(),
+                    Aggregate::Max => values.max().unwrap_or_default(),
+                }
+            },
+            _ => key.next().unwrap_or_default(),
+        }).collect();
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+
+    use {Condition, Value, Query};
+    use Condition::*;
+    use query::Aggregate;
+    use execution::{FactSource, Attachment, Executor};
+
+    /// Facts as `(id, [(attribute, value)])`, attached in order
+    pub struct Facts(pub Vec<(&'static str, Vec<(&'static str, &'static str)>)>);
+
+    impl FactSource for Facts {
+        fn facts(&self) -> Vec<Vec<u8>> {
+            self.0.iter().map(|&(id, _)| id.as_bytes().to_vec()).collect()
+        }
+
+        fn facts_with(&self, attribute: &[u8], value: Option<&[u8]>) -> Vec<Vec<u8>> {
+            self.0.iter()
+                .filter(|&(_, attrs)| attrs.iter().any(|&(a, v)| a.as_bytes() == attribute &&
+                                                                      value.map(|value| v.as_bytes() == value).unwrap_or(true)))
+                .map(|&(id, _)| id.as_bytes().to_vec()).collect()
+        }
+
+        fn attachments(&self, fact: &[u8], attribute: &[u8]) -> Vec<Attachment> {
+            let mut txid = 0u8;
+            let mut result = vec![];
+            for &(id, ref attrs) in self.0.iter() {
+                for &(a, v) in attrs.iter() {
+                    txid += 1;
+                    if id.as_bytes() == fact && a.as_bytes() == attribute {
+                        result.push(Attachment { value: v.as_bytes().to_vec(), txid: vec![txid] });
+                    }
+                }
+            }
+            result
+        }
+    }
+
+    pub fn people() -> Facts {
+        Facts(vec![
+            ("1", vec![("#factType", "NameChanged"), ("#object", "alice"), ("#value", "Alice"), ("#timestamp", "1")]),
+            ("2", vec![("#factType", "NameChanged"), ("#object", "bob"), ("#value", "Bob"), ("#timestamp", "2")]),
+            ("3", vec![("#factType", "EmailChanged"), ("#object", "alice"), ("#value", "alice@example.com")]),
+            ("4", vec![("#factType", "NameChanged"), ("#object", "alice"), ("#value", "Alicia"), ("#timestamp", "3")]),
+            ("5", vec![("#factType", "AccountClosed"), ("#object", "bob")]),
+        ])
+    }
+
+    pub fn fact_type(fact_type: &'static str) -> Condition<&'static str> {
+        Equal(Value::Attribute("#factType"), Value::Data(fact_type))
+    }
+
+    #[test]
+    pub fn join() {
+        let cond = Condition::fact(fact_type("NameChanged")
+                                   .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                   .and(Equal(Value::Attribute("#value"), Value::Binding("Name"))))
+                   .and(Condition::fact(fact_type("EmailChanged")
+                                        .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                        .and(Equal(Value::Attribute("#value"), Value::Binding("Email")))));
+        let query = Query::new(cond).select("Name").select("Email");
+        let facts = people();
+        let rows = Executor::new(&facts).execute(&query).unwrap();
+        assert_eq!(rows, vec![vec![b"Alice".to_vec(), b"alice@example.com".to_vec()],
+                              vec![b"Alicia".to_vec(), b"alice@example.com".to_vec()]]);
+    }
+
+    #[test]
+    pub fn anti_join() {
+        let cond = Condition::fact(fact_type("NameChanged")
+                                   .and(Equal(Value::Attribute("#object"), Value::Binding("Person"))))
+                   .and(Condition::not_exists(fact_type("AccountClosed")
+                                              .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))));
+        let query = Query::new(cond).select("Person");
+        let facts = people();
+        let rows = Executor::new(&facts).execute(&query).unwrap();
+        assert_eq!(rows, vec![vec![b"alice".to_vec()]]);
+    }
+
+    #[test]
+    pub fn aggregate() {
+        let cond = Condition::fact(fact_type("NameChanged")
+                                   .and(Equal(Value::Attribute("#object"), Value::Data("alice")))
+                                   .and(Equal(Value::Attribute("#timestamp"), Value::Binding("Timestamp"))));
+        let query = Query::new(cond).select_aggregate(Aggregate::Max, "Timestamp")
+                                    .select_aggregate(Aggregate::Count, "Timestamp");
+        let facts = people();
+        let rows = Executor::new(&facts).execute(&query).unwrap();
+        assert_eq!(rows, vec![vec![b"3".to_vec(), vec![0, 0, 0, 0, 0, 0, 0, 2]]]);
+
+        // two changes to the same name are two solutions
+        let Facts(mut facts) = people();
+        facts.push(("6", vec![("#factType", "NameChanged"), ("#object", "alice"), ("#value", "Alice")]));
+        let cond = Condition::fact(fact_type("NameChanged")
+                                   .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                   .and(Equal(Value::Attribute("#value"), Value::Binding("Name"))));
+        let query = Query::new(cond).select("Person").select_aggregate(Aggregate::Count, "Name");
+        let rows = Executor::new(&Facts(facts)).execute(&query).unwrap();
+        assert_eq!(rows[0], vec![b"alice".to_vec(), vec![0, 0, 0, 0, 0, 0, 0, 3]]);
+
+        // ...but a change that matches both branches of an `Or` is one
+        let cond = Condition::fact(fact_type("NameChanged")
+                                   .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                   .and(Equal(Value::Attribute("#value"), Value::Data("Alice"))
+                                        .or(Equal(Value::Attribute("#timestamp"), Value::Data("1")))));
+        let query = Query::new(cond).select("Person").select_aggregate(Aggregate::Count, "Person");
+        let rows = Executor::new(&people()).execute(&query).unwrap();
+        assert_eq!(rows, vec![vec![b"alice".to_vec(), vec![0, 0, 0, 0, 0, 0, 0, 1]]]);
+    }
+}
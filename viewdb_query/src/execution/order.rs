@@ -0,0 +1,293 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Ordering and paging.
+//!
+//! Rows are ordered by the query's sort keys, and then by all of their
+//! columns, so that the order is total and a page can be resumed from the
+//! last row of the previous one (which is what a `Cursor` holds). With a
+//! limit, only the rows up to the end of the page are sorted.
+//!
+//! If the first sort key is bound to an attribute by a fact scope of the
+//! whole condition, and the source keeps that attribute's values indexed
+//! (`FactSource::values`), rows are found in index order instead, one key
+//! value at a time: only the rows sharing a key value are sorted, and with
+//! a limit, values past the end of the page aren't looked at.
+
+use std::cmp::Ordering;
+
+use condition::{Condition, Value};
+use query::{Query, Projection, Direction, Kind};
+use encoding::{Encode, Decode};
+use super::{Executor, FactSource, Error, Row, project};
+
+/// Opaque position in an ordered result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor(Vec<u8>);
+
+impl Cursor {
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Cursor(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub rows: Vec<Row>,
+    /// Where the next page starts, if there are more rows
+    pub next: Option<Cursor>,
+}
+
+type Keys = [(usize, Direction, Kind)];
+
+fn compare(a: &Row, b: &Row, keys: &Keys) -> Ordering {
+    for &(column, direction, kind) in keys {
+        let ordering = match kind {
+            Kind::Bytes => a[column].cmp(&b[column]),
+            Kind::Number(encoding) => encoding.decode(&a[column]).cmp(&encoding.decode(&b[column])),
+        };
+        let ordering = match direction {
+            Direction::Ascending => ordering,
+            Direction::Descending => ordering.reverse(),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.cmp(b)
+}
+
+/// Attribute the first sort key is bound to by a fact scope that every
+/// solution goes through, if its index order is the key's order
+fn indexed<T : AsRef<[u8]> + Clone>(query: &Query<T>) -> Option<&T> {
+    let key = query.order.first()?;
+    if key.kind != Kind::Bytes ||
+       !query.projection.iter().all(|p| matches!(*p, Projection::Binding(_))) {
+        return None;
+    }
+    for scope in query.condition.conjuncts() {
+        if let Condition::Fact(ref body) = *scope {
+            for part in body.conjuncts() {
+                if let Condition::Equal(Value::Attribute(ref a), Value::Binding(ref b)) |
+                       Condition::Equal(Value::Binding(ref b), Value::Attribute(ref a)) = *part {
+                    if b.as_ref() == key.binding.as_ref() {
+                        return Some(a);
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+impl<'a, S : FactSource + 'a> Executor<'a, S> {
+    /// Ordered rows after `after`, going through the first sort key's values
+    /// in index order, until there are `needed` rows (if any limit)
+    fn indexed_rows<T>(&self, query: &Query<T>, values: Vec<Vec<u8>>, keys: &Keys, after: Option<&Row>,
+                       needed: Option<usize>) -> Result<Vec<Row>, Error<T>> where T : AsRef<[u8]> + Clone {
+        let relations = self.relations(query)?;
+        let (column, direction, _) = keys[0];
+        let binding = &query.order[0].binding;
+        let values: Vec<Vec<u8>> = match direction {
+            Direction::Ascending => values,
+            Direction::Descending => values.into_iter().rev().collect(),
+        };
+        let mut rows = vec![];
+        for value in values {
+            if let Some(last) = after {
+                let ordering = match direction {
+                    Direction::Ascending => value.cmp(&last[column]),
+                    Direction::Descending => last[column].cmp(&value),
+                };
+                if ordering == Ordering::Less {
+                    continue;
+                }
+            }
+            let envs = self.solve(query, vec![vec![(binding.clone(), value)]], &relations)?;
+            let mut group = project(&query.projection, envs);
+            if let Some(last) = after {
+                group.retain(|row| compare(row, last, keys) == Ordering::Greater);
+            }
+            group.sort_by(|a, b| compare(a, b, keys));
+            rows.extend(group);
+            if needed.map(|needed| rows.len() >= needed).unwrap_or(false) {
+                break;
+            }
+        }
+        Ok(rows)
+    }
+}
+
+pub(crate) fn page<S, T>(executor: &Executor<S>, query: &Query<T>, cursor: Option<&Cursor>) -> Result<Page, Error<T>>
+    where S : FactSource, T : AsRef<[u8]> + Clone {
+    let mut keys = vec![];
+    for key in query.order.iter() {
+        match query.projection.iter().position(|p| p.binding().as_ref() == key.binding.as_ref()) {
+            Some(column) => keys.push((column, key.direction, key.kind)),
+            None => return Err(Error::NotProjected(key.binding.clone())),
+        }
+    }
+    let after = match cursor {
+        Some(Cursor(bytes)) => match Row::from_bytes(bytes) {
+            Ok(ref last) if last.len() == query.projection.len() => Some(last.clone()),
+            _ => return Err(Error::InvalidCursor),
+        },
+        None => None,
+    };
+    let offset = if after.is_some() { 0 } else { query.offset };
+    let end = query.limit.map(|limit| offset.saturating_add(limit));
+    let values = indexed(query).and_then(|attribute| executor.source.values(attribute.as_ref()));
+    let mut rows = match values {
+        Some(values) => {
+            // one more row than the page tells whether there is a next one
+            let rows = executor.indexed_rows(query, values, &keys, after.as_ref(), end.map(|end| end.saturating_add(1)))?;
+            let next = match end {
+                Some(end) if end > 0 && end < rows.len() => Some(Cursor(rows[end - 1].to_bytes())),
+                _ => None,
+            };
+            let rows = rows.into_iter().take(end.unwrap_or(usize::MAX)).skip(offset).collect();
+            return Ok(Page { rows, next });
+        },
+        None => project(&query.projection, executor.solutions(query, None)?),
+    };
+    if let Some(ref last) = after {
+        rows.retain(|row| compare(row, last, &keys) == Ordering::Greater);
+    }
+    match end {
+        Some(end) if end < rows.len() => {
+            rows.select_nth_unstable_by(end, |a, b| compare(a, b, &keys));
+            rows.truncate(end);
+            rows.sort_by(|a, b| compare(a, b, &keys));
+            let rows: Vec<Row> = rows.into_iter().skip(offset).collect();
+            let next = rows.last().map(|row| Cursor(row.to_bytes()));
+            Ok(Page { rows, next })
+        },
+        _ => {
+            if !keys.is_empty() || query.limit.is_some() || offset > 0 {
+                rows.sort_by(|a, b| compare(a, b, &keys));
+            }
+            Ok(Page { rows: rows.into_iter().skip(offset).collect(), next: None })
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::cell::Cell;
+    use std::collections::BTreeSet;
+
+    use {Condition, Value, Query};
+    use Condition::Equal;
+    use query::{Direction, Kind};
+    use time::Encoding;
+    use execution::{Executor, FactSource, Attachment, Cursor, Error, Row};
+    use execution::tests::{Facts, people, fact_type};
+
+    /// Facts with their values indexed, counting index lookups
+    struct Indexed(Facts, Cell<usize>);
+
+    impl FactSource for Indexed {
+        fn facts(&self) -> Vec<Vec<u8>> {
+            self.0.facts()
+        }
+
+        fn facts_with(&self, attribute: &[u8], value: Option<&[u8]>) -> Vec<Vec<u8>> {
+            self.1.set(self.1.get() + 1);
+            self.0.facts_with(attribute, value)
+        }
+
+        fn attachments(&self, fact: &[u8], attribute: &[u8]) -> Vec<Attachment> {
+            self.0.attachments(fact, attribute)
+        }
+
+        fn values(&self, attribute: &[u8]) -> Option<Vec<Vec<u8>>> {
+            let values: BTreeSet<Vec<u8>> = (self.0).0.iter()
+                .flat_map(|(_, attrs)| attrs.iter().filter(|&&(a, _)| a.as_bytes() == attribute)
+                                                 .map(|&(_, v)| v.as_bytes().to_vec()))
+                .collect();
+            Some(values.into_iter().collect())
+        }
+    }
+
+    fn names() -> Query<&'static str> {
+        Query::new(Condition::fact(fact_type("NameChanged")
+                                   .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                   .and(Equal(Value::Attribute("#value"), Value::Binding("Name")))))
+        .select("Person").select("Name")
+    }
+
+    fn pages<S : FactSource>(executor: &Executor<S>, query: &Query<&'static str>) -> Vec<Vec<Row>> {
+        let mut pages = vec![];
+        let mut cursor = None;
+        loop {
+            let page = executor.page(query, cursor.as_ref()).unwrap();
+            pages.push(page.rows);
+            match page.next {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    pub fn pagination() {
+        let facts = people();
+        let executor = Executor::new(&facts);
+        let query = Query::new(Condition::fact(fact_type("NameChanged")
+                                               .and(Equal(Value::Attribute("#value"), Value::Binding("Name")))
+                                               .and(Equal(Value::AttributeTxid("#factType"), Value::Binding("Txid")))))
+                    .select("Name").select("Txid")
+                    .order_by("Txid", Direction::Descending, Kind::Number(Encoding::BigEndian))
+                    .limit(2);
+        let name = |name: &str, txid: u8| vec![name.as_bytes().to_vec(), vec![txid]];
+        let page = executor.page(&query, None).unwrap();
+        assert_eq!(page.rows, vec![name("Alicia", 12), name("Bob", 5)]);
+        let page = executor.page(&query, page.next.as_ref()).unwrap();
+        assert_eq!(page.rows, vec![name("Alice", 1)]);
+        assert_eq!(page.next, None);
+        assert_eq!(executor.execute(&query.clone().offset(1)).unwrap(), vec![name("Bob", 5), name("Alice", 1)]);
+        assert_eq!(executor.execute(&query.order_by("Email", Direction::Ascending, Kind::Bytes)),
+                   Err(Error::NotProjected("Email")));
+    }
+
+    #[test]
+    pub fn cursors() {
+        let facts = people();
+        let executor = Executor::new(&facts);
+        let row = |person: &str, name: &str| vec![person.as_bytes().to_vec(), name.as_bytes().to_vec()];
+        // without sort keys, and with tied ones, rows are ordered by all columns
+        assert_eq!(pages(&executor, &names().limit(2)),
+                   vec![vec![row("alice", "Alice"), row("alice", "Alicia")], vec![row("bob", "Bob")]]);
+        assert_eq!(pages(&executor, &names().order_by("Person", Direction::Descending, Kind::Bytes).limit(1)),
+                   vec![vec![row("bob", "Bob")], vec![row("alice", "Alice")], vec![row("alice", "Alicia")]]);
+        assert_eq!(executor.page(&names().limit(1), Some(&Cursor::from_bytes(vec![1, 2, 3]))),
+                   Err(Error::InvalidCursor));
+        assert_eq!(executor.execute(&names().offset(2).limit(usize::MAX)).unwrap(), vec![row("bob", "Bob")]);
+    }
+
+    #[test]
+    pub fn index_order() {
+        let facts = Indexed(people(), Cell::new(0));
+        let executor = Executor::new(&facts);
+        let row = |person: &str, name: &str| vec![person.as_bytes().to_vec(), name.as_bytes().to_vec()];
+        let query = names().order_by("Person", Direction::Ascending, Kind::Bytes).limit(1);
+        assert_eq!(pages(&executor, &query),
+                   vec![vec![row("alice", "Alice")], vec![row("alice", "Alicia")], vec![row("bob", "Bob")]]);
+        let query = names().order_by("Person", Direction::Descending, Kind::Bytes).offset(1);
+        assert_eq!(executor.execute(&query).unwrap(), vec![row("alice", "Alice"), row("alice", "Alicia")]);
+        // "alice"'s rows are enough for the first page (and to know there's more)
+        facts.1.set(0);
+        let page = executor.page(&names().order_by("Person", Direction::Ascending, Kind::Bytes).limit(1), None).unwrap();
+        assert_eq!(page.rows, vec![row("alice", "Alice")]);
+        assert!(page.next.is_some());
+        assert_eq!(facts.1.get(), 1);
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Cost-based planning.
+//!
+//! Without statistics, a fact scope is looked up through its first usable
+//! `Equal` (then `Present`) conjunct, and conjuncts are evaluated in their
+//! original order (binders first). With `Statistics`, every possible index
+//! lookup is estimated and the cheapest one is used, and the binders of a
+//! conjunction are ordered greedily: the cheapest one given what's already
+//! bound goes first.
+
+use std::collections::{HashMap, HashSet};
+
+use condition::{Condition, Value};
+use condition::analysis;
+use feed::{Change, ChangeSource};
+
+#[derive(Debug, Clone, Default)]
+pub struct AttributeStatistics {
+    /// Number of attachments
+    pub attachments: u64,
+    /// Number of attachments of every value
+    pub values: HashMap<Vec<u8>, u64>,
+}
+
+/// Attribute statistics, kept up to date by `record`ing every change
+#[derive(Debug, Clone, Default)]
+pub struct Statistics {
+    facts: HashSet<Vec<u8>>,
+    attributes: HashMap<Vec<u8>, AttributeStatistics>,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Statistics::default()
+    }
+
+    /// Statistics of all changes recorded so far
+    pub fn collect<S : ChangeSource>(source: &S) -> Self {
+        let mut statistics = Statistics::new();
+        for change in source.changes(None) {
+            statistics.record(&change);
+        }
+        statistics
+    }
+
+    pub fn record(&mut self, change: &Change) {
+        if !self.facts.contains(&change.fact) {
+            self.facts.insert(change.fact.clone());
+        }
+        let attribute = self.attributes.entry(change.attribute.clone()).or_default();
+        attribute.attachments += 1;
+        *attribute.values.entry(change.value.clone()).or_insert(0) += 1;
+    }
+
+    pub fn attribute(&self, attribute: &[u8]) -> Option<&AttributeStatistics> {
+        self.attributes.get(attribute)
+    }
+
+    /// Estimated number of facts to look at for a scan, a `Present` lookup
+    /// (`value` is `None`) or an `Equal` lookup (with a known or, if
+    /// `Some(None)`, yet unknown value)
+    pub fn estimate(&self, attribute: Option<&[u8]>, value: Option<Option<&[u8]>>) -> f64 {
+        let attribute = match attribute {
+            Some(attribute) => match self.attributes.get(attribute) {
+                Some(attribute) => attribute,
+                None => return 0.0,
+            },
+            None => return self.facts.len() as f64,
+        };
+        match value {
+            None => attribute.attachments as f64,
+            Some(Some(value)) => attribute.values.get(value).cloned().unwrap_or(0) as f64,
+            Some(None) => attribute.attachments as f64 / attribute.values.len().max(1) as f64,
+        }
+    }
+}
+
+/// How the facts of a fact scope are found
+#[derive(Debug, PartialEq)]
+pub(crate) enum Index<'c, T : AsRef<[u8]> + Clone + 'c> {
+    /// Facts with the attribute equal to the value (data or a bound binding)
+    Lookup(&'c T, &'c Value<T>),
+    /// Facts with the attribute
+    Present(&'c T),
+    Scan,
+}
+
+impl<'c, T : AsRef<[u8]> + Clone> Index<'c, T> {
+    pub(crate) fn cost(&self, statistics: &Statistics) -> f64 {
+        match *self {
+            Index::Lookup(a, Value::Data(d)) => statistics.estimate(Some(a.as_ref()), Some(Some(d.as_ref()))),
+            Index::Lookup(a, _) => statistics.estimate(Some(a.as_ref()), Some(None)),
+            Index::Present(a) => statistics.estimate(Some(a.as_ref()), None),
+            Index::Scan => statistics.estimate(None, None),
+        }
+    }
+}
+
+/// Index to look up the facts of a fact scope's body with
+pub(crate) fn index<'c, T, F>(body: &'c Condition<T>, bound: F, statistics: Option<&Statistics>) -> Index<'c, T>
+    where T : AsRef<[u8]> + Clone, F : Fn(&T) -> bool {
+    let mut options = vec![];
+    let mut present = vec![];
+    for part in body.conjuncts() {
+        match *part {
+            Condition::Equal(Value::Attribute(ref a), ref v) | Condition::Equal(ref v, Value::Attribute(ref a)) => {
+                match *v {
+                    Value::Data(_) => options.push(Index::Lookup(a, v)),
+                    Value::Binding(ref b) if bound(b) => options.push(Index::Lookup(a, v)),
+                    Value::Binding(_) => present.push(Index::Present(a)),
+                    _ => (),
+                }
+            },
+            Condition::Present(Value::Attribute(ref a)) => present.push(Index::Present(a)),
+            _ => (),
+        }
+    }
+    options.extend(present);
+    options.push(Index::Scan);
+    match statistics {
+        Some(statistics) => {
+            let mut best = options.remove(0);
+            let mut cost = best.cost(statistics);
+            for option in options {
+                let c = option.cost(statistics);
+                if c < cost {
+                    best = option;
+                    cost = c;
+                }
+            }
+            best
+        },
+        None => options.remove(0),
+    }
+}
+
+/// Conjuncts that bind come before those that only filter
+pub(crate) fn rank<T : AsRef<[u8]> + Clone>(condition: &Condition<T>) -> u8 {
+    match *condition {
+        Condition::Equal(Value::Binding(_), Value::Binding(_)) => 1,
+        Condition::LessThan(_, _) | Condition::GreaterThan(_, _) => 2,
+        Condition::Not(_) => 3,
+        _ => 0,
+    }
+}
+
+/// Evaluation order of a conjunction's parts; `cost` estimates a binder
+/// given the bindings bound before it
+pub(crate) fn order<'c, T, F>(mut parts: Vec<&'c Condition<T>>, bound: Vec<T>, statistics: Option<&Statistics>, cost: F)
+                              -> Vec<&'c Condition<T>>
+    where T : AsRef<[u8]> + Clone, F : Fn(&Condition<T>, &[T]) -> f64 {
+    parts.sort_by_key(|c| rank(c));
+    if statistics.is_none() {
+        return parts;
+    }
+    let split = parts.iter().position(|c| rank(c) > 0).unwrap_or(parts.len());
+    let rest = parts.split_off(split);
+    let mut bound = bound;
+    let mut result = vec![];
+    while !parts.is_empty() {
+        let mut best = 0;
+        let mut best_cost = cost(parts[0], &bound);
+        for (i, part) in parts.iter().enumerate().skip(1) {
+            let c = cost(part, &bound);
+            if c < best_cost {
+                best = i;
+                best_cost = c;
+            }
+        }
+        let part = parts.remove(best);
+        for b in analysis::bound(part) {
+            if !analysis::contains(&bound, &b) {
+                bound.push(b);
+            }
+        }
+        result.push(part);
+    }
+    result.extend(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query};
+    use Condition::Equal;
+    use execution::Executor;
+    use execution::plan::{index, Index, Statistics};
+    use execution::tests::{people, fact_type};
+
+    #[test]
+    pub fn statistics() {
+        let facts = people();
+        let statistics = Statistics::collect(&facts);
+        assert_eq!(statistics.estimate(Some(b"#factType"), Some(Some(b"NameChanged"))), 3.0);
+        assert_eq!(statistics.estimate(Some(b"#object"), Some(None)), 2.5);
+        assert_eq!(statistics.estimate(None, None), 5.0);
+
+        let body = fact_type("NameChanged").and(Equal(Value::Attribute("#object"), Value::Data("bob")));
+        assert_eq!(index(&body, |_| false, None), Index::Lookup(&"#factType", &Value::Data("NameChanged")));
+        assert_eq!(index(&body, |_| false, Some(&statistics)), Index::Lookup(&"#object", &Value::Data("bob")));
+
+        let cond = Condition::fact(fact_type("NameChanged")
+                                   .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                   .and(Equal(Value::Attribute("#value"), Value::Binding("Name"))))
+                   .and(Condition::fact(fact_type("AccountClosed")
+                                        .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))));
+        let query = Query::new(cond).select("Name");
+        let planned = Executor::with_statistics(&facts, &statistics).execute(&query).unwrap();
+        assert_eq!(planned, Executor::new(&facts).execute(&query).unwrap());
+        assert_eq!(planned, vec![vec![b"Bob".to_vec()]]);
+    }
+}
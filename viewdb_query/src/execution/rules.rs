@@ -0,0 +1,244 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Rule evaluation.
+//!
+//! Rules are stratified first: a rule that is referred to under a `Not` is
+//! fully evaluated before any rule that negates it, and recursion through
+//! negation is rejected. Every stratum is then evaluated semi-naively: after
+//! the first round, a rule's body is only re-evaluated with one of its
+//! recursive atoms reading the tuples derived in the previous round.
+
+use std::collections::BTreeSet;
+use std::ptr;
+
+use condition::Condition;
+use query::{Query, Rule};
+use super::{Executor, FactSource, Error, Row, lookup};
+
+pub(crate) struct Relations<'a, T : AsRef<[u8]> + Clone + 'a> {
+    total: Vec<(T, BTreeSet<Row>)>,
+    /// Recursive atom that only sees the previous round's tuples
+    delta: Option<(&'a Condition<T>, BTreeSet<Row>)>,
+    /// Fact scope that only ranges over the given fact
+    pinned: Option<(&'a Condition<T>, Vec<u8>)>,
+}
+
+impl<'a, T : AsRef<[u8]> + Clone + 'a> Relations<'a, T> {
+    fn index(&self, name: &T) -> Option<usize> {
+        self.total.iter().position(|(n, _)| n.as_ref() == name.as_ref())
+    }
+
+    pub(crate) fn tuples(&self, atom: &Condition<T>, name: &T) -> Option<&BTreeSet<Row>> {
+        if let Some((delta_atom, ref tuples)) = self.delta {
+            if ptr::eq(delta_atom, atom) {
+                return Some(tuples);
+            }
+        }
+        self.index(name).map(|i| &self.total[i].1)
+    }
+
+    pub(crate) fn pin(&mut self, scope: &'a Condition<T>, fact: &[u8]) {
+        self.pinned = Some((scope, fact.to_vec()));
+    }
+
+    pub(crate) fn pinned(&self, scope: &Condition<T>) -> Option<&[u8]> {
+        match self.pinned {
+            Some((pinned, ref fact)) if ptr::eq(pinned, scope) => Some(fact),
+            _ => None,
+        }
+    }
+}
+
+/// Rule atoms of a condition, and whether they are negated
+fn atoms<'a, T : AsRef<[u8]> + Clone>(condition: &'a Condition<T>, negated: bool,
+                                      result: &mut Vec<(&'a Condition<T>, bool)>) {
+    match *condition {
+        Condition::Rule(_, _) => result.push((condition, negated)),
+        Condition::Not(ref c) => atoms(c, !negated, result),
+        Condition::And(ref c1, ref c2) | Condition::Or(ref c1, ref c2) => {
+            atoms(c1, negated, result);
+            atoms(c2, negated, result);
+        },
+        Condition::Fact(ref c) | Condition::Trait(_, ref c) => atoms(c, negated, result),
+        _ => (),
+    }
+}
+
+fn stratify<'r, T : AsRef<[u8]> + Clone>(rules: &'r [Rule<T>], names: &[T]) -> Result<Vec<Vec<&'r Rule<T>>>, Error<T>> {
+    let index = |name: &T| names.iter().position(|n| n.as_ref() == name.as_ref());
+    let mut strata = vec![0; names.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for rule in rules {
+            let r = index(&rule.name).unwrap();
+            let mut dependencies = vec![];
+            atoms(&rule.body, false, &mut dependencies);
+            for (atom, negated) in dependencies {
+                if let Condition::Rule(ref name, _) = *atom {
+                    let d = index(name).ok_or_else(|| Error::UnknownRule(name.clone()))?;
+                    let required = strata[d] + if negated { 1 } else { 0 };
+                    if required > strata[r] {
+                        if required > names.len() {
+                            return Err(Error::NegatedRecursion(rule.name.clone()));
+                        }
+                        strata[r] = required;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    let mut result: Vec<Vec<&Rule<T>>> = vec![];
+    for rule in rules {
+        let s = strata[index(&rule.name).unwrap()];
+        while result.len() <= s {
+            result.push(vec![]);
+        }
+        result[s].push(rule);
+    }
+    Ok(result)
+}
+
+fn check_arity<T : AsRef<[u8]> + Clone>(condition: &Condition<T>, query: &Query<T>) -> Result<(), Error<T>> {
+    let mut dependencies = vec![];
+    atoms(condition, false, &mut dependencies);
+    for (atom, _) in dependencies {
+        if let Condition::Rule(ref name, ref arguments) = *atom {
+            if query.rules.iter().any(|r| r.name.as_ref() == name.as_ref() && r.parameters.len() != arguments.len()) ||
+               query.views.iter().any(|v| v.name.as_ref() == name.as_ref() && v.query.projection.len() != arguments.len()) {
+                return Err(Error::RuleArity(name.clone()));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn derive<'a, S, T>(executor: &Executor<S>, rule: &Rule<T>, relations: &Relations<'a, T>) -> Result<BTreeSet<Row>, Error<T>>
+    where S : FactSource, T : AsRef<[u8]> + Clone {
+    let envs = executor.eval(&rule.body, None, vec![vec![]], relations)?;
+    Ok(envs.iter()
+           .map(|env| rule.parameters.iter().map(|p| lookup(env, p).map(|v| v.to_vec()).unwrap_or_default()).collect())
+           .collect())
+}
+
+/// Derives every rule's tuples; views attached to the query are
+/// evaluated up front, as they can't depend on the query's rules
+pub(crate) fn evaluate<'a, S, T>(executor: &Executor<S>, query: &'a Query<T>) -> Result<Relations<'a, T>, Error<T>>
+    where S : FactSource, T : AsRef<[u8]> + Clone {
+    let rules = &query.rules;
+    let mut names: Vec<T> = vec![];
+    let mut total = vec![];
+    for view in query.views.iter() {
+        let rows = executor.execute(&view.query)?;
+        names.push(view.name.clone());
+        total.push((view.name.clone(), rows.into_iter().collect()));
+    }
+    for rule in rules {
+        if !names.iter().any(|n| n.as_ref() == rule.name.as_ref()) {
+            names.push(rule.name.clone());
+            total.push((rule.name.clone(), BTreeSet::new()));
+        }
+    }
+    check_arity(&query.condition, query)?;
+    for rule in rules {
+        check_arity(&rule.body, query)?;
+    }
+    let strata = stratify(rules, &names)?;
+    let mut relations = Relations { total, delta: None, pinned: None };
+    for stratum in strata {
+        let in_stratum = |name: &T| stratum.iter().any(|r| r.name.as_ref() == name.as_ref());
+        // first round sees everything derived so far
+        let mut delta: Vec<(usize, BTreeSet<Row>)> = vec![];
+        for rule in stratum.iter() {
+            let i = relations.index(&rule.name).unwrap();
+            let tuples = derive(executor, rule, &relations)?;
+            delta.push((i, tuples));
+        }
+        loop {
+            let mut new = false;
+            for &mut (i, ref mut tuples) in delta.iter_mut() {
+                let total = &mut relations.total[i].1;
+                tuples.retain(|t| !total.contains(t));
+                new |= !tuples.is_empty();
+                total.extend(tuples.iter().cloned());
+            }
+            if !new {
+                break;
+            }
+            let mut next = vec![];
+            for rule in stratum.iter() {
+                let i = relations.index(&rule.name).unwrap();
+                let mut recursive = vec![];
+                atoms(&rule.body, false, &mut recursive);
+                for (atom, negated) in recursive {
+                    let name = match *atom {
+                        Condition::Rule(ref name, _) if !negated && in_stratum(name) => name,
+                        _ => continue,
+                    };
+                    let n = relations.index(name).unwrap();
+                    let previous = delta.iter().filter(|&&(j, _)| j == n)
+                                        .fold(BTreeSet::new(), |mut acc, (_, tuples)| {
+                                            acc.extend(tuples.iter().cloned());
+                                            acc
+                                        });
+                    relations.delta = Some((atom, previous));
+                    let tuples = derive(executor, rule, &relations)?;
+                    relations.delta = None;
+                    next.push((i, tuples));
+                }
+            }
+            delta = next;
+        }
+    }
+    Ok(relations)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query, Rule};
+    use Condition::*;
+    use execution::{Executor, Error};
+    use execution::tests::Facts;
+
+    fn link(from: &'static str, to: &'static str) -> Condition<&'static str> {
+        Condition::fact(Equal(Value::Attribute("#factType"), Value::Data("IdentityLinked"))
+                        .and(Equal(Value::Attribute("#from"), Value::Binding(from)))
+                        .and(Equal(Value::Attribute("#to"), Value::Binding(to))))
+    }
+
+    #[test]
+    pub fn transitive_closure() {
+        let facts = Facts(vec![
+            ("1", vec![("#factType", "IdentityLinked"), ("#from", "a"), ("#to", "b")]),
+            ("2", vec![("#factType", "IdentityLinked"), ("#from", "b"), ("#to", "c")]),
+            ("3", vec![("#factType", "IdentityLinked"), ("#from", "c"), ("#to", "a")]),
+            ("4", vec![("#factType", "IdentityLinked"), ("#from", "d"), ("#to", "e")]),
+        ]);
+        let query = Query::new(Condition::rule("linked", vec![Value::Data("a"), Value::Binding("X")]))
+                    .select("X")
+                    .rule(Rule::new("linked", vec!["A", "B"], link("A", "B")))
+                    .rule(Rule::new("linked", vec!["A", "C"],
+                                    Condition::rule("linked", vec![Value::Binding("A"), Value::Binding("B")])
+                                    .and(link("B", "C"))));
+        let mut rows = Executor::new(&facts).execute(&query).unwrap();
+        rows.sort();
+        assert_eq!(rows, vec![vec![b"a".to_vec()], vec![b"b".to_vec()], vec![b"c".to_vec()]]);
+    }
+
+    #[test]
+    pub fn negated_recursion() {
+        let facts = Facts(vec![("1", vec![("#from", "a"), ("#to", "b")])]);
+        let query = Query::new(Condition::rule("odd", vec![Value::Binding("X")]))
+                    .select("X")
+                    .rule(Rule::new("odd", vec!["X"],
+                                    Condition::fact(Equal(Value::Attribute("#from"), Value::Binding("X")))
+                                    .and(!Condition::rule("odd", vec![Value::Binding("X")]))));
+        assert_eq!(Executor::new(&facts).execute(&query), Err(Error::NegatedRecursion("odd")));
+    }
+}
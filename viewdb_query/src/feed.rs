@@ -0,0 +1,160 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Change feed of attribute attachments.
+//!
+//! Every attachment is a `Change`, and changes are ordered by TXID. A
+//! `Feed` follows them from a given TXID on, optionally only those of
+//! certain attributes or traits. In ViewDB, changes are recorded under the
+//! feed prefix as they are committed (see `FEED` in the engine) and
+//! published, encoded, on the feed topic.
+
+use Trait;
+use encoding::{self, Encode, Decode};
+use execution::FactSource;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub fact: Vec<u8>,
+    pub attribute: Vec<u8>,
+    pub value: Vec<u8>,
+    pub txid: Vec<u8>,
+}
+
+impl Encode for Change {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        vec![self.fact.clone(), self.attribute.clone(), self.value.clone(), self.txid.clone()].encode(buf)
+    }
+}
+
+impl Decode for Change {
+    fn decode(buf: &mut &[u8]) -> Result<Self, encoding::Error> {
+        let mut row = Vec::<Vec<u8>>::decode(buf)?.into_iter();
+        match (row.next(), row.next(), row.next(), row.next(), row.next()) {
+            (Some(fact), Some(attribute), Some(value), Some(txid), None) => Ok(Change { fact, attribute, value, txid }),
+            _ => Err(encoding::Error::UnexpectedEnd),
+        }
+    }
+}
+
+/// Access to recorded attachments in TXID order
+pub trait ChangeSource {
+    /// Changes committed after `txid` (all changes if `None`)
+    fn changes(&self, txid: Option<&[u8]>) -> Vec<Change>;
+}
+
+pub enum Filter<T : AsRef<[u8]> + Clone> {
+    Attribute(T),
+    /// Attachments of the trait's attributes to facts that have the trait
+    Trait(Trait<T>),
+}
+
+impl<T : AsRef<[u8]> + Clone> Filter<T> {
+    fn matches<S : FactSource>(&self, source: &S, change: &Change) -> bool {
+        match *self {
+            Filter::Attribute(ref a) => a.as_ref() == change.attribute.as_slice(),
+            Filter::Trait(ref t) => {
+                t.iter().any(|p| p.0.as_ref() == change.attribute.as_slice()) &&
+                t.iter().all(|p| match p.1 {
+                    Some(ref v) => source.attachments(&change.fact, p.0.as_ref()).iter()
+                                         .any(|a| a.value.as_slice() == v.as_ref()),
+                    None => true,
+                })
+            },
+        }
+    }
+}
+
+pub struct Feed<T : AsRef<[u8]> + Clone> {
+    filters: Vec<Filter<T>>,
+    txid: Option<Vec<u8>>,
+}
+
+impl<T : AsRef<[u8]> + Clone> Default for Feed<T> {
+    fn default() -> Self {
+        Feed::new()
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> Feed<T> {
+    /// Feed of all changes, from the first one
+    pub fn new() -> Self {
+        Feed { filters: vec![], txid: None }
+    }
+
+    /// Feed of changes committed after `txid`
+    pub fn resume(txid: Vec<u8>) -> Self {
+        Feed { filters: vec![], txid: Some(txid) }
+    }
+
+    /// Only follows changes that match one of the filters
+    pub fn filter(mut self, filter: Filter<T>) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// TXID of the last change seen, to resume from
+    pub fn txid(&self) -> Option<&[u8]> {
+        self.txid.as_deref()
+    }
+
+    /// Changes committed since the last poll
+    pub fn poll<S : ChangeSource + FactSource>(&mut self, source: &S) -> Vec<Change> {
+        let changes = source.changes(self.txid());
+        if let Some(last) = changes.last() {
+            self.txid = Some(last.txid.clone());
+        }
+        changes.into_iter()
+               .filter(|c| self.filters.is_empty() || self.filters.iter().any(|f| f.matches(source, c)))
+               .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use encoding::{Encode, Decode};
+    use execution::tests::{Facts, people};
+    use feed::{Change, ChangeSource, Feed, Filter};
+
+    impl ChangeSource for Facts {
+        fn changes(&self, txid: Option<&[u8]>) -> Vec<Change> {
+            let mut result = vec![];
+            for &(id, ref attrs) in self.0.iter() {
+                for &(a, v) in attrs.iter() {
+                    let change = Change {
+                        fact: id.as_bytes().to_vec(),
+                        attribute: a.as_bytes().to_vec(),
+                        value: v.as_bytes().to_vec(),
+                        txid: vec![result.len() as u8 + 1],
+                    };
+                    result.push(change);
+                }
+            }
+            result.into_iter().filter(|c| txid.map(|t| c.txid.as_slice() > t).unwrap_or(true)).collect()
+        }
+    }
+
+    #[test]
+    pub fn follow() {
+        let Facts(mut facts) = people();
+        let name_changed = vec![("#factType", Some("NameChanged")).into(), ("#value", None).into()].into();
+        let mut feed = Feed::new().filter(Filter::Trait(name_changed)).filter(Filter::Attribute("#timestamp"));
+        let values = |changes: Vec<Change>| changes.into_iter().map(|c| c.value).collect::<Vec<_>>();
+        assert_eq!(values(feed.poll(&Facts(facts.clone()))),
+                   vec![b"NameChanged".to_vec(), b"Alice".to_vec(), b"1".to_vec(),
+                        b"NameChanged".to_vec(), b"Bob".to_vec(), b"2".to_vec(),
+                        b"NameChanged".to_vec(), b"Alicia".to_vec(), b"3".to_vec()]);
+        assert!(feed.poll(&Facts(facts.clone())).is_empty());
+
+        facts.push(("6", vec![("#factType", "NameChanged"), ("#value", "Robert")]));
+        let txid = feed.txid().unwrap().to_vec();
+        let changes = feed.poll(&Facts(facts.clone()));
+        assert_eq!(values(changes.clone()), vec![b"NameChanged".to_vec(), b"Robert".to_vec()]);
+        assert_eq!(Feed::<&str>::resume(txid).poll(&Facts(facts.clone())).len(), 2);
+        assert_eq!(Change::from_bytes(&changes[0].to_bytes()).as_ref(), Ok(&changes[0]));
+    }
+}
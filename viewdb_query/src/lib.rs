@@ -3,42 +3,58 @@
 // This Source Code Form is subject to the terms of the Mozilla Public
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
-#[macro_use]
-extern crate try_opt;
 extern crate viewdb_core;
 
 #[cfg(test)] #[macro_use]
 extern crate assert_matches;
 
-pub(crate) use viewdb_core::{TraitPattern, TraitResolver};
-#[cfg(test)]
-pub(crate) use viewdb_core::{Trait};
+pub(crate) use viewdb_core::{Trait, TraitPattern, TraitResolver};
 
 pub mod condition;
+pub mod query;
+pub mod execution;
+pub mod view;
+pub mod materialized;
+pub mod live;
+pub mod feed;
+pub mod reactor;
+pub mod encoding;
+pub mod time;
+pub mod sequence;
+pub mod prepared;
+pub mod mapping;
+pub mod datum;
+pub mod memory;
+pub mod working;
+pub mod traits;
 pub use condition::{Condition, Value};
+pub use condition::visit::Visitor;
+pub use condition::fold::Fold;
+pub use query::{Query, Rule};
+pub use view::{View, ViewResolver};
 
 #[cfg(test)]
 mod tests {
     use super::{Value, Trait, TraitResolver, Condition};
     use super::Condition::{Equal};
-    use condition::processing::{Processor, ProcessorExtension, TraitsExpansion, PresentEqualCompaction,
+    use condition::processing::{Processor, ProcessorExtension, Error, TraitsExpansion, PresentEqualCompaction,
                                 ComparisonSuppression, BooleanLiteralSuppression, ImplicitFact};
 
     #[derive(Clone)]
     pub struct Test<T : AsRef<[u8]> + Clone>((T, Trait<T>), (T, Trait<T>), (T, Trait<T>));
 
     impl<T : AsRef<[u8]> + Clone> TraitResolver<T> for Test<T>  {
-        fn resolve(&self, name: T) -> &Trait<T> {
+        fn resolve(&self, name: T) -> Option<&Trait<T>> {
             if name.as_ref() == (self.0).0.as_ref() {
-                return &(self.0).1
+                return Some(&(self.0).1)
             }
             if name.as_ref() == (self.1).0.as_ref() {
-                return &(self.1).1
+                return Some(&(self.1).1)
             }
             if name.as_ref() == (self.2).0.as_ref() {
-                return &(self.2).1
+                return Some(&(self.2).1)
             }
-            unreachable!()
+            None
         }
     }
 
@@ -63,6 +79,9 @@ mod tests {
                     .after_that(ImplicitFact)
                     .unwrap();
 
-        println!("{:#?}", cond1);
+        println!("{}", cond1);
+
+        assert_eq!(te.process(Condition::trait_scope("Unknown", Condition::True)),
+                   Err(Error::UnknownTrait("Unknown")));
     }
 }
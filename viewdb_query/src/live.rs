@@ -0,0 +1,197 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Live queries.
+//!
+//! A subscriber gets the query's current result set, and then a `Delta`
+//! (rows added and removed) whenever an attachment changes it or, if it
+//! has a sliding window, time passes it by (see `tick`). Every
+//! subscription is backed by a materialized view; in ViewDB, deltas are
+//! published (encoded) on the subscription's topic once a transaction
+//! commits (see `LIVE/SUBSCRIBE` and `LIVETOPIC` in the engine).
+
+use std::collections::BTreeSet;
+
+use query::Query;
+use view::View;
+use materialized::Materialized;
+use encoding::{self, Encode, Decode};
+use execution::{Executor, FactSource, Error, Row};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Delta {
+    pub added: Vec<Row>,
+    pub removed: Vec<Row>,
+}
+
+impl Delta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Encode for Delta {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        self.added.encode(buf);
+        self.removed.encode(buf);
+    }
+}
+
+impl Decode for Delta {
+    fn decode(buf: &mut &[u8]) -> Result<Self, encoding::Error> {
+        let added = Vec::<Row>::decode(buf)?;
+        Ok(Delta { added, removed: Vec::<Row>::decode(buf)? })
+    }
+}
+
+pub type SubscriptionId = u64;
+
+struct Subscription<T : AsRef<[u8]> + Clone> {
+    id: SubscriptionId,
+    view: Materialized<T>,
+    rows: BTreeSet<Row>,
+}
+
+impl<T : AsRef<[u8]> + Clone> Subscription<T> {
+    /// Rows added and removed since the last delta, if any
+    fn delta(&mut self) -> Option<Delta> {
+        let rows: BTreeSet<Row> = self.view.rows().into_iter().collect();
+        let delta = Delta {
+            added: rows.difference(&self.rows).cloned().collect(),
+            removed: self.rows.difference(&rows).cloned().collect(),
+        };
+        if delta.is_empty() {
+            None
+        } else {
+            self.rows = rows;
+            Some(delta)
+        }
+    }
+}
+
+pub struct Subscriptions<T : AsRef<[u8]> + Clone> {
+    subscriptions: Vec<Subscription<T>>,
+    next: SubscriptionId,
+}
+
+impl<T : AsRef<[u8]> + Clone> Default for Subscriptions<T> {
+    fn default() -> Self {
+        Subscriptions::new()
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> Subscriptions<T> {
+    pub fn new() -> Self {
+        Subscriptions { subscriptions: vec![], next: 0 }
+    }
+
+    /// Subscribes to the query, returning the subscription and the
+    /// initial result set
+    pub fn subscribe<S : FactSource>(&mut self, executor: &Executor<S>, name: T, query: Query<T>)
+                                     -> Result<(SubscriptionId, Vec<Row>), Error<T>> {
+        let mut view = Materialized::new(View { name, version: vec![], query });
+        view.rebuild(executor)?;
+        let rows = view.rows();
+        self.next += 1;
+        self.subscriptions.push(Subscription { id: self.next, view, rows: rows.iter().cloned().collect() });
+        Ok((self.next, rows))
+    }
+
+    /// Attributes the subscribed queries read, in bytewise order
+    pub fn attributes(&self) -> Vec<Vec<u8>> {
+        let attributes: BTreeSet<Vec<u8>> = self.subscriptions.iter()
+                                                .flat_map(|s| s.view.view().query.attributes()).collect();
+        attributes.into_iter().collect()
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let len = self.subscriptions.len();
+        self.subscriptions.retain(|s| s.id != id);
+        self.subscriptions.len() != len
+    }
+
+    /// Deltas of the subscriptions whose results changed after values have
+    /// been attached to facts under attributes, as `(fact, attribute)`s (say,
+    /// by one transaction), at most one per subscription
+    pub fn attached<S : FactSource>(&mut self, executor: &Executor<S>, attachments: &[(&[u8], &[u8])])
+                                    -> Result<Vec<(SubscriptionId, Delta)>, Error<T>> {
+        let mut deltas = vec![];
+        for subscription in self.subscriptions.iter_mut() {
+            subscription.view.attached(executor, attachments)?;
+            if let Some(delta) = subscription.delta() {
+                deltas.push((subscription.id, delta));
+            }
+        }
+        Ok(deltas)
+    }
+
+    /// Deltas of the subscriptions with a sliding window whose results
+    /// changed as time passed (see `Materialized::tick`)
+    pub fn tick<S : FactSource>(&mut self, executor: &Executor<S>) -> Result<Vec<(SubscriptionId, Delta)>, Error<T>> {
+        let mut deltas = vec![];
+        for subscription in self.subscriptions.iter_mut().filter(|s| s.view.sliding()) {
+            subscription.view.tick(executor)?;
+            if let Some(delta) = subscription.delta() {
+                deltas.push((subscription.id, delta));
+            }
+        }
+        Ok(deltas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query};
+    use Condition::Equal;
+    use query::Aggregate;
+    use encoding::{Encode, Decode};
+    use execution::Executor;
+    use execution::tests::{Facts, people, fact_type};
+    use live::{Subscriptions, Delta};
+    use time::{Encoding, Window};
+
+    #[test]
+    pub fn deltas() {
+        let Facts(mut facts) = people();
+        let query = Query::new(Condition::fact(fact_type("NameChanged")
+                                               .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                               .and(Equal(Value::Attribute("#timestamp"), Value::Binding("Timestamp")))))
+                    .select("Person").select_aggregate(Aggregate::Max, "Timestamp");
+        let mut subscriptions = Subscriptions::new();
+        let (id, rows) = subscriptions.subscribe(&Executor::new(&Facts(facts.clone())), "profile", query).unwrap();
+        assert_eq!(rows, vec![vec![b"alice".to_vec(), b"3".to_vec()], vec![b"bob".to_vec(), b"2".to_vec()]]);
+
+        facts.push(("6", vec![("#factType", "NameChanged"), ("#object", "bob"), ("#timestamp", "4")]));
+        let source = Facts(facts.clone());
+        let deltas = subscriptions.attached(&Executor::new(&source), &[(b"6", b"#timestamp")]).unwrap();
+        let delta = Delta {
+            added: vec![vec![b"bob".to_vec(), b"4".to_vec()]],
+            removed: vec![vec![b"bob".to_vec(), b"2".to_vec()]],
+        };
+        assert_eq!(deltas, vec![(id, delta.clone())]);
+        assert_eq!(Delta::from_bytes(&delta.to_bytes()), Ok(delta));
+        assert!(subscriptions.attached(&Executor::new(&source), &[(b"6", b"#timestamp")]).unwrap().is_empty());
+        assert_eq!(subscriptions.attributes(), vec![b"#factType".to_vec(), b"#object".to_vec(), b"#timestamp".to_vec()]);
+        assert!(subscriptions.unsubscribe(id));
+        assert!(subscriptions.attributes().is_empty());
+    }
+
+    #[test]
+    pub fn sliding() {
+        let query = Query::new(Condition::fact(fact_type("NameChanged")
+                                               .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                               .and(Equal(Value::Attribute("#timestamp"), Value::Binding("Timestamp")))))
+                    .select("Person").window(Window::last("Timestamp", Encoding::Decimal, 1));
+        let facts = people();
+        let mut subscriptions = Subscriptions::new();
+        let (id, rows) = subscriptions.subscribe(&Executor::new(&facts).at(3), "recent", query).unwrap();
+        assert_eq!(rows, vec![vec![b"alice".to_vec()], vec![b"bob".to_vec()]]);
+        assert!(subscriptions.tick(&Executor::new(&facts).at(3)).unwrap().is_empty());
+        let delta = Delta { added: vec![], removed: vec![vec![b"bob".to_vec()]] };
+        assert_eq!(subscriptions.tick(&Executor::new(&facts).at(4)).unwrap(), vec![(id, delta)]);
+    }
+}
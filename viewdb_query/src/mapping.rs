@@ -0,0 +1,112 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Result rows as typed values.
+//!
+//! A `FromRow` type (usually `#[derive(FromRow)]`, see `viewdb_derive`)
+//! names the bindings it is made of; `rows` finds them among a query's
+//! projected columns, so the type doesn't depend on the projection order.
+
+use query::Query;
+use execution::Row;
+use time::Encoding;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Binding is not projected by the query
+    NotProjected(&'static str),
+    /// Binding's value doesn't decode into the field's type
+    Invalid(&'static str),
+}
+
+pub trait FromValue : Sized {
+    fn from_value(value: &[u8]) -> Option<Self>;
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(value: &[u8]) -> Option<Self> {
+        Some(value.to_vec())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &[u8]) -> Option<Self> {
+        String::from_utf8(value.to_vec()).ok()
+    }
+}
+
+/// Big-endian, as aggregated counts are
+impl FromValue for u64 {
+    fn from_value(value: &[u8]) -> Option<Self> {
+        Encoding::BigEndian.decode(value)
+    }
+}
+
+pub trait FromRow : Sized {
+    /// Bindings of the fields, in order
+    fn bindings() -> &'static [&'static str];
+    /// Value from the bindings' values, in the order of `bindings()`
+    fn from_row(values: &[&[u8]]) -> Result<Self, Error>;
+}
+
+/// Rows of the query's results, as `R`s
+pub fn rows<R : FromRow, T : AsRef<[u8]> + Clone>(query: &Query<T>, rows: &[Row]) -> Result<Vec<R>, Error> {
+    let mut columns = vec![];
+    for binding in R::bindings() {
+        match query.projection.iter().position(|p| p.binding().as_ref() == binding.as_bytes()) {
+            Some(column) => columns.push(column),
+            None => return Err(Error::NotProjected(binding)),
+        }
+    }
+    rows.iter()
+        .map(|row| R::from_row(&columns.iter().map(|&c| row[c].as_slice()).collect::<Vec<_>>()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query};
+    use Condition::Equal;
+    use query::Aggregate;
+    use execution::Executor;
+    use execution::tests::{people, fact_type};
+    use mapping::{self, FromRow, FromValue, Error};
+
+    #[derive(Debug, PartialEq)]
+    struct Changes {
+        person: String,
+        count: u64,
+    }
+
+    impl FromRow for Changes {
+        fn bindings() -> &'static [&'static str] {
+            &["Person", "Name"]
+        }
+
+        fn from_row(values: &[&[u8]]) -> Result<Self, Error> {
+            Ok(Changes {
+                person: FromValue::from_value(values[0]).ok_or(Error::Invalid("Person"))?,
+                count: FromValue::from_value(values[1]).ok_or(Error::Invalid("Name"))?,
+            })
+        }
+    }
+
+    #[test]
+    pub fn mapping() {
+        let facts = people();
+        let query = Query::new(Condition::fact(fact_type("NameChanged")
+                                               .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                               .and(Equal(Value::Attribute("#value"), Value::Binding("Name")))))
+                    .select_aggregate(Aggregate::Count, "Name").select("Person");
+        let rows = Executor::new(&facts).execute(&query).unwrap();
+        assert_eq!(mapping::rows::<Changes, _>(&query, &rows).unwrap(),
+                   vec![Changes { person: "alice".into(), count: 2 }, Changes { person: "bob".into(), count: 1 }]);
+        assert_eq!(mapping::rows::<Changes, _>(&query.clone().select("Other"), &[]), Ok(vec![]));
+        let query = Query::new(Condition::True).select("Person");
+        assert_eq!(mapping::rows::<Changes, _>(&query, &rows), Err(Error::NotProjected("Name")));
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Incrementally maintained (materialized) views.
+//!
+//! Facts are append-only, so the solutions of a view without negation or
+//! rules only ever grow. When an attachment is added to a fact, every new
+//! solution involves that fact in one of the view's fact scopes; the view
+//! is maintained by evaluating it once per fact scope with that scope
+//! pinned to the fact (the delta rule for joins). `MIN` and `MAX` keep the
+//! distinct values of every group, so absorbing a solution twice doesn't
+//! change the result. `COUNT` counts every solution, and a solution that
+//! is found again can't be told apart from a new one, so the groups the
+//! new solutions fall in are counted again instead, with the group's
+//! bindings bound (unless a group is keyed by a time bucket). Other views
+//! are rebuilt on every relevant change. A view with a sliding window (see
+//! `Window::last`) is also rebuilt as time passes, on every `tick`; until
+//! then, it may still have solutions that have since left the window.
+//!
+//! In ViewDB, the engine maintains the views it's asked to materialize
+//! (see `MATERIALIZE`), storing every group as a separate key, with
+//! `entries`, `changes` and `load` converting to and from them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use condition::Condition;
+use query::{Projection, Aggregate};
+use view::View;
+use encoding::{self, Encode, Decode};
+use execution::{Executor, FactSource, Error, Row, Env, column, encode_count};
+
+/// Fact scopes of a condition, and whether it can be maintained incrementally
+struct Scopes<'a, T : AsRef<[u8]> + Clone + 'a> {
+    scopes: Vec<&'a Condition<T>>,
+    monotone: bool,
+}
+
+impl<'a, T : AsRef<[u8]> + Clone> Scopes<'a, T> {
+    fn of(condition: &'a Condition<T>) -> Self {
+        let mut scopes = Scopes { scopes: vec![], monotone: true };
+        scopes.collect(condition);
+        scopes
+    }
+
+    fn collect(&mut self, condition: &'a Condition<T>) {
+        match *condition {
+            Condition::Fact(ref c) => {
+                self.scopes.push(condition);
+                self.collect(c);
+            },
+            Condition::Not(_) | Condition::Rule(_, _) => self.monotone = false,
+            Condition::And(ref c1, ref c2) | Condition::Or(ref c1, ref c2) => {
+                self.collect(c1);
+                self.collect(c2);
+            },
+            Condition::Trait(_, ref c) => self.collect(c),
+            _ => (),
+        }
+    }
+}
+
+pub struct Materialized<T : AsRef<[u8]> + Clone> {
+    view: View<T>,
+    /// Values of the projected bindings → sorted values of every aggregate
+    /// (distinct, except for `COUNT`)
+    groups: BTreeMap<Row, Vec<Vec<Vec<u8>>>>,
+    /// Groups changed since the last `changes`
+    changed: BTreeSet<Row>,
+}
+
+impl<T : AsRef<[u8]> + Clone> Materialized<T> {
+    /// Empty materialization of the view (see `rebuild`)
+    pub fn new(view: View<T>) -> Self {
+        Materialized { view, groups: BTreeMap::new(), changed: BTreeSet::new() }
+    }
+
+    pub fn view(&self) -> &View<T> {
+        &self.view
+    }
+
+    /// Whether the view is maintained incrementally, as opposed to being
+    /// rebuilt on every relevant change
+    pub fn incremental(&self) -> bool {
+        let query = &self.view.query;
+        query.views.is_empty() && Scopes::of(&query.condition).monotone &&
+        !(self.counts() && query.projection.iter().any(|p| matches!(*p, Projection::Bucket(_, _, _))))
+    }
+
+    /// Whether the view has a `COUNT`, so that its groups have to be
+    /// counted again as they change
+    fn counts(&self) -> bool {
+        self.view.query.projection.iter().any(|p| matches!(*p, Projection::Aggregate(Aggregate::Count, _)))
+    }
+
+    /// Whether the view has to be brought up to date as time passes
+    pub fn sliding(&self) -> bool {
+        self.view.query.sliding()
+    }
+
+    /// Brings the view up to date as time passes (every second, say),
+    /// if it has a sliding window
+    pub fn tick<S : FactSource>(&mut self, executor: &Executor<S>) -> Result<(), Error<T>> {
+        if self.sliding() {
+            self.rebuild(executor)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Values of the projected bindings (and buckets) of a solution
+    fn key(&self, env: &Env<T>) -> Row {
+        self.view.query.projection.iter().filter_map(|p| match *p {
+            Projection::Aggregate(_, _) => None,
+            _ => Some(column(p, env)),
+        }).collect()
+    }
+
+    fn absorb(&mut self, envs: Vec<Env<T>>) {
+        for env in envs.iter() {
+            let key = self.key(env);
+            self.changed.insert(key.clone());
+            let projection = &self.view.query.projection;
+            let aggregates = projection.iter().filter(|p| matches!(**p, Projection::Aggregate(_, _))).count();
+            let group = self.groups.entry(key).or_insert_with(|| vec![vec![]; aggregates]);
+            let aggregated = projection.iter().filter_map(|p| match *p {
+                Projection::Aggregate(aggregate, _) => Some((aggregate, column(p, env))),
+                _ => None,
+            });
+            for (values, (aggregate, value)) in group.iter_mut().zip(aggregated) {
+                match values.binary_search(&value) {
+                    Ok(_) if aggregate != Aggregate::Count => (),
+                    Ok(i) | Err(i) => values.insert(i, value),
+                }
+            }
+        }
+    }
+
+    /// Re-evaluates the view over all facts, e.g. after its definition
+    /// has changed
+    pub fn rebuild<S : FactSource>(&mut self, executor: &Executor<S>) -> Result<(), Error<T>> {
+        let envs = executor.solutions(&self.view.query, None)?;
+        self.changed.extend(self.groups.keys().cloned());
+        self.groups.clear();
+        self.absorb(envs);
+        Ok(())
+    }
+
+    /// Counts the groups again (see `Materialized`)
+    fn recount<S : FactSource>(&mut self, executor: &Executor<S>, groups: BTreeSet<Row>) -> Result<(), Error<T>> {
+        for key in groups {
+            let env: Env<T> = self.view.query.projection.iter()
+                                  .filter(|p| !matches!(**p, Projection::Aggregate(_, _)))
+                                  .map(|p| p.binding().clone()).zip(key.iter().cloned()).collect();
+            let envs = executor.solutions_from(&self.view.query, env)?;
+            self.groups.remove(&key);
+            self.absorb(envs);
+        }
+        Ok(())
+    }
+
+    /// Brings the view up to date after values have been attached to facts
+    /// under attributes, as `(fact, attribute)`s (say, by one transaction),
+    /// rebuilding it at most once
+    pub fn attached<S : FactSource>(&mut self, executor: &Executor<S>, attachments: &[(&[u8], &[u8])])
+                                    -> Result<(), Error<T>> {
+        let attributes = self.view.query.attributes();
+        let mut facts: Vec<&[u8]> = attachments.iter()
+                                               .filter(|&&(_, a)| attributes.iter().any(|r| r.as_slice() == a))
+                                               .map(|&(f, _)| f).collect();
+        if facts.is_empty() {
+            return Ok(());
+        }
+        if !self.incremental() {
+            return self.rebuild(executor);
+        }
+        facts.sort();
+        facts.dedup();
+        let mut envs = vec![];
+        {
+            let query = &self.view.query;
+            for scope in Scopes::of(&query.condition).scopes {
+                for fact in facts.iter() {
+                    envs.extend(executor.solutions(query, Some((scope, fact)))?);
+                }
+            }
+        }
+        if self.counts() {
+            let groups = envs.iter().map(|env| self.key(env)).collect();
+            self.recount(executor, groups)
+        } else {
+            self.absorb(envs);
+            Ok(())
+        }
+    }
+
+    /// Entries (see `entries`) of the groups changed since the last call,
+    /// with `None` for those that are gone
+    pub fn changes(&mut self) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        let changed = ::std::mem::take(&mut self.changed);
+        changed.into_iter().map(|key| {
+            let group = self.groups.get(&key).map(|group| group.to_bytes());
+            (key.to_bytes(), group)
+        }).collect()
+    }
+
+    /// Encoded groups (projected bindings → aggregated values)
+    pub fn entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.groups.iter().map(|(key, group)| (key.to_bytes(), group.to_bytes())).collect()
+    }
+
+    /// Materialization of the view from previously stored `entries`
+    pub fn load<'e, I>(view: View<T>, entries: I) -> Result<Self, encoding::Error>
+        where I : IntoIterator<Item = (&'e [u8], &'e [u8])> {
+        let mut groups = BTreeMap::new();
+        for (key, group) in entries {
+            groups.insert(Row::from_bytes(key)?, Vec::<Row>::from_bytes(group)?);
+        }
+        Ok(Materialized { view, groups, changed: BTreeSet::new() })
+    }
+
+    pub fn rows(&self) -> Vec<Row> {
+        self.groups.iter().map(|(key, group)| {
+            let mut key = key.iter().cloned();
+            let mut group = group.iter();
+            self.view.query.projection.iter().map(|p| match *p {
+                Projection::Aggregate(aggregate, _) => {
+                    let values = group.next().unwrap();
+                    match aggregate {
+                        Aggregate::Count => encode_count(values.len()),
+                        Aggregate::Min => values.first().cloned().unwrap_or_default(),
+                        Aggregate::Max => values.last().cloned().unwrap_or_default(),
+                    }
+                },
+                _ => key.next().unwrap_or_default(),
+            }).collect()
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query, View};
+    use Condition::Equal;
+    use query::Aggregate;
+    use execution::Executor;
+    use execution::tests::{Facts, people, fact_type};
+    use materialized::Materialized;
+
+    fn names(aggregate: Aggregate) -> View<&'static str> {
+        let query = Query::new(Condition::fact(fact_type("NameChanged")
+                                               .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                               .and(Equal(Value::Attribute("#value"), Value::Binding("Name")))))
+                    .select("Person").select_aggregate(aggregate, "Name");
+        View { name: "names", version: vec![0], query }
+    }
+
+    #[test]
+    pub fn maintenance() {
+        let Facts(mut facts) = people();
+        let mut view = Materialized::new(names(Aggregate::Max));
+        assert!(view.incremental());
+        view.rebuild(&Executor::new(&Facts(facts.clone()))).unwrap();
+
+        facts.push(("6", vec![("#factType", "NameChanged"), ("#object", "bob")]));
+        let source = Facts(facts.clone());
+        view.attached(&Executor::new(&source), &[(b"6", b"#object")]).unwrap();
+        facts[5].1.push(("#value", "Robert"));
+        let source = Facts(facts.clone());
+        view.attached(&Executor::new(&source), &[(b"6", b"#value"), (b"6", b"#unrelated")]).unwrap();
+
+        let rows = view.rows();
+        view.rebuild(&Executor::new(&source)).unwrap();
+        assert_eq!(rows, view.rows());
+        let entries = view.entries();
+        let loaded = Materialized::load(names(Aggregate::Max), entries.iter().map(|(k, v)| (k.as_slice(), v.as_slice())));
+        assert_eq!(loaded.unwrap().rows(), rows);
+        assert_eq!(rows, vec![vec![b"alice".to_vec(), b"Alicia".to_vec()], vec![b"bob".to_vec(), b"Robert".to_vec()]]);
+
+        let mut counts = Materialized::new(names(Aggregate::Count));
+        assert!(counts.incremental());
+        counts.rebuild(&Executor::new(&people())).unwrap();
+        assert_eq!(counts.changes().len(), 2);
+        // attaching to a fact that is already part of a solution doesn't
+        // count it twice
+        counts.attached(&Executor::new(&source), &[(b"6", b"#object"), (b"6", b"#value"), (b"1", b"#value")]).unwrap();
+        counts.attached(&Executor::new(&source), &[(b"6", b"#value")]).unwrap();
+        assert_eq!(counts.rows(), vec![vec![b"alice".to_vec(), vec![0, 0, 0, 0, 0, 0, 0, 2]],
+                                       vec![b"bob".to_vec(), vec![0, 0, 0, 0, 0, 0, 0, 2]]]);
+        let changes = counts.changes();
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|(_, group)| group.is_some()));
+        assert!(counts.changes().is_empty());
+    }
+}
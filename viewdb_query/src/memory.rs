@@ -0,0 +1,244 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! In-memory reference engine.
+//!
+//! `Memory` records facts and attribute attachments the way ViewDB does,
+//! without a storage environment: attachments committed together share a
+//! TXID, and TXIDs (synthetic, 8-byte big-endian counters) sort in commit
+//! order. Queries are resolved against its views, processed with its
+//! traits and executed over its facts, so views can be tested without
+//! LMDB, and the engine's results can be checked against it. Changes
+//! committed elsewhere can be replayed into it (see `replay`).
+
+use std::collections::BTreeSet;
+
+use {Trait, TraitResolver};
+use condition::processing::{self, Pipeline, TraitsExpansion, PresentEqualCompaction,
+                            ComparisonSuppression, BooleanLiteralSuppression, ImplicitFact};
+use query::Query;
+use view::{self, Views};
+use execution::{self, Executor, FactSource, Attachment, Row};
+use feed::{Change, ChangeSource};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// Fact has already been recorded
+    DuplicateFact(Vec<u8>),
+    /// Attachment to a fact that hasn't been recorded
+    UnknownFact(Vec<u8>),
+    Processing(processing::Error<Vec<u8>>),
+    View(view::Error<Vec<u8>>),
+    Execution(execution::Error<Vec<u8>>),
+}
+
+impl From<processing::Error<Vec<u8>>> for Error {
+    fn from(error: processing::Error<Vec<u8>>) -> Self {
+        Error::Processing(error)
+    }
+}
+
+impl From<view::Error<Vec<u8>>> for Error {
+    fn from(error: view::Error<Vec<u8>>) -> Self {
+        Error::View(error)
+    }
+}
+
+impl From<execution::Error<Vec<u8>>> for Error {
+    fn from(error: execution::Error<Vec<u8>>) -> Self {
+        Error::Execution(error)
+    }
+}
+
+pub struct Memory {
+    facts: Vec<Vec<u8>>,
+    /// Every attachment, in TXID order
+    changes: Vec<Change>,
+    traits: Vec<(Vec<u8>, Trait<Vec<u8>>)>,
+    views: Views<Vec<u8>>,
+    txid: u64,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::new()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory { facts: vec![], changes: vec![], traits: vec![], views: Views::new(), txid: 0 }
+    }
+
+    /// Records a fact (see `viewdb_core::Fact::identifier`)
+    pub fn record(&mut self, fact: &[u8]) -> Result<(), Error> {
+        if self.facts.iter().any(|f| f.as_slice() == fact) {
+            return Err(Error::DuplicateFact(fact.to_vec()));
+        }
+        self.facts.push(fact.to_vec());
+        Ok(())
+    }
+
+    /// Attaches `(fact, attribute, value)`s in one transaction (none of
+    /// them if any fact is unknown), returning its TXID
+    pub fn commit(&mut self, attachments: &[(&[u8], &[u8], &[u8])]) -> Result<Vec<u8>, Error> {
+        if let Some(&(fact, _, _)) = attachments.iter().find(|&&(f, _, _)| !self.facts.iter().any(|r| r.as_slice() == f)) {
+            return Err(Error::UnknownFact(fact.to_vec()));
+        }
+        self.txid += 1;
+        let txid = self.txid.to_be_bytes().to_vec();
+        for &(fact, attribute, value) in attachments {
+            self.changes.push(Change {
+                fact: fact.to_vec(),
+                attribute: attribute.to_vec(),
+                value: value.to_vec(),
+                txid: txid.clone(),
+            });
+        }
+        Ok(txid)
+    }
+
+    /// Records a change committed elsewhere, with its TXID, recording its
+    /// fact first if need be
+    pub fn replay(&mut self, change: Change) {
+        if !self.facts.contains(&change.fact) {
+            self.facts.push(change.fact.clone());
+        }
+        // changes of concurrent transactions can arrive out of order
+        let position = self.changes.iter().rposition(|c| c.txid <= change.txid).map(|i| i + 1).unwrap_or(0);
+        self.changes.insert(position, change);
+    }
+
+    /// Attaches a value in a transaction of its own
+    pub fn attach(&mut self, fact: &[u8], attribute: &[u8], value: &[u8]) -> Result<Vec<u8>, Error> {
+        self.commit(&[(fact, attribute, value)])
+    }
+
+    /// Defines the trait, replacing its previous definition
+    pub fn define_trait(&mut self, name: &[u8], trait_: Trait<Vec<u8>>) {
+        self.traits.retain(|(n, _)| n.as_slice() != name);
+        self.traits.push((name.to_vec(), trait_));
+    }
+
+    pub fn views(&mut self) -> &mut Views<Vec<u8>> {
+        &mut self.views
+    }
+
+    pub fn query(&self, query: Query<Vec<u8>>) -> Result<Vec<Row>, Error> {
+        let query = view::resolve(query, &self.views)?;
+        let pipeline = Pipeline::new()
+                       .with(TraitsExpansion::new(self))
+                       .with(PresentEqualCompaction)
+                       .with(ComparisonSuppression)
+                       .with(BooleanLiteralSuppression)
+                       .with(ImplicitFact);
+        let query = query.process(&pipeline)?;
+        Ok(Executor::new(self).execute(&query)?)
+    }
+}
+
+impl TraitResolver<Vec<u8>> for Memory {
+    fn resolve(&self, name: Vec<u8>) -> Option<&Trait<Vec<u8>>> {
+        self.traits.iter().find(|&(n, _)| *n == name).map(|(_, t)| t)
+    }
+}
+
+impl FactSource for Memory {
+    fn facts(&self) -> Vec<Vec<u8>> {
+        self.facts.clone()
+    }
+
+    fn facts_with(&self, attribute: &[u8], value: Option<&[u8]>) -> Vec<Vec<u8>> {
+        self.facts.iter()
+            .filter(|&f| self.changes.iter().any(|c| c.fact == *f && c.attribute.as_slice() == attribute &&
+                                                      value.map(|v| c.value.as_slice() == v).unwrap_or(true)))
+            .cloned().collect()
+    }
+
+    fn attachments(&self, fact: &[u8], attribute: &[u8]) -> Vec<Attachment> {
+        self.changes.iter()
+            .filter(|c| c.fact.as_slice() == fact && c.attribute.as_slice() == attribute)
+            .map(|c| Attachment { value: c.value.clone(), txid: c.txid.clone() })
+            .collect()
+    }
+    fn values(&self, attribute: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let values: BTreeSet<_> = self.changes.iter().filter(|c| c.attribute.as_slice() == attribute)
+                                      .map(|c| c.value.clone()).collect();
+        Some(values.into_iter().collect())
+    }
+}
+
+impl ChangeSource for Memory {
+    fn changes(&self, txid: Option<&[u8]>) -> Vec<Change> {
+        self.changes.iter().filter(|c| txid.map(|t| c.txid.as_slice() > t).unwrap_or(true)).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use Query;
+    use condition::processing;
+    use execution::FactSource;
+    use feed::{Change, ChangeSource};
+    use memory::{Memory, Error};
+
+    fn memory() -> Memory {
+        let mut memory = Memory::new();
+        memory.define_trait(b"NameChanged", vec![(b"#factType".to_vec(), Some(b"NameChanged".to_vec())).into(),
+                                                 (b"#object".to_vec(), None).into(),
+                                                 (b"#value".to_vec(), None).into()].into());
+        for &(fact, object, value) in [(b"1", "alice", "Alice"), (b"2", "bob", "Bob"), (b"3", "alice", "Alicia")].iter() {
+            memory.record(fact).unwrap();
+            memory.commit(&[(fact, b"#factType", b"NameChanged"), (fact, b"#object", object.as_bytes()),
+                            (fact, b"#value", value.as_bytes())]).unwrap();
+        }
+        memory
+    }
+
+    #[test]
+    pub fn record() {
+        let mut memory = memory();
+        assert_eq!(memory.record(b"1"), Err(Error::DuplicateFact(b"1".to_vec())));
+        assert_eq!(memory.attach(b"4", b"#value", b"Robert"), Err(Error::UnknownFact(b"4".to_vec())));
+        assert_eq!(memory.attach(b"2", b"#value", b"Robert"), Ok(vec![0, 0, 0, 0, 0, 0, 0, 4]));
+        let values = memory.attachments(b"2", b"#value").into_iter().map(|a| (a.value, a.txid)).collect::<Vec<_>>();
+        assert_eq!(values, vec![(b"Bob".to_vec(), vec![0, 0, 0, 0, 0, 0, 0, 2]),
+                                (b"Robert".to_vec(), vec![0, 0, 0, 0, 0, 0, 0, 4])]);
+        assert_eq!(memory.changes(Some(&[0, 0, 0, 0, 0, 0, 0, 3])).len(), 1);
+        assert_eq!(memory.facts_with(b"#object", Some(b"alice")), vec![b"1".to_vec(), b"3".to_vec()]);
+    }
+
+    #[test]
+    pub fn replay() {
+        let change = |fact: &[u8], value: &[u8], txid: u8| Change {
+            fact: fact.to_vec(), attribute: b"#value".to_vec(), value: value.to_vec(), txid: vec![txid],
+        };
+        let mut memory = Memory::new();
+        memory.replay(change(b"1", b"Alice", 1));
+        memory.replay(change(b"2", b"Bob", 3));
+        memory.replay(change(b"1", b"Alicia", 2));
+        assert_eq!(memory.facts(), vec![b"1".to_vec(), b"2".to_vec()]);
+        assert_eq!(memory.changes(None), vec![change(b"1", b"Alice", 1), change(b"1", b"Alicia", 2),
+                                              change(b"2", b"Bob", 3)]);
+        assert_eq!(memory.record(b"2"), Err(Error::DuplicateFact(b"2".to_vec())));
+    }
+
+    #[test]
+    pub fn query() {
+        let mut memory = memory();
+        let names: Query<Vec<u8>> = "SELECT ?Person, ?Name WHERE NameChanged(<#object> = ?Person AND <#value> = ?Name)"
+                                    .parse().unwrap();
+        memory.views().create(b"Names".to_vec(), names.clone()).unwrap();
+        let rows = memory.query(names).unwrap();
+        assert_eq!(rows, vec![vec![b"alice".to_vec(), b"Alice".to_vec()], vec![b"bob".to_vec(), b"Bob".to_vec()],
+                              vec![b"alice".to_vec(), b"Alicia".to_vec()]]);
+        let query = "SELECT ?Name WHERE RULE Names(\"alice\", ?Name)".parse().unwrap();
+        assert_eq!(memory.query(query).unwrap(), vec![vec![b"Alice".to_vec()], vec![b"Alicia".to_vec()]]);
+        let query = "SELECT ?Name WHERE Unknown(<#value> = ?Name)".parse().unwrap();
+        assert_eq!(memory.query(query), Err(Error::Processing(processing::Error::UnknownTrait(b"Unknown".to_vec()))));
+    }
+}
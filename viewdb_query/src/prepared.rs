@@ -0,0 +1,150 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Prepared, parameterized queries.
+//!
+//! A query is processed and validated once, with its `Value::Parameter`s
+//! left in place (processors treat them as opaque values, and so does
+//! binding analysis); every execution then
+//! substitutes the supplied arguments as data. An unsupplied parameter
+//! fails the execution with `Error::UnboundParameter`.
+
+use condition::{Condition, Value};
+use condition::fold::Fold;
+use condition::visit::{self, Visitor};
+use condition::cache;
+use condition::processing::Processor;
+use query::Query;
+use execution::{Executor, FactSource, Error, Row, Cursor, Page};
+
+struct Parameters<T : AsRef<[u8]> + Clone>(Vec<T>);
+
+impl<T : AsRef<[u8]> + Clone> Visitor<T> for Parameters<T> {
+    fn visit_parameter(&mut self, parameter: &T) {
+        if !self.0.iter().any(|p| p.as_ref() == parameter.as_ref()) {
+            self.0.push(parameter.clone());
+        }
+    }
+}
+
+struct Substitution<'a, T : AsRef<[u8]> + Clone + 'a> {
+    arguments: &'a [(T, T)],
+    unbound: Option<T>,
+}
+
+impl<'a, T : AsRef<[u8]> + Clone> Fold<T> for Substitution<'a, T> {
+    fn fold_parameter(&mut self, parameter: T) -> Value<T> {
+        match self.arguments.iter().find(|&(p, _)| p.as_ref() == parameter.as_ref()) {
+            Some((_, value)) => Value::Data(value.clone()),
+            None => {
+                self.unbound = self.unbound.take().or_else(|| Some(parameter.clone()));
+                Value::Parameter(parameter)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Prepared<T : AsRef<[u8]> + Clone> {
+    query: Query<T>,
+    parameters: Vec<T>,
+}
+
+impl<T : AsRef<[u8]> + Clone> Prepared<T> {
+    /// Processes the query (see `Query::process`) and validates it, failing
+    /// like `Cache::compile`
+    pub fn new<P : Processor<T>>(processor: &P, query: Query<T>) -> Result<Self, cache::Error<T>> {
+        let query = query.process(processor)?;
+        query.validate().map_err(cache::Error::Invalid)?;
+        let mut parameters = Parameters(vec![]);
+        parameters.visit_condition(&query.condition);
+        for rule in query.rules.iter() {
+            parameters.visit_condition(&rule.body);
+        }
+        Ok(Prepared { query, parameters: parameters.0 })
+    }
+
+    pub fn query(&self) -> &Query<T> {
+        &self.query
+    }
+
+    /// Parameters the query refers to, in order of appearance
+    pub fn parameters(&self) -> &[T] {
+        &self.parameters
+    }
+
+    /// Query with the parameters replaced by the arguments' values
+    pub fn bind(&self, arguments: &[(T, T)]) -> Result<Query<T>, Error<T>> {
+        let mut substitution = Substitution { arguments, unbound: None };
+        let mut query = self.query.clone();
+        query.condition = substitution.fold_condition(query.condition);
+        for rule in query.rules.iter_mut() {
+            rule.body = substitution.fold_condition(rule.body.clone());
+        }
+        match substitution.unbound {
+            Some(parameter) => Err(Error::UnboundParameter(parameter)),
+            None => Ok(query),
+        }
+    }
+
+    pub fn execute<S : FactSource>(&self, executor: &Executor<S>, arguments: &[(T, T)]) -> Result<Vec<Row>, Error<T>> {
+        executor.execute(&self.bind(arguments)?)
+    }
+
+    pub fn page<S : FactSource>(&self, executor: &Executor<S>, arguments: &[(T, T)], cursor: Option<&Cursor>)
+                                -> Result<Page, Error<T>> {
+        executor.page(&self.bind(arguments)?, cursor)
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> Condition<T> {
+    /// Whether the condition refers to any parameter
+    pub fn is_parameterized(&self) -> bool {
+        let mut parameters = Parameters(vec![]);
+        visit::visit_condition(&mut parameters, self);
+        !parameters.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query, View};
+    use Condition::Equal;
+    use condition::cache;
+    use condition::analysis::Problem;
+    use condition::processing::{Pipeline, ImplicitFact, ComparisonSuppression};
+    use execution::{Executor, Error};
+    use execution::tests::{people, fact_type};
+    use prepared::Prepared;
+
+    #[test]
+    pub fn prepared() {
+        let facts = people();
+        let executor = Executor::new(&facts);
+        let cond = fact_type("NameChanged")
+                   .and(Equal(Value::Attribute("#object"), Value::Parameter("PersonId")))
+                   .and(Equal(Value::Attribute("#value"), Value::Binding("Name")));
+        assert!(cond.is_parameterized());
+        let pipeline = Pipeline::new().with(ComparisonSuppression).with(ImplicitFact);
+        let prepared = Prepared::new(&pipeline, Query::new(cond).select("Name")).unwrap();
+        assert_eq!(prepared.parameters(), &["PersonId"]);
+        assert!(matches!(prepared.query().condition, Condition::Fact(_)));
+
+        assert_eq!(prepared.execute(&executor, &[("PersonId", "bob")]).unwrap(), vec![vec![b"Bob".to_vec()]]);
+        assert_eq!(prepared.execute(&executor, &[("PersonId", "alice")]).unwrap().len(), 2);
+        assert_eq!(prepared.execute(&executor, &[]), Err(Error::UnboundParameter("PersonId")));
+
+        let query = Query::new(Condition::rule("names", vec![Value::Binding("Name")])).select("Name");
+        let names = Query::new(Equal(Value::Attribute("#value"), Value::Binding("Name"))).select("Name");
+        let mut with_view = query.clone();
+        with_view.views.push(View { name: "names", version: vec![1], query: names });
+        let prepared = Prepared::new(&pipeline, with_view).unwrap();
+        assert!(matches!(prepared.query().views[0].query.condition, Condition::Fact(_)));
+        assert_eq!(Prepared::new(&pipeline, query.select("Email")).unwrap_err(),
+                   cache::Error::Invalid(vec![Problem::Unbound("Email")]));
+    }
+}
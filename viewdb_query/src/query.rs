@@ -0,0 +1,242 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeSet;
+
+use condition::Condition;
+use condition::visit::Visitor;
+use condition::analysis::{self, Problem};
+use condition::processing::{self, Processor};
+use view::View;
+use time::{Bucket, Encoding, Window};
+use sequence::Sequence;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Aggregate {
+    Count,
+    Min,
+    Max,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection<T : AsRef<[u8]> + Clone> {
+    Binding(T),
+    Aggregate(Aggregate, T),
+    /// Start of the time bucket of a timestamp binding
+    Bucket(Bucket, Encoding, T),
+}
+
+impl<T : AsRef<[u8]> + Clone> Projection<T> {
+    pub fn binding(&self) -> &T {
+        match *self {
+            Projection::Binding(ref b) | Projection::Aggregate(_, ref b) | Projection::Bucket(_, _, ref b) => b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Ascending,
+    Descending,
+}
+
+/// How the values of a sort key compare
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Bytes,
+    /// Unsigned integers (values that fail to decode sort first)
+    Number(Encoding),
+}
+
+/// `ORDER BY` key: a projected binding
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey<T : AsRef<[u8]> + Clone> {
+    pub binding: T,
+    pub direction: Direction,
+    pub kind: Kind,
+}
+
+/// `name(?P1, ..., ?Pn) :- body`
+///
+/// Rules sharing a name are alternatives; a rule can refer to itself
+/// (or to other rules) through `Condition::Rule`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule<T : AsRef<[u8]> + Clone> {
+    pub name: T,
+    pub parameters: Vec<T>,
+    pub body: Condition<T>,
+}
+
+impl<T : AsRef<[u8]> + Clone> Rule<T> {
+    pub fn new(name: T, parameters: Vec<T>, body: Condition<T>) -> Self {
+        Rule { name, parameters, body }
+    }
+}
+
+/// `SELECT <projection> WHERE <condition> ORDER BY <order> LIMIT <limit> OFFSET <offset>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query<T : AsRef<[u8]> + Clone> {
+    pub projection: Vec<Projection<T>>,
+    pub condition: Condition<T>,
+    pub rules: Vec<Rule<T>>,
+    /// Definitions of the views the condition refers to (see `view::resolve`)
+    pub views: Vec<View<T>>,
+    /// Solutions have to fall in every window
+    pub windows: Vec<Window<T>>,
+    /// Solutions have to match every sequence (see `Query::sequence`)
+    pub sequences: Vec<Sequence<T>>,
+    pub order: Vec<SortKey<T>>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl<T : AsRef<[u8]> + Clone> Query<T> {
+    pub fn new(condition: Condition<T>) -> Self {
+        Query {
+            projection: vec![],
+            condition,
+            rules: vec![],
+            views: vec![],
+            windows: vec![],
+            sequences: vec![],
+            order: vec![],
+            limit: None,
+            offset: 0,
+        }
+    }
+
+    pub fn select(mut self, binding: T) -> Self {
+        self.projection.push(Projection::Binding(binding));
+        self
+    }
+
+    pub fn select_aggregate(mut self, aggregate: Aggregate, binding: T) -> Self {
+        self.projection.push(Projection::Aggregate(aggregate, binding));
+        self
+    }
+
+    pub fn select_bucket(mut self, bucket: Bucket, encoding: Encoding, binding: T) -> Self {
+        self.projection.push(Projection::Bucket(bucket, encoding, binding));
+        self
+    }
+
+    pub fn window(mut self, window: Window<T>) -> Self {
+        self.windows.push(window);
+        self
+    }
+
+    /// Adds the sequence's steps to the condition
+    pub fn sequence(mut self, sequence: Sequence<T>) -> Self {
+        self.condition = match self.condition {
+            Condition::True => sequence.condition(),
+            condition => condition.and(sequence.condition()),
+        };
+        self.sequences.push(sequence);
+        self
+    }
+
+    pub fn order_by(mut self, binding: T, direction: Direction, kind: Kind) -> Self {
+        self.order.push(SortKey { binding, direction, kind });
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn rule(mut self, rule: Rule<T>) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Query with its condition, its rules' bodies and its views' queries
+    /// processed
+    pub fn process<P : Processor<T>>(mut self, processor: &P) -> Result<Self, processing::Error<T>> {
+        self.condition = processor.process(self.condition)?;
+        for rule in self.rules.iter_mut() {
+            rule.body = processor.process(rule.body.clone())?;
+        }
+        for view in self.views.iter_mut() {
+            view.query = view.query.clone().process(processor)?;
+        }
+        Ok(self)
+    }
+
+    /// Whether the query has a window that slides as time passes (possibly
+    /// through a view), so that its results can change without any change
+    /// to the facts
+    pub fn sliding(&self) -> bool {
+        self.windows.iter().any(|w| w.sliding()) || self.views.iter().any(|v| v.query.sliding())
+    }
+
+    /// Attributes the query reads, in its condition, its rules and the
+    /// views it refers to, in bytewise order
+    pub fn attributes(&self) -> Vec<Vec<u8>> {
+        let mut attributes = Attributes(BTreeSet::new());
+        attributes.visit_condition(&self.condition);
+        for rule in self.rules.iter() {
+            attributes.visit_condition(&rule.body);
+        }
+        for view in self.views.iter() {
+            attributes.0.extend(view.query.attributes());
+        }
+        attributes.0.into_iter().collect()
+    }
+
+    /// Checks that every projected, windowed or compared binding is safely bound
+    /// by the condition, and every rule parameter by the rule's body
+    pub fn validate(&self) -> Result<(), Vec<Problem<T>>> {
+        let projection: Vec<T> = self.projection.iter().map(|p| p.binding())
+                                     .chain(self.windows.iter().map(|w| &w.binding))
+                                     .cloned().collect();
+        let mut problems = analysis::check(&self.condition, &projection);
+        for rule in self.rules.iter() {
+            problems.extend(analysis::check(&rule.body, &rule.parameters));
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+struct Attributes(BTreeSet<Vec<u8>>);
+
+impl<T : AsRef<[u8]> + Clone> Visitor<T> for Attributes {
+    fn visit_attribute(&mut self, attribute: &T) {
+        self.0.insert(attribute.as_ref().to_vec());
+    }
+
+    fn visit_attribute_txid(&mut self, attribute: &T) {
+        self.0.insert(attribute.as_ref().to_vec());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query};
+    use Condition::Equal;
+    use query::Aggregate;
+    use condition::analysis::Problem;
+
+    #[test]
+    pub fn validate() {
+        let cond = Condition::fact(Equal(Value::Attribute("#value"), Value::Binding("Name"))
+                                   .and(Equal(Value::Attribute("#timestamp"), Value::Binding("Timestamp"))));
+        let query = Query::new(cond).select("Name").select_aggregate(Aggregate::Max, "Timestamp");
+        assert_eq!(query.validate(), Ok(()));
+        assert_eq!(query.attributes(), vec![b"#timestamp".to_vec(), b"#value".to_vec()]);
+        assert_eq!(query.select("Email").validate(), Err(vec![Problem::Unbound("Email")]));
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Reactors: actions run when a newly recorded fact matches a trait.
+//!
+//! Once a transaction is committed, `Reactors::committed` queues an
+//! invocation of every reactor whose trait matches one of its facts that
+//! didn't match it before, and `Reactors::run` runs them. A failed
+//! invocation is retried on the next run, up to a number of attempts,
+//! after which it becomes a dead letter. Script invocations can also be
+//! taken and run elsewhere (see `take_scripts` and `settle`). In ViewDB,
+//! script reactors are stored encoded as a `Definition` (see `REACTOR` in
+//! the engine), and the engine runs reactors once transactions commit.
+
+use {Trait, TraitPattern};
+use encoding::{self, Encode, Decode};
+use execution::FactSource;
+use feed::Change;
+use traits::{self, Patterns};
+
+pub const DEFAULT_MAX_ATTEMPTS: usize = 3;
+
+pub type Handler = Box<dyn FnMut(&[u8]) -> Result<(), String> + Send>;
+
+pub enum Action {
+    /// PumpkinScript program, run with the fact's identifier on the stack
+    Script(Vec<u8>),
+    Native(Handler),
+}
+
+pub struct Reactor<T : AsRef<[u8]> + Clone> {
+    pub name: T,
+    pub trait_: Trait<T>,
+    pub action: Action,
+}
+
+/// Stored form of a script reactor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: Vec<u8>,
+    pub trait_: Patterns,
+    pub script: Vec<u8>,
+}
+
+impl Encode for Definition {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        vec![self.name.clone(), self.script.clone()].encode(buf);
+        traits::encode_patterns(&self.trait_, buf);
+    }
+}
+
+impl Decode for Definition {
+    fn decode(buf: &mut &[u8]) -> Result<Self, encoding::Error> {
+        let mut header = Vec::<Vec<u8>>::decode(buf)?.into_iter();
+        let (name, script) = match (header.next(), header.next()) {
+            (Some(name), Some(script)) => (name, script),
+            _ => return Err(encoding::Error::UnexpectedEnd),
+        };
+        let trait_ = traits::decode_patterns(buf)?;
+        Ok(Definition { name, trait_, script })
+    }
+}
+
+impl From<Definition> for Reactor<Vec<u8>> {
+    fn from(definition: Definition) -> Self {
+        let patterns: Vec<TraitPattern<Vec<u8>>> = definition.trait_.into_iter().map(TraitPattern::from).collect();
+        Reactor { name: definition.name, trait_: patterns.into(), action: Action::Script(definition.script) }
+    }
+}
+
+/// Whether the fact has every attribute of the trait (with the required
+/// value, if any)
+pub fn has_trait<S : FactSource, T : AsRef<[u8]> + Clone>(source: &S, fact: &[u8], trait_: &Trait<T>) -> bool {
+    had_trait(source, fact, trait_, None)
+}
+
+/// Whether the fact had the trait before the transaction `txid`, if any
+fn had_trait<S, T>(source: &S, fact: &[u8], trait_: &Trait<T>, txid: Option<&[u8]>) -> bool
+    where S : FactSource, T : AsRef<[u8]> + Clone {
+    trait_.iter().all(|p| {
+        let attachments = source.attachments(fact, p.0.as_ref());
+        let mut attachments = attachments.iter().filter(|a| txid.map(|t| a.txid.as_slice() < t).unwrap_or(true));
+        match p.1 {
+            Some(ref value) => attachments.any(|a| a.value.as_slice() == value.as_ref()),
+            None => attachments.next().is_some(),
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation<T : AsRef<[u8]> + Clone> {
+    pub reactor: T,
+    pub fact: Vec<u8>,
+    pub attempts: usize,
+    pub error: Option<String>,
+}
+
+pub struct Reactors<T : AsRef<[u8]> + Clone> {
+    reactors: Vec<Reactor<T>>,
+    pending: Vec<Invocation<T>>,
+    dead_letters: Vec<Invocation<T>>,
+    max_attempts: usize,
+}
+
+impl<T : AsRef<[u8]> + Clone> Default for Reactors<T> {
+    fn default() -> Self {
+        Reactors::new()
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> Reactors<T> {
+    pub fn new() -> Self {
+        Reactors { reactors: vec![], pending: vec![], dead_letters: vec![], max_attempts: DEFAULT_MAX_ATTEMPTS }
+    }
+
+    pub fn max_attempts(mut self, n: usize) -> Self {
+        self.max_attempts = n;
+        self
+    }
+
+    /// Registers the reactor, replacing any reactor with the same name
+    pub fn register(&mut self, reactor: Reactor<T>) {
+        self.reactors.retain(|r| r.name.as_ref() != reactor.name.as_ref());
+        self.reactors.push(reactor);
+    }
+
+    /// Attributes of the reactors' traits, in bytewise order
+    pub fn attributes(&self) -> Vec<Vec<u8>> {
+        let mut attributes: Vec<Vec<u8>> = self.reactors.iter()
+                                               .flat_map(|r| r.trait_.iter().map(|p| p.0.as_ref().to_vec()))
+                                               .collect();
+        attributes.sort();
+        attributes.dedup();
+        attributes
+    }
+
+    /// Queues the reactors triggered by the changes of a committed
+    /// transaction, which `source` already has: those whose trait a changed
+    /// fact has now, but didn't have before the transaction
+    pub fn committed<S : FactSource>(&mut self, source: &S, changes: &[Change]) {
+        let mut facts: Vec<(&[u8], &[u8])> = vec![];
+        for change in changes {
+            match facts.iter_mut().find(|&&mut (f, _)| f == change.fact.as_slice()) {
+                Some(&mut (_, ref mut txid)) => *txid = ::std::cmp::min(*txid, change.txid.as_slice()),
+                None => facts.push((&change.fact, &change.txid)),
+            }
+        }
+        for (fact, txid) in facts {
+            for reactor in self.reactors.iter() {
+                if has_trait(source, fact, &reactor.trait_) && !had_trait(source, fact, &reactor.trait_, Some(txid)) {
+                    self.pending.push(Invocation {
+                        reactor: reactor.name.clone(), fact: fact.to_vec(), attempts: 0, error: None
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn pending(&self) -> &[Invocation<T>] {
+        &self.pending
+    }
+
+    pub fn dead_letters(&self) -> &[Invocation<T>] {
+        &self.dead_letters
+    }
+
+    /// Runs pending invocations once, with `script` running script reactors
+    /// (`script(program, fact)`)
+    pub fn run<F>(&mut self, mut script: F) where F : FnMut(&[u8], &[u8]) -> Result<(), String> {
+        self.run_native();
+        for (invocation, program) in self.take_scripts() {
+            let result = script(&program, &invocation.fact);
+            self.settle(invocation, result);
+        }
+    }
+
+    /// Runs pending invocations of native reactors once, leaving those of
+    /// script reactors pending
+    pub fn run_native(&mut self) {
+        let pending = ::std::mem::take(&mut self.pending);
+        for invocation in pending {
+            let result = match self.reactors.iter_mut().find(|r| r.name.as_ref() == invocation.reactor.as_ref()) {
+                Some(&mut Reactor { action: Action::Script(_), .. }) => {
+                    self.pending.push(invocation);
+                    continue;
+                },
+                Some(&mut Reactor { action: Action::Native(ref mut handler), .. }) => handler(&invocation.fact),
+                None => Err("reactor is no longer registered".to_string()),
+            };
+            self.settle(invocation, result);
+        }
+    }
+
+    /// Takes the pending invocations of script reactors, with their
+    /// programs, to be run elsewhere and then `settle`d
+    pub fn take_scripts(&mut self) -> Vec<(Invocation<T>, Vec<u8>)> {
+        let pending = ::std::mem::take(&mut self.pending);
+        let mut scripts = vec![];
+        for invocation in pending {
+            match self.reactors.iter().find(|r| r.name.as_ref() == invocation.reactor.as_ref()) {
+                Some(&Reactor { action: Action::Script(ref program), .. }) => {
+                    let program = program.clone();
+                    scripts.push((invocation, program));
+                },
+                _ => self.pending.push(invocation),
+            }
+        }
+        scripts
+    }
+
+    /// Records the result of running the invocation: a failed one is
+    /// retried (left pending) or becomes a dead letter
+    pub fn settle(&mut self, mut invocation: Invocation<T>, result: Result<(), String>) {
+        if let Err(error) = result {
+            invocation.attempts += 1;
+            invocation.error = Some(error);
+            if invocation.attempts < self.max_attempts {
+                self.pending.push(invocation);
+            } else {
+                self.dead_letters.push(invocation);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::{Arc, Mutex};
+
+    use encoding::{Encode, Decode};
+    use execution::tests::{Facts, people};
+    use feed::ChangeSource;
+    use memory::Memory;
+    use reactor::{Reactors, Reactor, Action, Definition};
+
+    /// People, each fact recorded in a transaction of its own
+    fn memory() -> Memory {
+        let Facts(facts) = people();
+        let mut memory = Memory::new();
+        for (fact, attributes) in facts {
+            memory.record(fact.as_bytes()).unwrap();
+            let attachments: Vec<(&[u8], &[u8], &[u8])> = attributes.iter()
+                .map(|&(a, v)| (fact.as_bytes(), a.as_bytes(), v.as_bytes())).collect();
+            memory.commit(&attachments).unwrap();
+        }
+        memory
+    }
+
+    #[test]
+    pub fn react() {
+        let derived = Arc::new(Mutex::new(vec![]));
+        let mut reactors = Reactors::new().max_attempts(2);
+        let d = derived.clone();
+        reactors.register(Reactor {
+            name: "ProfileUpdated",
+            trait_: vec![("#factType", Some("NameChanged")).into(), ("#object", None).into()].into(),
+            action: Action::Native(Box::new(move |fact| {
+                d.lock().unwrap().push(fact.to_vec());
+                Ok(())
+            })),
+        });
+        reactors.register(Reactor {
+            name: "Failing",
+            trait_: vec![("#factType", Some("AccountClosed")).into()].into(),
+            action: Action::Script(b"FAIL".to_vec()),
+        });
+        let memory = memory();
+        reactors.committed(&memory, &memory.changes(None));
+        assert_eq!(reactors.pending().len(), 4);
+        assert_eq!(reactors.attributes(), vec![b"#factType".to_vec(), b"#object".to_vec()]);
+
+        reactors.run(|_, _| Err("failed".to_string()));
+        assert_eq!(*derived.lock().unwrap(), vec![b"1".to_vec(), b"2".to_vec(), b"4".to_vec()]);
+        assert_eq!(reactors.pending().len(), 1);
+        reactors.run(|_, _| Err("failed".to_string()));
+        assert!(reactors.pending().is_empty());
+        assert_eq!(reactors.dead_letters()[0].fact, b"5".to_vec());
+        assert_eq!(reactors.dead_letters()[0].attempts, 2);
+    }
+
+    #[test]
+    pub fn newly_matching() {
+        let mut reactors = Reactors::new();
+        reactors.register(Reactor {
+            name: "ProfileUpdated",
+            trait_: vec![("#factType", Some("NameChanged")).into(), ("#object", None).into()].into(),
+            action: Action::Script(b"program".to_vec()),
+        });
+        let mut memory = memory();
+        let before = memory.changes(None).pop().unwrap().txid;
+        let txid = memory.attach(b"1", b"#object", b"alice").unwrap();
+        // fact 1 already had the trait before it was attached to again
+        reactors.committed(&memory, &memory.changes(Some(&before)));
+        assert!(reactors.pending().is_empty());
+        memory.attach(b"3", b"#factType", b"NameChanged").unwrap();
+        reactors.committed(&memory, &memory.changes(Some(&txid)));
+        assert_eq!(reactors.pending().len(), 1);
+        assert_eq!(reactors.pending()[0].fact, b"3".to_vec());
+        assert_eq!(reactors.take_scripts()[0].1, b"program".to_vec());
+        assert!(reactors.pending().is_empty());
+    }
+
+    #[test]
+    pub fn definition() {
+        let definition = Definition {
+            name: b"ProfileUpdated".to_vec(),
+            trait_: vec![(b"#factType".to_vec(), Some(b"NameChanged".to_vec())), (b"#object".to_vec(), None)],
+            script: b"program".to_vec(),
+        };
+        assert_eq!(Definition::from_bytes(&definition.to_bytes()), Ok(definition.clone()));
+        let reactor: Reactor<Vec<u8>> = definition.into();
+        assert_eq!(reactor.trait_.iter().count(), 2);
+    }
+}
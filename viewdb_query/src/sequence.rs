@@ -0,0 +1,163 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Sequence patterns (`A then B then C within 1h`).
+//!
+//! Every step is matched by its own fact, and binds the step's order key:
+//! the TXID of one of the fact's attributes, or a timestamp attribute.
+//! Steps are usually tied together through a shared binding (the object
+//! the facts refer to). A solution matches the sequence if its order keys
+//! are strictly increasing and, for timestamps, the last one is within the
+//! time bound of the first one.
+
+use condition::{Condition, Value};
+use time::Encoding;
+use execution::{Env, lookup};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Order<T : AsRef<[u8]> + Clone> {
+    /// By the TXID of the attribute's attachment
+    Txid(T),
+    /// By the attribute's value, decoded as a timestamp
+    Timestamp(T, Encoding),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error<T : AsRef<[u8]> + Clone> {
+    /// Time bound on a sequence ordered by TXID (the binding of its first
+    /// step's key), which has no time to bound
+    TxidWithin(T),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sequence<T : AsRef<[u8]> + Clone> {
+    pub order: Order<T>,
+    /// Body of every step's fact scope, and the binding of its order key
+    pub steps: Vec<(Condition<T>, T)>,
+    /// Maximum number of seconds between the first and the last step
+    /// (timestamp order only, see `validate`)
+    pub within: Option<u64>,
+}
+
+impl<T : AsRef<[u8]> + Clone> Sequence<T> {
+    pub fn new(order: Order<T>) -> Self {
+        Sequence { order, steps: vec![], within: None }
+    }
+
+    pub fn then(mut self, body: Condition<T>, key: T) -> Self {
+        self.steps.push((body, key));
+        self
+    }
+
+    pub fn within(mut self, seconds: u64) -> Self {
+        self.within = Some(seconds);
+        self
+    }
+
+    /// Checks that only a timestamp-ordered sequence is bounded in time
+    pub fn validate(&self) -> Result<(), Error<T>> {
+        match (&self.order, self.within, self.steps.first()) {
+            (&Order::Txid(_), Some(_), Some((_, key))) => Err(Error::TxidWithin(key.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Conjunction of the steps' fact scopes, binding their order keys
+    pub fn condition(&self) -> Condition<T> {
+        let order = match self.order {
+            Order::Txid(ref a) => Value::AttributeTxid(a.clone()),
+            Order::Timestamp(ref a, _) => Value::Attribute(a.clone()),
+        };
+        self.steps.iter()
+            .map(|(body, key)| Condition::fact(body.clone().and(Condition::Equal(order.clone(), Value::Binding(key.clone())))))
+            .fold(Condition::True, |cond, step| if matches!(cond, Condition::True) { step } else { cond.and(step) })
+    }
+
+    pub(crate) fn matches(&self, env: &Env<T>) -> bool {
+        let keys: Option<Vec<&[u8]>> = self.steps.iter().map(|(_, key)| lookup(env, key)).collect();
+        let keys = match keys {
+            Some(keys) => keys,
+            None => return false,
+        };
+        match self.order {
+            Order::Txid(_) => keys.windows(2).all(|w| w[0] < w[1]),
+            Order::Timestamp(_, encoding) => {
+                let timestamps: Option<Vec<u64>> = keys.iter().map(|k| encoding.decode(k)).collect();
+                match timestamps {
+                    Some(ref t) if t.windows(2).all(|w| w[0] < w[1]) => match (self.within, t.first(), t.last()) {
+                        (Some(within), Some(first), Some(last)) => last - first <= within,
+                        _ => true,
+                    },
+                    _ => false,
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query};
+    use Condition::Equal;
+    use execution::{self, Executor};
+    use execution::tests::{Facts, fact_type};
+    use live::Subscriptions;
+    use sequence::{Sequence, Order, Error};
+    use time::Encoding;
+
+    fn step(fact: &'static str) -> Condition<&'static str> {
+        fact_type(fact).and(Equal(Value::Attribute("#object"), Value::Binding("Account")))
+    }
+
+    fn fraud() -> Query<&'static str> {
+        let sequence = Sequence::new(Order::Timestamp("#timestamp", Encoding::Decimal))
+                       .then(step("EmailChanged"), "T1")
+                       .then(step("PasswordReset"), "T2")
+                       .then(step("PayoutRequested"), "T3")
+                       .within(3600);
+        Query::new(Condition::True).sequence(sequence).select("Account")
+    }
+
+    #[test]
+    pub fn sequence() {
+        let mut facts = vec![
+            ("1", vec![("#factType", "EmailChanged"), ("#object", "a"), ("#timestamp", "1000")]),
+            ("2", vec![("#factType", "PasswordReset"), ("#object", "a"), ("#timestamp", "2000")]),
+            ("3", vec![("#factType", "PayoutRequested"), ("#object", "a"), ("#timestamp", "3000")]),
+            ("4", vec![("#factType", "EmailChanged"), ("#object", "b"), ("#timestamp", "1000")]),
+            ("5", vec![("#factType", "PayoutRequested"), ("#object", "b"), ("#timestamp", "1500")]),
+            ("6", vec![("#factType", "PasswordReset"), ("#object", "b"), ("#timestamp", "2000")]),
+            ("7", vec![("#factType", "EmailChanged"), ("#object", "c"), ("#timestamp", "1000")]),
+            ("8", vec![("#factType", "PasswordReset"), ("#object", "c"), ("#timestamp", "2000")]),
+        ];
+        let rows = Executor::new(&Facts(facts.clone())).execute(&fraud()).unwrap();
+        assert_eq!(rows, vec![vec![b"a".to_vec()]]);
+
+        let mut subscriptions = Subscriptions::new();
+        subscriptions.subscribe(&Executor::new(&Facts(facts.clone())), "fraud", fraud()).unwrap();
+        facts.push(("9", vec![("#factType", "PayoutRequested"), ("#object", "c"), ("#timestamp", "9000")]));
+        let source = Facts(facts.clone());
+        assert!(subscriptions.attached(&Executor::new(&source), &[(b"9", b"#timestamp")]).unwrap().is_empty());
+        facts.push(("10", vec![("#factType", "PayoutRequested"), ("#object", "c"), ("#timestamp", "2500")]));
+        let source = Facts(facts.clone());
+        let deltas = subscriptions.attached(&Executor::new(&source), &[(b"10", b"#timestamp")]).unwrap();
+        assert_eq!(deltas[0].1.added, vec![vec![b"c".to_vec()]]);
+    }
+
+    #[test]
+    pub fn txid_within() {
+        let sequence = Sequence::new(Order::Txid("#object"))
+                       .then(step("EmailChanged"), "T1")
+                       .then(step("PayoutRequested"), "T2");
+        assert_eq!(sequence.validate(), Ok(()));
+        let sequence = sequence.within(3600);
+        assert_eq!(sequence.validate(), Err(Error::TxidWithin("T1")));
+        let query = Query::new(Condition::True).sequence(sequence).select("Account");
+        assert_eq!(Executor::new(&Facts(vec![])).execute(&query),
+                   Err(execution::Error::Sequence(Error::TxidWithin("T1"))));
+    }
+}
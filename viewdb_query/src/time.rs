@@ -0,0 +1,206 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Timestamps, windows and time buckets.
+//!
+//! Attribute values are just binaries, so a timestamp binding is decoded
+//! according to an `Encoding` (to seconds since the Unix epoch). A `Window`
+//! keeps the solutions whose timestamp falls in a range, fixed or relative
+//! to the time the query is evaluated (e.g. the last 30 days); a `Bucket` truncates a timestamp to the start of its hour, day,
+//! month, or of a fixed-size (tumbling) window, so that it can be grouped
+//! by.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// Unsigned big-endian integer (up to 8 bytes)
+    BigEndian,
+    /// ASCII decimal digits
+    Decimal,
+}
+
+impl Encoding {
+    pub fn decode(&self, bytes: &[u8]) -> Option<u64> {
+        match *self {
+            Encoding::BigEndian if bytes.len() <= 8 =>
+                Some(bytes.iter().fold(0, |n, &b| (n << 8) | u64::from(b))),
+            Encoding::BigEndian => None,
+            Encoding::Decimal => ::std::str::from_utf8(bytes).ok().and_then(|s| s.parse().ok()),
+        }
+    }
+
+    pub fn encode(&self, timestamp: u64) -> Vec<u8> {
+        match *self {
+            Encoding::BigEndian => timestamp.to_be_bytes().to_vec(),
+            Encoding::Decimal => timestamp.to_string().into_bytes(),
+        }
+    }
+}
+
+pub const HOUR: u64 = 60 * 60;
+pub const DAY: u64 = 24 * HOUR;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bucket {
+    /// Tumbling window of the given number of seconds
+    Seconds(u64),
+    Hour,
+    Day,
+    /// Calendar month (UTC)
+    Month,
+}
+
+/// (year, month) of the day, counting days from 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month)
+}
+
+/// Days from 1970-01-01 to the first day of the month
+fn days_from_civil(year: i64, month: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+impl Bucket {
+    /// Start of the bucket the timestamp falls in
+    pub fn start(&self, timestamp: u64) -> u64 {
+        match *self {
+            Bucket::Seconds(0) => timestamp,
+            Bucket::Seconds(n) => timestamp - timestamp % n,
+            Bucket::Hour => timestamp - timestamp % HOUR,
+            Bucket::Day => timestamp - timestamp % DAY,
+            Bucket::Month => {
+                let (year, month) = civil_from_days((timestamp / DAY) as i64);
+                days_from_civil(year, month) as u64 * DAY
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Range {
+    /// `[from, until)`
+    Fixed(Option<u64>, Option<u64>),
+    /// The last that many seconds, up to (and including) the time the
+    /// query is evaluated
+    Last(u64),
+}
+
+impl Range {
+    /// Bounds (`[from, until)`) of the range when evaluated at `now`
+    pub fn at(&self, now: u64) -> (Option<u64>, Option<u64>) {
+        match *self {
+            Range::Fixed(from, until) => (from, until),
+            Range::Last(length) => (Some(now.saturating_sub(length)), now.checked_add(1)),
+        }
+    }
+}
+
+/// Keeps solutions whose timestamp is in the range
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window<T : AsRef<[u8]> + Clone> {
+    pub binding: T,
+    pub encoding: Encoding,
+    pub range: Range,
+}
+
+impl<T : AsRef<[u8]> + Clone> Window<T> {
+    pub fn new(binding: T, encoding: Encoding, from: Option<u64>, until: Option<u64>) -> Self {
+        Window { binding, encoding, range: Range::Fixed(from, until) }
+    }
+
+    /// Window of `length` seconds, up to (and including) the time the query
+    /// is evaluated, so that it slides as time passes (see `Query::sliding`)
+    pub fn last(binding: T, encoding: Encoding, length: u64) -> Self {
+        Window { binding, encoding, range: Range::Last(length) }
+    }
+
+    pub fn sliding(&self) -> bool {
+        matches!(self.range, Range::Last(_))
+    }
+
+    /// Whether the timestamp is in the range when evaluated at `now`
+    pub fn contains(&self, value: &[u8], now: u64) -> bool {
+        let (from, until) = self.range.at(now);
+        match self.encoding.decode(value) {
+            Some(t) => from.map(|from| t >= from).unwrap_or(true) && until.map(|until| t < until).unwrap_or(true),
+            None => false,
+        }
+    }
+}
+
+/// Current time, in seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query};
+    use Condition::Equal;
+    use query::Aggregate;
+    use execution::Executor;
+    use execution::tests::{Facts, fact_type};
+    use time::{Bucket, Encoding, Window, DAY};
+
+    #[test]
+    pub fn buckets() {
+        // 2017-03-15T10:20:30Z
+        let t = 1_489_573_230;
+        assert_eq!(Bucket::Hour.start(t), 1_489_572_000);
+        assert_eq!(Bucket::Day.start(t), 1_489_536_000);
+        assert_eq!(Bucket::Month.start(t), 1_488_326_400);
+        assert_eq!(Bucket::Month.start(1_488_326_400 - 1), 1_488_326_400 - 28 * DAY);
+        assert_eq!(Bucket::Seconds(7 * DAY).start(t), t - t % (7 * DAY));
+        assert_eq!(Encoding::BigEndian.decode(&Encoding::BigEndian.encode(t)), Some(t));
+        assert_eq!(Encoding::Decimal.decode(b"12x"), None);
+        let window = Window::last("T", Encoding::Decimal, 30 * DAY);
+        assert!(window.contains(t.to_string().as_bytes(), t));
+        assert!(!window.contains((t - 31 * DAY).to_string().as_bytes(), t));
+        assert!(window.contains((t - 31 * DAY).to_string().as_bytes(), t - DAY));
+        let window = Window::last("T", Encoding::BigEndian, DAY);
+        assert!(window.contains(&Encoding::BigEndian.encode(u64::MAX), u64::MAX));
+    }
+
+    #[test]
+    pub fn windowed_count() {
+        let facts = Facts(vec![
+            ("1", vec![("#factType", "NameChanged"), ("#object", "alice"), ("#timestamp", "100")]),
+            ("2", vec![("#factType", "NameChanged"), ("#object", "alice"), ("#timestamp", "86500")]),
+            ("3", vec![("#factType", "NameChanged"), ("#object", "alice"), ("#timestamp", "86600")]),
+            ("4", vec![("#factType", "NameChanged"), ("#object", "bob"), ("#timestamp", "90000")]),
+        ]);
+        let cond = Condition::fact(fact_type("NameChanged")
+                                   .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                   .and(Equal(Value::Attribute("#timestamp"), Value::Binding("Timestamp"))));
+        let count = |n: u8| vec![0, 0, 0, 0, 0, 0, 0, n];
+        let query = Query::new(cond.clone()).select("Person").select_aggregate(Aggregate::Count, "Timestamp")
+                    .window(Window::last("Timestamp", Encoding::Decimal, DAY));
+        assert!(query.sliding());
+        assert_eq!(Executor::new(&facts).at(100_000).execute(&query).unwrap(),
+                   vec![vec![b"alice".to_vec(), count(2)], vec![b"bob".to_vec(), count(1)]]);
+        assert_eq!(Executor::new(&facts).at(176_000).execute(&query).unwrap(),
+                   vec![vec![b"bob".to_vec(), count(1)]]);
+        let query = Query::new(cond).select_bucket(Bucket::Day, Encoding::Decimal, "Timestamp")
+                    .select_aggregate(Aggregate::Count, "Timestamp");
+        assert_eq!(Executor::new(&facts).execute(&query).unwrap(),
+                   vec![vec![b"0".to_vec(), count(1)], vec![b"86400".to_vec(), count(3)]]);
+    }
+}
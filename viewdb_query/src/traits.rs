@@ -0,0 +1,131 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Trait definitions.
+//!
+//! `Traits` resolves the traits `TraitsExpansion` expands from their
+//! latest `Definition`s. In ViewDB, definitions are stored encoded (see
+//! `TRAIT` in the engine), and queries compiled with a trait's previous
+//! definition have to be compiled again once it's redefined.
+
+use {Trait, TraitPattern, TraitResolver};
+use encoding::{self, Encode, Decode};
+
+/// Patterns (attribute, and the value it's attached to, if any)
+pub type Patterns = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
+/// Stored form of a trait
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: Vec<u8>,
+    pub patterns: Patterns,
+}
+
+/// Every pattern as a list of its attribute and value
+pub(crate) fn encode_patterns(patterns: &Patterns, buf: &mut Vec<u8>) {
+    let patterns: Vec<Vec<Vec<u8>>> = patterns.iter().map(|(attribute, value)| {
+        let mut pattern = vec![attribute.clone()];
+        pattern.extend(value.iter().cloned());
+        pattern
+    }).collect();
+    patterns.encode(buf);
+}
+
+pub(crate) fn decode_patterns(buf: &mut &[u8]) -> Result<Patterns, encoding::Error> {
+    let mut patterns = vec![];
+    for pattern in Vec::<Vec<Vec<u8>>>::decode(buf)? {
+        let mut pattern = pattern.into_iter();
+        match pattern.next() {
+            Some(attribute) => patterns.push((attribute, pattern.next())),
+            None => return Err(encoding::Error::UnexpectedEnd),
+        }
+    }
+    Ok(patterns)
+}
+
+impl Encode for Definition {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        vec![self.name.clone()].encode(buf);
+        encode_patterns(&self.patterns, buf);
+    }
+}
+
+impl Decode for Definition {
+    fn decode(buf: &mut &[u8]) -> Result<Self, encoding::Error> {
+        let name = match Vec::<Vec<u8>>::decode(buf)?.pop() {
+            Some(name) => name,
+            None => return Err(encoding::Error::UnexpectedEnd),
+        };
+        let patterns = decode_patterns(buf)?;
+        Ok(Definition { name, patterns })
+    }
+}
+
+impl From<Definition> for Trait<Vec<u8>> {
+    fn from(definition: Definition) -> Self {
+        definition.patterns.into_iter().map(TraitPattern::from).collect::<Vec<_>>().into()
+    }
+}
+
+#[derive(Default)]
+pub struct Traits(Vec<(Vec<u8>, Trait<Vec<u8>>)>);
+
+impl Traits {
+    pub fn new() -> Self {
+        Traits::default()
+    }
+
+    /// Defines the trait, replacing its previous definition
+    pub fn define(&mut self, definition: Definition) {
+        self.0.retain(|(name, _)| *name != definition.name);
+        self.0.push((definition.name.clone(), definition.into()));
+    }
+
+    /// Names of all traits, in the order they've been (re)defined in
+    pub fn names(&self) -> Vec<Vec<u8>> {
+        self.0.iter().map(|(name, _)| name.clone()).collect()
+    }
+}
+
+impl TraitResolver<Vec<u8>> for Traits {
+    fn resolve(&self, name: Vec<u8>) -> Option<&Trait<Vec<u8>>> {
+        self.0.iter().find(|&(n, _)| *n == name).map(|(_, t)| t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, TraitResolver};
+    use encoding::{Encode, Decode};
+    use condition::processing::{Processor, TraitsExpansion};
+    use traits::{Traits, Definition};
+
+    #[test]
+    pub fn traits() {
+        let definition = Definition {
+            name: b"NameChanged".to_vec(),
+            patterns: vec![(b"#factType".to_vec(), Some(b"NameChanged".to_vec())), (b"#object".to_vec(), None)],
+        };
+        assert_eq!(Definition::from_bytes(&definition.to_bytes()), Ok(definition.clone()));
+
+        let mut traits = Traits::new();
+        traits.define(definition);
+        let condition = Condition::trait_scope(b"NameChanged".to_vec(), Condition::True);
+        assert_eq!(TraitsExpansion::new(&traits).process(condition.clone()),
+                   Ok(Condition::True.and(Condition::Equal(Value::Attribute(b"#factType".to_vec()),
+                                                           Value::Data(b"NameChanged".to_vec())))
+                                     .and(Condition::Present(Value::Attribute(b"#object".to_vec())))));
+
+        // redefined
+        traits.define(Definition { name: b"NameChanged".to_vec(), patterns: vec![(b"#object".to_vec(), None)] });
+        assert_eq!(traits.names(), vec![b"NameChanged".to_vec()]);
+        assert_eq!(traits.resolve(b"NameChanged".to_vec()).unwrap().iter().count(), 1);
+        assert_eq!(TraitsExpansion::new(&traits).process(condition),
+                   Ok(Condition::True.and(Condition::Present(Value::Attribute(b"#object".to_vec())))));
+        assert!(traits.resolve(b"Unknown".to_vec()).is_none());
+    }
+}
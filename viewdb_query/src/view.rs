@@ -0,0 +1,210 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Named views (`CREATE VIEW name AS <query>`).
+//!
+//! A view is referred to like a rule, `Condition::Rule(name, arguments)`,
+//! with one argument per projected column. Views are versioned: defining
+//! a view again adds a new version, and references resolve to the latest
+//! one. In ViewDB, the version is the TXID of the definition (see `VIEW`
+//! in the engine), and the engine keeps the committed definitions in a
+//! `Views` catalog, which its queries are resolved against.
+
+use condition::visit::{self, Visitor};
+use condition::analysis::Problem;
+use query::Query;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct View<T : AsRef<[u8]> + Clone> {
+    pub name: T,
+    /// Versions of a view sort bytewise in the order they were defined
+    pub version: Vec<u8>,
+    pub query: Query<T>,
+}
+
+pub trait ViewResolver<T : AsRef<[u8]> + Clone> {
+    /// Latest version of the view
+    fn resolve(&self, name: &T) -> Option<View<T>>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error<T : AsRef<[u8]> + Clone> {
+    /// View refers to itself, directly or through other views
+    Cycle(T),
+}
+
+#[derive(Debug, Clone)]
+pub struct Views<T : AsRef<[u8]> + Clone> {
+    views: Vec<View<T>>,
+    txid: u64,
+}
+
+impl<T : AsRef<[u8]> + Clone> Default for Views<T> {
+    fn default() -> Self {
+        Views::new()
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> Views<T> {
+    pub fn new() -> Self {
+        Views { views: vec![], txid: 0 }
+    }
+
+    /// Defines a new version of the view, returning that version
+    pub fn create(&mut self, name: T, query: Query<T>) -> Result<Vec<u8>, Vec<Problem<T>>> {
+        query.validate()?;
+        self.txid += 1;
+        let version = self.txid.to_be_bytes().to_vec();
+        self.views.push(View { name, version: version.clone(), query });
+        Ok(version)
+    }
+
+    /// Adds a version that was defined elsewhere (e.g. by ViewDB), keeping
+    /// its version
+    pub fn insert(&mut self, view: View<T>) -> Result<(), Vec<Problem<T>>> {
+        view.query.validate()?;
+        let position = self.views.iter().rposition(|v| v.version <= view.version).map(|i| i + 1).unwrap_or(0);
+        self.views.insert(position, view);
+        Ok(())
+    }
+
+    /// Every version of the view, oldest first
+    pub fn versions(&self, name: &T) -> Vec<&View<T>> {
+        self.views.iter().filter(|v| v.name.as_ref() == name.as_ref()).collect()
+    }
+
+    pub fn version(&self, name: &T, version: &[u8]) -> Option<&View<T>> {
+        self.versions(name).into_iter().find(|v| v.version.as_slice() == version)
+    }
+
+    /// Names of all defined views
+    pub fn list(&self) -> Vec<&T> {
+        let mut names: Vec<&T> = vec![];
+        for view in self.views.iter() {
+            if !names.iter().any(|n| n.as_ref() == view.name.as_ref()) {
+                names.push(&view.name);
+            }
+        }
+        names
+    }
+}
+
+impl<T : AsRef<[u8]> + Clone> ViewResolver<T> for Views<T> {
+    fn resolve(&self, name: &T) -> Option<View<T>> {
+        self.versions(name).pop().cloned()
+    }
+}
+
+struct References<'a, T : AsRef<[u8]> + Clone + 'a> {
+    query: &'a Query<T>,
+    names: Vec<T>,
+}
+
+impl<'a, T : AsRef<[u8]> + Clone> Visitor<T> for References<'a, T> {
+    fn visit_rule(&mut self, name: &T, arguments: &[::Value<T>]) {
+        if !self.query.rules.iter().any(|r| r.name.as_ref() == name.as_ref()) &&
+           !self.names.iter().any(|n| n.as_ref() == name.as_ref()) {
+            self.names.push(name.clone());
+        }
+        visit::visit_rule(self, name, arguments)
+    }
+}
+
+fn references<T : AsRef<[u8]> + Clone>(query: &Query<T>) -> Vec<T> {
+    let mut references = References { query, names: vec![] };
+    references.visit_condition(&query.condition);
+    for rule in query.rules.iter() {
+        references.visit_condition(&rule.body);
+    }
+    references.names
+}
+
+fn attach<T, R>(mut query: Query<T>, resolver: &R, resolving: &mut Vec<T>) -> Result<Query<T>, Error<T>>
+    where T : AsRef<[u8]> + Clone, R : ViewResolver<T> {
+    for name in references(&query) {
+        if query.views.iter().any(|v| v.name.as_ref() == name.as_ref()) {
+            continue;
+        }
+        if resolving.iter().any(|n| n.as_ref() == name.as_ref()) {
+            return Err(Error::Cycle(name));
+        }
+        if let Some(mut view) = resolver.resolve(&name) {
+            resolving.push(name);
+            view.query = attach(view.query, resolver, resolving)?;
+            resolving.pop();
+            query.views.push(view);
+        }
+    }
+    Ok(query)
+}
+
+/// Attaches the definitions of the views the query refers to (and the
+/// views those refer to) to the query. Names defined by the query's own
+/// rules are not looked up, and names that can't be resolved are left for
+/// the executor to report.
+pub fn resolve<T, R>(query: Query<T>, resolver: &R) -> Result<Query<T>, Error<T>>
+    where T : AsRef<[u8]> + Clone, R : ViewResolver<T> {
+    attach(query, resolver, &mut vec![])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query};
+    use Condition::Equal;
+    use execution::Executor;
+    use execution::tests::{people, fact_type};
+    use view::{self, View, Views, ViewResolver, Error};
+
+    fn latest_name() -> Query<&'static str> {
+        Query::new(Condition::fact(fact_type("NameChanged")
+                                   .and(Equal(Value::Attribute("#object"), Value::Binding("Person")))
+                                   .and(Equal(Value::Attribute("#value"), Value::Binding("Name")))))
+             .select("Person").select("Name")
+    }
+
+    #[test]
+    pub fn create_view() {
+        let mut views = Views::new();
+        let v1 = views.create("names", latest_name()).unwrap();
+        let v2 = views.create("names", latest_name().select("Person")).unwrap();
+        assert!(v1 < v2);
+        assert_eq!(views.list(), vec![&"names"]);
+        assert_eq!(views.resolve(&"names").unwrap().version, v2);
+        assert_eq!(views.version(&"names", &v1).unwrap().query, latest_name());
+
+        views.create("names", latest_name()).unwrap();
+        let query = Query::new(Condition::rule("names", vec![Value::Binding("P"), Value::Data("Alicia")]))
+                    .select("P");
+        let query = view::resolve(query, &views).unwrap();
+        assert_eq!(query.views.len(), 1);
+        let rows = Executor::new(&people()).execute(&query).unwrap();
+        assert_eq!(rows, vec![vec![b"alice".to_vec()]]);
+    }
+
+    #[test]
+    pub fn cycle() {
+        let mut views = Views::new();
+        let query = Query::new(Condition::rule("b", vec![Value::Binding("X")])).select("X");
+        views.create("a", query).unwrap();
+        let query = Query::new(Condition::rule("a", vec![Value::Binding("X")])).select("X");
+        views.create("b", query.clone()).unwrap();
+        assert_eq!(view::resolve(query, &views), Err(Error::Cycle("a")));
+    }
+
+    #[test]
+    pub fn insert_view() {
+        let mut views = Views::new();
+        let names = |version: u8| View { name: "names", version: vec![version], query: latest_name() };
+        views.insert(names(2)).unwrap();
+        views.insert(names(1)).unwrap();
+        assert_eq!(views.resolve(&"names"), Some(names(2)));
+        assert_eq!(views.versions(&"names").len(), 2);
+        let invalid = View { name: "invalid", version: vec![3], query: Query::new(Condition::True).select("X") };
+        assert!(views.insert(invalid).is_err());
+        assert_eq!(views.list(), vec![&"names"]);
+    }
+}
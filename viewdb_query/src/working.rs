@@ -0,0 +1,154 @@
+// Copyright (c) 2017, All Contributors (see CONTRIBUTORS file)
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Working sets of committed changes.
+//!
+//! A `WorkingSet` keeps the attachments of the attributes it holds (see
+//! `hold`) and nothing else, indexed by fact and attribute and by attribute
+//! and value, so that queries that only read those attributes (see
+//! `Query::attributes`) can be executed over it. It knows the identifiers
+//! of all facts, as a fact scan has to see every fact. In ViewDB, the
+//! engine holds the attributes its live queries read, loading their
+//! history from storage as it starts holding them.
+
+use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
+
+use execution::{FactSource, Attachment};
+use feed::Change;
+
+/// Value → facts it's attached to
+type Values = BTreeMap<Vec<u8>, BTreeSet<Vec<u8>>>;
+
+#[derive(Default)]
+pub struct WorkingSet {
+    /// Identifiers of all facts, in the order they've been seen in
+    facts: Vec<Vec<u8>>,
+    known: HashSet<Vec<u8>>,
+    /// (fact, attribute) → attachments, in TXID order
+    attachments: HashMap<(Vec<u8>, Vec<u8>), Vec<Attachment>>,
+    /// Values of every held attribute
+    index: HashMap<Vec<u8>, Values>,
+}
+
+impl WorkingSet {
+    pub fn new() -> Self {
+        WorkingSet::default()
+    }
+
+    pub fn holds(&self, attribute: &[u8]) -> bool {
+        self.index.contains_key(attribute)
+    }
+
+    /// Held attributes, in no particular order
+    pub fn held(&self) -> Vec<Vec<u8>> {
+        self.index.keys().cloned().collect()
+    }
+
+    /// Starts holding the attribute; its earlier changes have to be
+    /// replayed for it to be complete
+    pub fn hold(&mut self, attribute: &[u8]) {
+        self.index.entry(attribute.to_vec()).or_default();
+    }
+
+    /// Stops holding the attribute, dropping its attachments
+    pub fn release(&mut self, attribute: &[u8]) {
+        if self.index.remove(attribute).is_some() {
+            self.attachments.retain(|(_, a), _| a.as_slice() != attribute);
+        }
+    }
+
+    /// Records the change's fact and, if its attribute is held, the change
+    /// (once, even if it's replayed again)
+    pub fn replay(&mut self, change: &Change) {
+        if self.known.insert(change.fact.clone()) {
+            self.facts.push(change.fact.clone());
+        }
+        let facts = match self.index.get_mut(&change.attribute) {
+            Some(values) => values.entry(change.value.clone()).or_default(),
+            None => return,
+        };
+        facts.insert(change.fact.clone());
+        let attachments = self.attachments.entry((change.fact.clone(), change.attribute.clone()))
+                              .or_default();
+        let attachment = Attachment { value: change.value.clone(), txid: change.txid.clone() };
+        if attachments.contains(&attachment) {
+            return;
+        }
+        // changes of concurrent transactions (and loaded history) can
+        // arrive out of order
+        let position = attachments.iter().rposition(|a| a.txid <= attachment.txid).map(|i| i + 1).unwrap_or(0);
+        attachments.insert(position, attachment);
+    }
+}
+
+impl FactSource for WorkingSet {
+    fn facts(&self) -> Vec<Vec<u8>> {
+        self.facts.clone()
+    }
+
+    fn facts_with(&self, attribute: &[u8], value: Option<&[u8]>) -> Vec<Vec<u8>> {
+        let values = match self.index.get(attribute) {
+            Some(values) => values,
+            None => return vec![],
+        };
+        let facts: BTreeSet<&Vec<u8>> = match value {
+            Some(value) => values.get(value).into_iter().flat_map(|facts| facts.iter()).collect(),
+            None => values.values().flat_map(|facts| facts.iter()).collect(),
+        };
+        self.facts.iter().filter(|f| facts.contains(f)).cloned().collect()
+    }
+
+    fn attachments(&self, fact: &[u8], attribute: &[u8]) -> Vec<Attachment> {
+        self.attachments.get(&(fact.to_vec(), attribute.to_vec())).cloned().unwrap_or_default()
+    }
+
+    fn values(&self, attribute: &[u8]) -> Option<Vec<Vec<u8>>> {
+        self.index.get(attribute).map(|values| values.keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use {Condition, Value, Query};
+    use execution::{Executor, FactSource};
+    use feed::Change;
+    use memory::Memory;
+    use working::WorkingSet;
+
+    fn change(fact: &[u8], attribute: &[u8], value: &[u8], txid: u8) -> Change {
+        Change { fact: fact.to_vec(), attribute: attribute.to_vec(), value: value.to_vec(), txid: vec![txid] }
+    }
+
+    #[test]
+    pub fn working_set() {
+        let changes = [change(b"1", b"#name", b"Alice", 1), change(b"1", b"#email", b"alice@example.com", 1),
+                           change(b"2", b"#name", b"Bob", 3), change(b"1", b"#name", b"Alicia", 2)];
+        let mut working = WorkingSet::new();
+        working.hold(b"#name");
+        let mut memory = Memory::new();
+        for change in changes.iter() {
+            working.replay(change);
+            memory.replay(change.clone());
+        }
+        working.replay(&changes[2]);
+        assert_eq!(working.facts(), vec![b"1".to_vec(), b"2".to_vec()]);
+        assert_eq!(working.facts_with(b"#name", Some(b"Bob")), vec![b"2".to_vec()]);
+        assert_eq!(working.attachments(b"1", b"#name"), memory.attachments(b"1", b"#name"));
+        assert!(working.attachments(b"1", b"#email").is_empty());
+        assert_eq!(working.values(b"#name"), memory.values(b"#name"));
+
+        let query = Query::new(Condition::fact(Condition::Equal(Value::Attribute(b"#name".to_vec()),
+                                                                Value::Binding(b"Name".to_vec()))))
+                    .select(b"Name".to_vec());
+        assert_eq!(Executor::new(&working).execute(&query), Executor::new(&memory).execute(&query));
+
+        working.release(b"#name");
+        assert!(!working.holds(b"#name"));
+        assert!(working.attachments(b"1", b"#name").is_empty());
+        assert_eq!(working.facts().len(), 2);
+    }
+}